@@ -0,0 +1,151 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use memchr::{memchr, memrchr};
+
+/// Boundaries byte ranges are snapped to with [`crate::Slicer::align`], so
+/// that what they select is whole although it was addressed in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    /// The start of a line, after a newline.
+    Lines,
+    /// The start of a UTF-8 character, not inside a multi-byte sequence.
+    Utf8,
+}
+
+/// Bytes peeked at a time while looking for a boundary.
+const BLOCK: usize = 4096;
+
+impl Align {
+    /// The last boundary at or before `pos` in `input`, of `len` bytes.
+    fn floor(
+        self,
+        input: &mut (impl Read + Seek),
+        pos: usize,
+        len: usize,
+    ) -> std::io::Result<usize> {
+        if pos == 0 || pos >= len {
+            return Ok(pos.min(len));
+        }
+        match self {
+            Align::Lines => line_floor(input, pos),
+            Align::Utf8 => {
+                // back over up to 3 continuation bytes to the lead byte
+                let start = pos.saturating_sub(MAX_CONTINUATION);
+                let mut buf = [0; MAX_CONTINUATION + 1];
+                let peek = read_at(input, start, &mut buf[..=pos - start])?;
+                Ok(match peek.iter().rposition(|&b| !is_continuation(b)) {
+                    Some(i) => start + i,
+                    None => pos, // not UTF-8, so any byte will do
+                })
+            }
+        }
+    }
+
+    /// The first boundary at or after `pos` in `input`, of `len` bytes.
+    fn ceil(
+        self,
+        input: &mut (impl Read + Seek),
+        pos: usize,
+        len: usize,
+    ) -> std::io::Result<usize> {
+        if pos == 0 || pos >= len {
+            return Ok(pos.min(len));
+        }
+        match self {
+            Align::Lines => line_ceil(input, pos, len),
+            Align::Utf8 => {
+                // on past up to 3 continuation bytes to the next character
+                let end = (pos + MAX_CONTINUATION + 1).min(len);
+                let mut buf = [0; MAX_CONTINUATION + 1];
+                let peek = read_at(input, pos, &mut buf[..end - pos])?;
+                Ok(match peek.iter().position(|&b| !is_continuation(b)) {
+                    Some(i) => pos + i,
+                    None if end == len => len,
+                    None => pos,
+                })
+            }
+        }
+    }
+}
+
+/// Continuation bytes a UTF-8 sequence can have after its lead byte.
+const MAX_CONTINUATION: usize = 3;
+
+fn is_continuation(b: u8) -> bool {
+    b & 0xc0 == 0x80
+}
+
+/// The start of the line `pos`, which is not 0, is in.
+fn line_floor(input: &mut (impl Read + Seek), pos: usize) -> std::io::Result<usize> {
+    let mut buf = [0; BLOCK];
+    let mut end = pos;
+    while end > 0 {
+        let start = end.saturating_sub(BLOCK);
+        let block = read_at(input, start, &mut buf[..end - start])?;
+        if let Some(i) = memrchr(b'\n', block) {
+            return Ok(start + i + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+/// The start of the first line at or after `pos`, which is within `len`
+/// but not 0, or `len`.
+fn line_ceil(input: &mut (impl Read + Seek), pos: usize, len: usize) -> std::io::Result<usize> {
+    let mut buf = [0; BLOCK];
+    // a newline just before `pos` makes it a boundary
+    let mut start = pos - 1;
+    while start < len {
+        let end = (start + BLOCK).min(len);
+        let block = read_at(input, start, &mut buf[..end - start])?;
+        if let Some(i) = memchr(b'\n', block) {
+            return Ok(start + i + 1);
+        }
+        start = end;
+    }
+    Ok(len)
+}
+
+fn read_at<'a>(
+    input: &mut (impl Read + Seek),
+    pos: usize,
+    buf: &'a mut [u8],
+) -> std::io::Result<&'a [u8]> {
+    input.seek(SeekFrom::Start(pos as u64))?;
+    input.read_exact(buf)?;
+    Ok(buf)
+}
+
+/// Sorted byte `runs` of `input`, of `len` bytes, widened to the boundaries
+/// of `align` around them, or with `inward` narrowed to those within them.
+/// Runs left empty are dropped, and those that come to meet are merged.
+pub(crate) fn snap(
+    runs: &[(usize, usize)],
+    align: Align,
+    inward: bool,
+    input: &mut (impl Read + Seek),
+    len: usize,
+) -> std::io::Result<Vec<(usize, usize)>> {
+    let mut snapped: Vec<(usize, usize)> = Vec::with_capacity(runs.len());
+    for &(start, end) in runs {
+        let (start, end) = match inward {
+            false => (
+                align.floor(input, start, len)?,
+                align.ceil(input, end, len)?,
+            ),
+            true => (
+                align.ceil(input, start, len)?,
+                align.floor(input, end, len)?,
+            ),
+        };
+        if start >= end {
+            continue;
+        }
+        match snapped.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => snapped.push((start, end)),
+        }
+    }
+    Ok(snapped)
+}
@@ -0,0 +1,199 @@
+use std::io::Write;
+
+use crate::{
+    Mode, Result, Slicer,
+    output::RecordSink,
+    range::{SliceIdx, SliceRange},
+    trace,
+    window::{self, Clock},
+};
+
+/// Sink finding the records that the anchors of some ranges point to, or
+/// where the time window is, or how many records there are for ranges
+/// resolved from the start, from all records of the input written to it.
+pub(crate) struct Matcher<'a> {
+    ranges: &'a [SliceRange],
+    clock: Option<Clock<'a>>,
+    found: Vec<(Option<usize>, Option<usize>)>, // record of each start and end
+    skip: usize,
+    last: bool,
+    terminator: Option<&'a [u8]>,
+    cur: Option<usize>,
+    record: Vec<u8>,
+    count: bool, // whether ranges are resolved from the start at the end
+    seen: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(slicer: &'a Slicer) -> Self {
+        Self {
+            ranges: &slicer.ranges,
+            clock: slicer.window.as_ref().map(Clock::new),
+            found: vec![(None, None); slicer.ranges.len()],
+            skip: slicer.header as usize,
+            last: slicer.last_match,
+            terminator: slicer.mode.terminator(),
+            cur: None,
+            record: Vec::new(),
+            count: slicer.needs_count(),
+            seen: 0,
+        }
+    }
+
+    /// Match the record just written against the anchors.
+    fn end_record(&mut self) {
+        let Some(i) = self.cur.take() else {
+            return;
+        };
+        let record = match self.terminator {
+            Some(t) => self.record.strip_suffix(t).unwrap_or(&self.record),
+            None => &self.record[..],
+        };
+        let record = record.trim_ascii_end();
+        if let Some(clock) = &mut self.clock {
+            clock.record(i, record);
+        }
+        for (r, (start, end)) in self.ranges.iter().zip(&mut self.found) {
+            // an end is looked for from the start on
+            let mut from = match &r.start {
+                Some(SliceIdx::Match(a)) => start.map(|s| a.at(s)),
+                Some(SliceIdx::FromStart(n)) => Some(*n),
+                _ => Some(0),
+            };
+            if let Some(SliceIdx::Match(a)) = &r.start
+                && (self.last || start.is_none())
+                && a.is_match(record)
+            {
+                *start = Some(i);
+                from = Some(a.at(i));
+                *end = end.filter(|&e| Some(e) >= from);
+            }
+            if let Some(SliceIdx::Match(a)) = &r.end
+                && from.is_some_and(|f| i >= f)
+                && (self.last || end.is_none())
+                && a.is_match(record)
+            {
+                *end = Some(i);
+            }
+        }
+        self.record.clear();
+    }
+
+    /// The ranges with their anchors replaced by the records found, or the
+    /// end of the input where there are none, or over the whole input
+    /// instead of the time window.
+    fn resolve(mut self) -> Vec<SliceRange> {
+        self.end_record();
+        if let Some(clock) = &self.clock {
+            let (start, len) = clock.resolve();
+            return window::within(self.ranges, start, len);
+        }
+        let bound = |idx: &Option<SliceIdx>, found: Option<usize>| match idx {
+            Some(SliceIdx::Match(a)) => Some(match found {
+                Some(i) => SliceIdx::FromStart(a.at(i)),
+                None => SliceIdx::FromEnd(0),
+            }),
+            idx => idx.clone(),
+        };
+        let ranges: Vec<_> = self
+            .ranges
+            .iter()
+            .zip(&self.found)
+            .map(|(r, &(start, end))| SliceRange {
+                start: bound(&r.start, start),
+                end: match (&r.end, end) {
+                    (Some(SliceIdx::Match(_)), None) => None,
+                    (idx, end) => bound(idx, end),
+                },
+                step: r.step,
+            })
+            .collect();
+        match self.count {
+            true => window::within(&ranges, 0, self.seen),
+            false => ranges,
+        }
+    }
+}
+
+impl Write for Matcher<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.cur.is_some() {
+            self.record.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl RecordSink for Matcher<'_> {
+    fn prefixed(&self) -> bool {
+        true
+    }
+
+    fn begin_record(&mut self, i: usize, _offset: u64) -> std::io::Result<()> {
+        self.end_record();
+        self.cur = i.checked_sub(self.skip);
+        if let Some(i) = self.cur {
+            self.seen = i + 1;
+        }
+        Ok(())
+    }
+}
+
+impl Slicer {
+    /// Whether some range has a bound that must be looked for in the input.
+    pub(crate) fn has_anchors(&self) -> bool {
+        let anchor = |i: &Option<SliceIdx>| matches!(i, Some(SliceIdx::Match(_)));
+        self.ranges
+            .iter()
+            .any(|r| anchor(&r.start) || anchor(&r.end))
+    }
+
+    /// Whether the input must be read once to resolve the selection before
+    /// it is sliced.
+    pub(crate) fn needs_scan(&self) -> bool {
+        self.has_anchors() || self.window.is_some() || self.needs_count()
+    }
+
+    /// This slicer with its anchors and time window resolved by `scan`,
+    /// which must emit every record of the input with the slicer and to the
+    /// sink given.
+    pub(crate) fn resolve_bounds<'a>(
+        &'a self,
+        scan: impl FnOnce(&Slicer, Matcher<'a>) -> Result<Matcher<'a>>,
+    ) -> Result<Slicer> {
+        if self.context.is_some() {
+            self.check_context()?;
+        }
+        let records = !matches!(self.mode, Mode::Byte | Mode::Char);
+        if self.window.is_some() {
+            if !records {
+                return Err("Time windows need records, such as lines".into());
+            }
+            if self.has_anchors() {
+                return Err("Patterns cannot be combined with a time window".into());
+            }
+        } else if !records {
+            return Err("Patterns need records, such as lines, to match".into());
+        }
+        let all = SliceRange {
+            start: None,
+            end: None,
+            step: 1,
+        };
+        let scanner = Slicer::new([all], self.mode.clone())
+            .header(self.header)
+            .max_records(self.max_records)
+            .decompress(self.decompress);
+        let mut this = self.clone();
+        let found = trace::timed("scanning for the bounds", || {
+            scan(&scanner, Matcher::new(self))
+        })?;
+        this.ranges = found.resolve();
+        this.window = None;
+        Ok(this)
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Take},
+    path::{Path, PathBuf},
+};
+
+/// Size of tar headers, and what member data is padded to.
+const BLOCK: u64 = 512;
+
+/// Where the data of a member is in its archive.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Entry {
+    pub(crate) start: u64,
+    pub(crate) len: u64,
+    pub(crate) deflated: bool, // or stored as it is
+}
+
+/// Member names compare equal without a leading `./`, as tar writes them
+/// either way.
+fn same_name(name: &[u8], member: &Path) -> bool {
+    let member = member.to_string_lossy();
+    let trim = |n: &[u8]| n.strip_prefix(b"./").unwrap_or(n).to_vec();
+    trim(name) == trim(member.as_bytes())
+}
+
+/// A numeric header field: octal digits, or base-256 when the high bit of
+/// the first byte is set, as GNU tar writes big sizes.
+fn number(field: &[u8]) -> std::io::Result<u64> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let mut n = u64::from(field[0] & 0x7f);
+        for &b in &field[1..] {
+            n = n.checked_mul(256).ok_or(ErrorKind::InvalidData)? | u64::from(b);
+        }
+        return Ok(n);
+    }
+    let digits = field
+        .iter()
+        .skip_while(|b| **b == b' ')
+        .take_while(|b| (b'0'..=b'7').contains(b));
+    let mut n = 0u64;
+    for &b in digits {
+        n = n.checked_mul(8).ok_or(ErrorKind::InvalidData)? + u64::from(b - b'0');
+    }
+    Ok(n)
+}
+
+/// A NUL-terminated header field.
+fn text(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+/// The value of `key` in a pax extended header.
+fn pax(records: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+    let mut rest = records;
+    while !rest.is_empty() {
+        // "<len> <key>=<value>\n", with len counting all of it
+        let space = rest.iter().position(|&b| b == b' ')?;
+        let len: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        let record = rest.get(space + 1..len)?.strip_suffix(b"\n")?;
+        if let Some(value) = record.strip_prefix(key).and_then(|r| r.strip_prefix(b"=")) {
+            return Some(value.to_vec());
+        }
+        rest = &rest[len..];
+    }
+    None
+}
+
+/// Read the tar archive `input` up to the data of `member`, giving its
+/// offset in the archive and size. Data of other members is passed over
+/// with `skip`.
+fn find_tar<R: Read>(
+    input: &mut R,
+    member: &Path,
+    mut skip: impl FnMut(&mut R, u64) -> std::io::Result<()>,
+) -> std::io::Result<(u64, u64)> {
+    let mut pos = 0;
+    // names and sizes from GNU long name and pax headers, for the next entry
+    let (mut long_name, mut pax_size) = (None, None);
+    loop {
+        let mut header = [0; BLOCK as usize];
+        input.read_exact(&mut header).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => Error::new(ErrorKind::InvalidData, "Truncated tar archive"),
+            _ => e,
+        })?;
+        pos += BLOCK;
+        if header.iter().all(|&b| b == 0) {
+            let msg = format!("No member {} in the archive", member.display());
+            return Err(Error::new(ErrorKind::NotFound, msg));
+        }
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| match i {
+                148..156 => u64::from(b' '),
+                _ => u64::from(b),
+            })
+            .sum();
+        if number(&header[148..156])? != sum {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a tar archive"));
+        }
+        let size = pax_size.take().unwrap_or(number(&header[124..136])?);
+        let padded = size.div_ceil(BLOCK) * BLOCK;
+        let kind = header[156];
+        if matches!(kind, b'L' | b'x') {
+            let mut data = Vec::new();
+            input.by_ref().take(size).read_to_end(&mut data)?;
+            skip(input, padded - size)?;
+            pos += padded;
+            match kind {
+                b'L' => long_name = Some(text(&data).to_vec()),
+                _ => {
+                    if let Some(path) = pax(&data, b"path") {
+                        long_name = Some(path);
+                    }
+                    pax_size =
+                        pax(&data, b"size").and_then(|s| String::from_utf8(s).ok()?.parse().ok());
+                }
+            }
+            continue;
+        }
+        let name = match long_name.take() {
+            Some(name) => name,
+            // ustar splits long names into a prefix and the rest
+            None if &header[257..262] == b"ustar" && header[345] != 0 => {
+                [text(&header[345..500]), b"/", text(&header[..100])].concat()
+            }
+            None => text(&header[..100]).to_vec(),
+        };
+        if matches!(kind, b'0' | b'\0' | b'7') && same_name(&name, member) {
+            return Ok((pos, size));
+        }
+        skip(input, padded)?;
+        pos += padded;
+    }
+}
+
+/// Where `member` is in the tar archive `file`, found by seeking from
+/// header to header.
+fn tar_entry(file: &mut File, member: &Path) -> std::io::Result<Entry> {
+    file.seek(SeekFrom::Start(0))?;
+    let (start, len) = find_tar(file, member, |f, n| {
+        f.seek(SeekFrom::Current(n as i64))?;
+        Ok(())
+    })?;
+    Ok(Entry {
+        start,
+        len,
+        deflated: false,
+    })
+}
+
+/// Signatures of zip records.
+const ZIP_LOCAL: u32 = 0x0403_4b50;
+const ZIP_CENTRAL: u32 = 0x0201_4b50;
+const ZIP_END: u32 = 0x0605_4b50;
+const ZIP64_END: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+
+/// Whether `head`, the start of a file, is that of a zip archive.
+pub(crate) fn is_zip(head: &[u8]) -> bool {
+    head.starts_with(&ZIP_LOCAL.to_le_bytes()) || head.starts_with(&ZIP_END.to_le_bytes())
+}
+
+/// Little-endian fields of a zip record, read in turn.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        let (field, rest) = self
+            .0
+            .split_first_chunk()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Truncated zip archive"))?;
+        self.0 = rest;
+        Ok(*field)
+    }
+
+    fn u16(&mut self) -> std::io::Result<u64> {
+        self.take().map(|b| u16::from_le_bytes(b).into())
+    }
+
+    fn u32(&mut self) -> std::io::Result<u64> {
+        self.take().map(|b| u32::from_le_bytes(b).into())
+    }
+
+    fn u64(&mut self) -> std::io::Result<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn bytes(&mut self, n: u64) -> std::io::Result<&'a [u8]> {
+        let n = n as usize;
+        if self.0.len() < n {
+            return Err(Error::new(ErrorKind::InvalidData, "Truncated zip archive"));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+}
+
+fn read_at(file: &mut File, pos: u64, len: u64) -> std::io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(pos))?;
+    let mut buf = Vec::new();
+    file.take(len).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Where `member` is in the zip archive `file`, looked up in its central
+/// directory at the end, so nothing else is read.
+fn zip_entry(file: &mut File, member: &Path) -> std::io::Result<Entry> {
+    let bad = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_owned());
+    let size = file.seek(SeekFrom::End(0))?;
+    // the end record, 22 bytes and a comment of up to 64 KiB
+    let tail_start = size.saturating_sub(22 + 0xffff);
+    let tail = read_at(file, tail_start, size - tail_start)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| tail[i..].starts_with(&ZIP_END.to_le_bytes()))
+        .ok_or_else(|| bad("Not a zip archive"))?;
+    let mut f = Fields(&tail[end + 4..]);
+    let _ = f.bytes(6)?; // disk numbers, entries on this disk
+    let (mut entries, mut dir_len, mut dir_start) = (f.u16()?, f.u32()?, f.u32()?);
+    // the zip64 end record, if the locator is right before
+    if end >= 20 && tail[end - 20..].starts_with(&ZIP64_LOCATOR.to_le_bytes()) {
+        let mut f = Fields(&tail[end - 16..end]);
+        let pos = f.u64()?;
+        let record = read_at(file, pos, 56)?;
+        let mut f = Fields(&record);
+        if f.u32()? != u64::from(ZIP64_END) {
+            return Err(bad("Invalid zip64 end record"));
+        }
+        let _ = f.bytes(8 + 2 + 2 + 4 + 4 + 8)?;
+        (entries, dir_len, dir_start) = (f.u64()?, f.u64()?, f.u64()?);
+    }
+
+    let dir = read_at(file, dir_start, dir_len)?;
+    let mut f = Fields(&dir);
+    for _ in 0..entries {
+        if f.u32()? != u64::from(ZIP_CENTRAL) {
+            return Err(bad("Invalid zip central directory"));
+        }
+        let _ = f.bytes(4)?;
+        let flags = f.u16()?;
+        let method = f.u16()?;
+        let _ = f.bytes(8)?;
+        let (mut len, mut plain_len) = (f.u32()?, f.u32()?);
+        let (name_len, extra_len, comment_len) = (f.u16()?, f.u16()?, f.u16()?);
+        let _ = f.bytes(8)?;
+        let mut local = f.u32()?;
+        let name = f.bytes(name_len)?;
+        let extra = f.bytes(extra_len)?;
+        let _ = f.bytes(comment_len)?;
+        if !same_name(name, member) {
+            continue;
+        }
+        // zip64 sizes and offset, for those too big for their fields, which
+        // come in this order where they are
+        let mut e = Fields(extra);
+        while let (Ok(id), Ok(n)) = (e.u16(), e.u16()) {
+            let data = e.bytes(n)?;
+            if id == 1 {
+                let mut z = Fields(data);
+                for field in [&mut plain_len, &mut len, &mut local] {
+                    if *field == 0xffff_ffff {
+                        *field = z.u64()?;
+                    }
+                }
+            }
+        }
+        if flags & 1 != 0 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Encrypted zip members are not supported",
+            ));
+        }
+        let deflated = match method {
+            0 => false,
+            8 => true,
+            m => {
+                let msg = format!("Zip compression method {m} is not supported");
+                return Err(Error::new(ErrorKind::Unsupported, msg));
+            }
+        };
+        let header = read_at(file, local, 30)?;
+        let mut h = Fields(&header);
+        if h.u32()? != u64::from(ZIP_LOCAL) {
+            return Err(bad("Invalid zip local header"));
+        }
+        let _ = h.bytes(22)?;
+        let start = local + 30 + h.u16()? + h.u16()?;
+        return Ok(Entry {
+            start,
+            len,
+            deflated,
+        });
+    }
+    let msg = format!("No member {} in the archive", member.display());
+    Err(Error::new(ErrorKind::NotFound, msg))
+}
+
+/// Where `member` is in `file`, a zip or tar archive.
+pub(crate) fn find_entry(file: &mut File, member: &Path) -> std::io::Result<Entry> {
+    let head = read_at(file, 0, 4)?;
+    match is_zip(&head) {
+        true => zip_entry(file, member),
+        false => tar_entry(file, member),
+    }
+}
+
+/// The zip archive and member in an input named like `logs.zip!app/x.log`,
+/// unless a file of that name exists.
+pub(crate) fn split_zip(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let name = path.to_str()?;
+    let at = name.to_ascii_lowercase().find(".zip!")? + 4;
+    if path.exists() {
+        return None;
+    }
+    Some((name[..at].into(), name[at + 1..].into()))
+}
+
+/// The data of `member` in the tar archive read from `input`.
+pub(crate) fn tar_stream<R: Read>(mut input: R, member: &Path) -> std::io::Result<Take<R>> {
+    let (_, size) = find_tar(&mut input, member, |r, n| {
+        std::io::copy(&mut r.take(n), &mut std::io::sink())?;
+        Ok(())
+    })?;
+    Ok(input.take(size))
+}
+
+/// The `len` bytes of a file from `start`, read and seeked as a file of its
+/// own.
+pub(crate) struct Window {
+    file: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl Window {
+    pub(crate) fn new(mut file: File, start: u64, len: u64) -> std::io::Result<Self> {
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            file,
+            start,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for Window {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        let n = buf.len().min(left.try_into().unwrap_or(usize::MAX));
+        let n = self.file.read(&mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Window {
+    fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+        let pos = match from {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        }
+        .ok_or(ErrorKind::InvalidInput)?;
+        self.file.seek(SeekFrom::Start(self.start + pos))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    io::{ErrorKind, Write},
+    path::Path,
+};
+
+use crate::Result;
+
+/// Errors slicing a batch of files, which with [`Slicer::continue_on_error`]
+/// are reported as they happen instead of ending it, and summed up at the
+/// end.
+///
+/// [`Slicer::continue_on_error`]: crate::Slicer::continue_on_error
+pub(crate) struct Failures {
+    go_on: bool,
+    json: bool,
+    count: usize,
+}
+
+impl Failures {
+    pub(crate) fn new(go_on: bool, json: bool) -> Self {
+        Self {
+            go_on,
+            json,
+            count: 0,
+        }
+    }
+
+    /// Hand back the result of slicing `path`, or report its error on stderr
+    /// and carry on if allowed.
+    pub(crate) fn check(&mut self, path: &Path, res: Result<()>) -> Result<()> {
+        match res {
+            Err(e) if self.go_on && self.json => {
+                eprintln!("{}", e.to_json(Some(path)));
+                self.count += 1;
+                Ok(())
+            }
+            Err(e) if self.go_on => {
+                let msg = e.to_string();
+                let name = path.display().to_string();
+                // most errors about a file already start with its name
+                match msg.starts_with(&format!("{name}: ")) {
+                    true => eprintln!("slice: {msg}"),
+                    false => eprintln!("slice: {name}: {msg}"),
+                }
+                self.count += 1;
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// Fail if any of the `total` files did.
+    pub(crate) fn finish(self, total: usize) -> Result<()> {
+        match self.count {
+            0 => Ok(()),
+            n => Err(format!("{n} of {total} files could not be sliced").into()),
+        }
+    }
+}
+
+/// Writer remembering whether writing failed, so that errors of the output,
+/// which would fail every file after, still end the batch.
+pub(crate) struct Checked<W> {
+    pub(crate) inner: W,
+    pub(crate) failed: bool,
+}
+
+impl<W: Write> Write for Checked<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let res = self.inner.write(buf);
+        self.failed |= res
+            .as_ref()
+            .is_err_and(|e| e.kind() != ErrorKind::Interrupted);
+        res
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let res = self.inner.flush();
+        self.failed |= res.is_err();
+        res
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+    rc::Rc,
+};
+
+use flate2::read::GzDecoder;
+
+/// Fixed part of a gzip member header, up to and including XLEN.
+const HEADER_LEN: usize = 12;
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Read a gzip header and its extra field, which may come up short at EOF.
+fn read_head(r: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(HEADER_LEN + 6);
+    r.by_ref().take(HEADER_LEN as u64).read_to_end(&mut head)?;
+    if head.len() == HEADER_LEN {
+        let xlen = u16::from_le_bytes([head[10], head[11]]);
+        r.by_ref().take(xlen as u64).read_to_end(&mut head)?;
+    }
+    Ok(head)
+}
+
+/// Total compressed size of the block starting with `head`, taken from the
+/// `BC` extra subfield, or None if it is not a BGZF block.
+fn block_size(head: &[u8]) -> Option<usize> {
+    if head.len() < HEADER_LEN || head[..4] != [0x1f, 0x8b, 8, 4] {
+        return None;
+    }
+    let xlen = u16::from_le_bytes([head[10], head[11]]) as usize;
+    let mut extra = head.get(HEADER_LEN..HEADER_LEN + xlen)?;
+    while extra.len() >= 4 {
+        let slen = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        if extra[..2] == *b"BC" && slen == 2 {
+            let bsize = extra.get(4..6)?;
+            return Some(u16::from_le_bytes([bsize[0], bsize[1]]) as usize + 1);
+        }
+        extra = extra.get(4 + slen..)?;
+    }
+    None
+}
+
+/// Whether `file` starts with a BGZF block. The file is rewound afterwards.
+pub(crate) fn is_bgzf(file: &mut File) -> std::io::Result<bool> {
+    let head = read_head(file)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(block_size(&head).is_some())
+}
+
+/// Map from uncompressed to compressed offsets of a BGZF file.
+#[derive(Debug)]
+pub(crate) struct Index {
+    entries: Vec<(u64, u64)>, // (compressed, uncompressed) offsets of block starts
+    size: u64,                // total uncompressed size
+}
+
+impl Index {
+    /// Load the `.gzi` index next to `path` if there is one, otherwise build
+    /// the index from the block headers, which needs no decompression.
+    pub fn load(file: &mut File, path: Option<&Path>) -> std::io::Result<Self> {
+        let mut entries = vec![(0, 0)];
+        if let Some(path) = path {
+            let mut gzi = path.as_os_str().to_owned();
+            gzi.push(".gzi");
+            if let Ok(data) = std::fs::read(gzi) {
+                entries.extend(parse_gzi(&data)?);
+            }
+        }
+        // the index does not record where the data ends
+        let (coff, uoff) = *entries.last().unwrap();
+        let size = scan(file, coff, uoff, &mut entries)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self { entries, size })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Parse a bgzip `.gzi` file: a little-endian count followed by that many
+/// (compressed, uncompressed) offset pairs, not including the first block.
+fn parse_gzi(data: &[u8]) -> std::io::Result<Vec<(u64, u64)>> {
+    let mut words = data
+        .chunks_exact(8)
+        .map(|w| u64::from_le_bytes(w.try_into().unwrap()));
+    let count = words
+        .next()
+        .ok_or_else(|| invalid("truncated .gzi index"))?;
+    if data.len() as u64 != 8 + count.saturating_mul(16) {
+        return Err(invalid("truncated .gzi index"));
+    }
+    let mut entries = Vec::with_capacity(count as usize);
+    while let (Some(c), Some(u)) = (words.next(), words.next()) {
+        entries.push((c, u));
+    }
+    Ok(entries)
+}
+
+/// Walk the blocks from `coff` to the end of `file`, recording the start of
+/// each one after the first, and return the total uncompressed size.
+fn scan(
+    file: &mut File,
+    mut coff: u64,
+    mut uoff: u64,
+    entries: &mut Vec<(u64, u64)>,
+) -> std::io::Result<u64> {
+    let len = file.metadata()?.len();
+    while coff < len {
+        file.seek(SeekFrom::Start(coff))?;
+        let size = block_size(&read_head(file)?).ok_or_else(|| invalid("not a BGZF block"))?;
+        // ISIZE is the last field of the block
+        file.seek(SeekFrom::Start(coff + size as u64 - 4))?;
+        let mut isize = [0; 4];
+        file.read_exact(&mut isize)?;
+        coff += size as u64;
+        uoff += u32::from_le_bytes(isize) as u64;
+        if coff < len {
+            entries.push((coff, uoff));
+        }
+    }
+    Ok(uoff)
+}
+
+/// Seekable reader over the uncompressed contents of a BGZF file. Seeking
+/// decodes only the block holding the target offset.
+pub(crate) struct BgzfReader {
+    file: File,
+    index: Rc<Index>,
+    block: Vec<u8>, // the current block, decoded
+    start: u64,     // uncompressed offset of the current block
+    pos: usize,     // read position within the block
+}
+
+impl BgzfReader {
+    pub fn new(mut file: File, index: Rc<Index>) -> std::io::Result<Self> {
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            file,
+            index,
+            block: Vec::new(),
+            start: 0,
+            pos: 0,
+        })
+    }
+
+    /// Decode the block at the current file position, returning false at EOF.
+    fn next_block(&mut self) -> std::io::Result<bool> {
+        self.start += self.block.len() as u64;
+        self.block.clear();
+        self.pos = 0;
+        let mut raw = read_head(&mut self.file)?;
+        if raw.is_empty() {
+            return Ok(false);
+        }
+        let size = block_size(&raw)
+            .and_then(|s| s.checked_sub(raw.len()))
+            .ok_or_else(|| invalid("not a BGZF block"))?;
+        self.file.by_ref().take(size as u64).read_to_end(&mut raw)?;
+        GzDecoder::new(&raw[..]).read_to_end(&mut self.block)?;
+        Ok(true)
+    }
+}
+
+impl Read for BgzfReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // empty blocks, like the EOF marker, are skipped
+        while self.pos == self.block.len() {
+            if buf.is_empty() || !self.next_block()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.block.len() - self.pos);
+        buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Seek for BgzfReader {
+    fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+        let target = match from {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.index.size.checked_add_signed(n),
+            SeekFrom::Current(n) => (self.start + self.pos as u64).checked_add_signed(n),
+        }
+        .ok_or(ErrorKind::InvalidInput)?;
+
+        // the first entry is always (0, 0)
+        let i = self.index.entries.partition_point(|&(_, u)| u <= target) - 1;
+        let (coff, uoff) = self.index.entries[i];
+        self.file.seek(SeekFrom::Start(coff))?;
+        self.block.clear();
+        self.start = uoff;
+        while self.start + self.block.len() as u64 <= target {
+            if !self.next_block()? {
+                break;
+            }
+        }
+        self.pos = ((target - self.start) as usize).min(self.block.len());
+        Ok(target)
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    fs::File,
+    io::{Cursor, ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use flate2::read::MultiGzDecoder;
+
+/// A compression format that is decoded transparently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+/// Bytes needed to recognize any supported format.
+pub(crate) const MAGIC_LEN: usize = 6;
+
+impl Compression {
+    pub fn from_magic(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if magic.starts_with(b"\xfd7zXZ\0") {
+            Some(Compression::Xz)
+        } else if magic.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Detect the format of a seekable file by its magic number or, failing
+    /// that, its extension. The file is rewound afterwards.
+    pub fn detect_file(file: &mut File, path: Option<&Path>) -> std::io::Result<Option<Self>> {
+        let mut magic = Vec::with_capacity(MAGIC_LEN);
+        file.by_ref()
+            .take(MAGIC_LEN as u64)
+            .read_to_end(&mut magic)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self::from_magic(&magic).or_else(|| path.and_then(Self::from_extension)))
+    }
+
+    /// Wrap `input` in a decoder; formats whose cargo feature is disabled
+    /// are still recognized, but fail here instead of passing through as is.
+    pub fn decoder<'a>(self, input: impl Read + 'a) -> std::io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(input)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(input)?),
+            #[cfg(feature = "xz")]
+            Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(input)),
+            #[cfg(feature = "bz2")]
+            Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(input)),
+            #[allow(unreachable_patterns)]
+            c => return Err(std::io::Error::new(ErrorKind::Unsupported, c.unsupported())),
+        })
+    }
+
+    fn unsupported(self) -> String {
+        let (name, feature) = match self {
+            Compression::Gzip => ("gzip", "default"),
+            Compression::Zstd => ("zstd", "zstd"),
+            Compression::Xz => ("xz", "xz"),
+            Compression::Bzip2 => ("bzip2", "bz2"),
+        };
+        format!("{name} input is not supported by this build (enable the `{feature}` feature)")
+    }
+}
+
+/// Peek at the start of a stream and decode it if it is compressed.
+pub(crate) fn decompress_stream<'a>(
+    mut input: impl Read + 'a,
+) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    input
+        .by_ref()
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+    let compression = Compression::from_magic(&magic);
+    let input = Cursor::new(magic).chain(input);
+    Ok(match compression {
+        Some(c) => c.decoder(input)?,
+        None => Box::new(input),
+    })
+}
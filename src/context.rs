@@ -0,0 +1,122 @@
+use std::io::Write;
+
+use crate::{
+    Mode, Result, Slicer,
+    output::{Format, RecordSink},
+    range::{SliceIdx, SliceRange},
+    slicer::Selection,
+};
+
+const DIM: &[u8] = b"\x1b[2m";
+const BOLD: &[u8] = b"\x1b[1m";
+const RESET: &[u8] = b"\x1b[0m";
+
+/// Sink marking the records of a selection widened by some context apart
+/// from that context, like `grep -C` does: with `> ` or in bold, while the
+/// records around them are indented or dimmed. Records that do not follow
+/// on from the last one are set apart by a `--` line.
+pub(crate) struct Marked<'a, S> {
+    inner: &'a mut S,
+    selection: Selection<'a>,
+    color: bool,
+    last: Option<usize>,
+}
+
+impl<'a, S: RecordSink> Marked<'a, S> {
+    pub(crate) fn new(inner: &'a mut S, selection: Selection<'a>, color: bool) -> Self {
+        Self {
+            inner,
+            selection,
+            color,
+            last: None,
+        }
+    }
+
+    /// End the colors of the last record, if any.
+    pub(crate) fn finish(self) -> std::io::Result<()> {
+        match self.last {
+            Some(_) if self.color => self.inner.write_all(RESET),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<S: RecordSink> Write for Marked<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: RecordSink> RecordSink for Marked<'_, S> {
+    fn prefixed(&self) -> bool {
+        true
+    }
+
+    fn begin_record(&mut self, i: usize, offset: u64) -> std::io::Result<()> {
+        if let Some(last) = self.last {
+            if self.color {
+                self.inner.write_all(RESET)?;
+            }
+            if last + 1 != i {
+                self.inner.write_all(b"--\n")?;
+            }
+        }
+        self.last = Some(i);
+        // the ranges are resolved from the start by then, so any length will do
+        let selected = self.selection.contains(i, usize::MAX);
+        let mark: &[u8] = match (self.color, selected) {
+            (true, true) => BOLD,
+            (true, false) => DIM,
+            (false, true) => b"> ",
+            (false, false) => b"  ",
+        };
+        self.inner.write_all(mark)?;
+        self.inner.begin_record(i, offset)
+    }
+}
+
+/// `ranges`, which count from the start, widened by `n` records on each
+/// side.
+pub(crate) fn widen(ranges: &[SliceRange], n: usize) -> Vec<SliceRange> {
+    let by = |idx: &Option<SliceIdx>, f: fn(usize, usize) -> usize| match idx {
+        Some(SliceIdx::FromStart(i)) => Some(SliceIdx::FromStart(f(*i, n))),
+        idx => idx.clone(),
+    };
+    ranges
+        .iter()
+        .map(|r| SliceRange {
+            start: by(&r.start, usize::saturating_sub),
+            end: by(&r.end, usize::saturating_add),
+            step: r.step,
+        })
+        .collect()
+}
+
+impl Slicer {
+    /// Whether ranges must be resolved from the start, by counting the
+    /// records of the whole input, before context can be shown around them.
+    pub(crate) fn needs_count(&self) -> bool {
+        self.context.is_some() && self.ranges.iter().any(|r| r.lookahead() > 0)
+    }
+
+    /// Fail unless context can be shown around the selection.
+    pub(crate) fn check_context(&self) -> Result<()> {
+        if matches!(self.mode, Mode::Byte | Mode::Char) {
+            return Err("Context needs records, such as lines".into());
+        }
+        if self.format != Format::Raw {
+            return Err("Context can only be shown around records written as they are".into());
+        }
+        if self.invert || self.sample.is_some() || self.shuffle {
+            return Err("Context cannot be shown around inverted or sampled records".into());
+        }
+        if self.ranges.iter().any(|r| r.step != 1) {
+            return Err("Context cannot be shown around ranges with a step".into());
+        }
+        Ok(())
+    }
+}
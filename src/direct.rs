@@ -0,0 +1,185 @@
+use std::{
+    alloc::{Layout, alloc_zeroed, dealloc, handle_alloc_error},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    ops::{Deref, DerefMut},
+    path::Path,
+    ptr::NonNull,
+};
+
+use crate::platform;
+
+/// Alignment of buffers, offsets and lengths for direct IO, enough for the
+/// logical blocks of every common device.
+const ALIGN: usize = 4096;
+
+/// Bytes moved by one read or write.
+const CHUNK: usize = 1 << 20;
+
+/// Heap buffer of `CHUNK` bytes aligned to `ALIGN`.
+struct AlignedBuf(NonNull<u8>);
+
+impl AlignedBuf {
+    const LAYOUT: Layout = match Layout::from_size_align(CHUNK, ALIGN) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid buffer layout"),
+    };
+
+    fn new() -> Self {
+        // SAFETY: the layout has a non-zero size
+        let ptr = unsafe { alloc_zeroed(Self::LAYOUT) };
+        Self(NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(Self::LAYOUT)))
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the allocation is CHUNK initialized bytes, owned by self
+        unsafe { std::slice::from_raw_parts(self.0.as_ptr(), CHUNK) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as above, and borrowed mutably through self
+        unsafe { std::slice::from_raw_parts_mut(self.0.as_ptr(), CHUNK) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: allocated in new with the same layout
+        unsafe { dealloc(self.0.as_ptr(), Self::LAYOUT) }
+    }
+}
+
+/// Reader of a file opened for direct IO, which reads whole aligned blocks
+/// into its own aligned buffer and hands out any bytes of them, from any
+/// offset.
+pub(crate) struct DirectReader {
+    file: File,
+    buf: AlignedBuf,
+    start: u64, // offset of the buffer in the file
+    filled: usize,
+    pos: u64,
+}
+
+impl DirectReader {
+    /// Open `path` to read it around the page cache.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = platform::open_direct(OpenOptions::new().read(true), path)?;
+        Ok(Self {
+            file,
+            buf: AlignedBuf::new(),
+            start: 0,
+            filled: 0,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for DirectReader {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if !(self.start..self.start + self.filled as u64).contains(&self.pos) {
+            self.start = self.pos & !(ALIGN as u64 - 1);
+            self.file.seek(SeekFrom::Start(self.start))?;
+            self.filled = 0;
+            self.filled = self.file.read(&mut self.buf)?;
+            if self.pos >= self.start + self.filled as u64 {
+                return Ok(0);
+            }
+        }
+        let from = (self.pos - self.start) as usize;
+        let n = out.len().min(self.filled - from);
+        out[..n].copy_from_slice(&self.buf[from..from + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for DirectReader {
+    fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match from {
+            SeekFrom::Start(n) => Some(n),
+            // block devices only tell their size by seeking
+            SeekFrom::End(n) => self.file.seek(SeekFrom::End(0))?.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        }
+        .ok_or(std::io::ErrorKind::InvalidInput)?;
+        Ok(self.pos)
+    }
+}
+
+/// Writer to a file around the page cache, for `--direct` output.
+///
+/// Whole aligned blocks are written directly; an unaligned tail, written
+/// when flushed, goes through the cache, as does everything after it.
+pub struct DirectWriter {
+    file: File,
+    buf: AlignedBuf,
+    len: usize,
+    cached: bool, // direct IO was turned off for the tail
+}
+
+impl DirectWriter {
+    /// Open `path` with `opts` to write it around the page cache.
+    pub fn open(opts: &mut OpenOptions, path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            file: platform::open_direct(opts, path)?,
+            buf: AlignedBuf::new(),
+            len: 0,
+            cached: false,
+        })
+    }
+
+    /// The file written to.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Write out the whole blocks buffered, keeping the rest.
+    fn write_blocks(&mut self) -> std::io::Result<()> {
+        let blocks = self.len & !(ALIGN - 1);
+        self.file.write_all(&self.buf[..blocks])?;
+        self.buf.copy_within(blocks..self.len, 0);
+        self.len -= blocks;
+        Ok(())
+    }
+}
+
+impl Write for DirectWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.cached {
+            return self.file.write(data);
+        }
+        if self.len == CHUNK {
+            self.write_blocks()?;
+        }
+        let n = data.len().min(CHUNK - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.len > 0 {
+            self.write_blocks()?;
+        }
+        if self.len > 0 {
+            platform::end_direct(&self.file)?;
+            self.cached = true;
+            self.file.write_all(&self.buf[..self.len])?;
+            self.len = 0;
+        }
+        self.file.flush()
+    }
+}
+
+impl Drop for DirectWriter {
+    fn drop(&mut self) {
+        // like BufWriter, errors here are lost; flush first to see them
+        let _ = self.flush();
+    }
+}
@@ -0,0 +1,125 @@
+use std::{fmt, io::ErrorKind, path::Path};
+
+/// Why slicing failed. Each kind ends the command line tool with its own
+/// exit status, see [`Error::status`].
+#[derive(Debug)]
+pub enum Error {
+    /// A range, size, pattern or other argument that cannot be parsed.
+    Parse(String),
+    /// A file that does not exist.
+    NotFound(std::io::Error),
+    /// Reading the input or writing the output failed.
+    Io(std::io::Error),
+    /// The input or the output is not what it has to be, such as invalid
+    /// UTF-8 with [`Slicer::validate_utf8`] or invalid JSON lines.
+    ///
+    /// [`Slicer::validate_utf8`]: crate::Slicer::validate_utf8
+    Data(std::io::Error),
+    /// Nothing was selected, where that is asked to be an error.
+    Empty,
+    /// Anything else, such as options that cannot be combined or an input
+    /// that is not what it was taken for.
+    Other(String),
+}
+
+impl Error {
+    /// `e`, which happened to the file at `path`, naming it.
+    pub fn at(path: &Path, e: std::io::Error) -> Self {
+        std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)).into()
+    }
+
+    /// The exit status for the error: 2 for parse errors, as for usage
+    /// errors, 3 when nothing was selected, 4 for a missing file, 5 for
+    /// other I/O errors, 6 for invalid data and 1 for anything else.
+    pub fn status(&self) -> u8 {
+        match self {
+            Error::Other(_) => 1,
+            Error::Parse(_) => 2,
+            Error::Empty => 3,
+            Error::NotFound(_) => 4,
+            Error::Io(_) => 5,
+            Error::Data(_) => 6,
+        }
+    }
+
+    /// A name for the kind of error, as in JSON error output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Parse(_) => "parse",
+            Error::NotFound(_) => "not_found",
+            Error::Io(_) => "io",
+            Error::Data(_) => "data",
+            Error::Empty => "empty",
+            Error::Other(_) => "other",
+        }
+    }
+
+    /// Whether the error comes from writing to a pipe whose reader has gone
+    /// away.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self, Error::Io(e) if e.kind() == ErrorKind::BrokenPipe)
+    }
+
+    /// The error as a line of JSON with its kind, message and exit status,
+    /// and the `file` it happened to if given.
+    pub fn to_json(&self, file: Option<&Path>) -> String {
+        let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+        let file = match file {
+            Some(path) => format!(r#","file":{}"#, quote(&path.to_string_lossy())),
+            None => String::new(),
+        };
+        format!(
+            r#"{{"error":"{}","message":{},"status":{}{file}}}"#,
+            self.kind(),
+            quote(&self.to_string()),
+            self.status()
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(msg) | Error::Other(msg) => f.write_str(msg),
+            Error::NotFound(e) | Error::Io(e) | Error::Data(e) => e.fmt(f),
+            Error::Empty => f.write_str("Nothing was selected"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NotFound(e) | Error::Io(e) | Error::Data(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::NotFound => Error::NotFound(e),
+            ErrorKind::InvalidData => Error::Data(e),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Error::Other(msg)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(msg: &str) -> Self {
+        Error::Other(msg.to_owned())
+    }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(e: std::num::ParseIntError) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{Error, Result, Slicer, platform::file_id};
+
+/// Copy whatever can be read from `file` right now, returning false at EOF.
+fn copy_available(file: &mut File, out: &mut impl Write, buf: &mut [u8]) -> Result<bool> {
+    let n = file.read(buf)?;
+    if n == 0 {
+        return Ok(false);
+    }
+    out.write_all(&buf[..n])?;
+    out.flush()?;
+    Ok(true)
+}
+
+/// Start over if `file` shrank below the current offset.
+fn check_truncated(file: &mut File, name: &Path) -> Result<()> {
+    if file.metadata()?.len() < file.stream_position()? {
+        eprintln!("slice: {}: file truncated", name.display());
+        file.seek(SeekFrom::Start(0))?;
+    }
+    Ok(())
+}
+
+impl Slicer {
+    fn check_followable(&self) -> Result<()> {
+        if !self.open_ended() {
+            return Err("Following requires a single range open at the end, e.g. -10:".into());
+        }
+        Ok(())
+    }
+
+    /// Slice `file`, then keep it open and copy data appended to it, checking
+    /// every `interval`, like `tail -f`. Only returns on error.
+    pub fn follow(&self, file: File, mut out: impl Write, interval: Duration) -> Result<()> {
+        self.check_followable()?;
+        // the clone shares the file offset, which ends up where slicing stopped
+        let mut file_at = file.try_clone()?;
+        self.slice_file(file, &mut out)?;
+
+        let mut buf = vec![0; self.buf_len()];
+        loop {
+            if !copy_available(&mut file_at, &mut out, &mut buf)? {
+                check_truncated(&mut file_at, Path::new("input"))?;
+                std::thread::sleep(interval);
+            }
+        }
+    }
+
+    /// Like [`Slicer::follow`], but keep following `path` when it is rotated,
+    /// like `tail -F`: a replaced file is reopened and printed from the start,
+    /// and a missing one is waited for.
+    pub fn follow_name(
+        &self,
+        path: impl AsRef<Path>,
+        mut out: impl Write,
+        interval: Duration,
+    ) -> Result<()> {
+        self.check_followable()?;
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::at(path, e))?;
+        let mut id = Some(file_id(&file.metadata()?));
+        let mut cur = file.try_clone()?;
+        self.slice_file(file, &mut out)?;
+
+        let mut buf = vec![0; self.buf_len()];
+        loop {
+            // drain the old file first, writers may still hold it open
+            if copy_available(&mut cur, &mut out, &mut buf)? {
+                continue;
+            }
+            check_truncated(&mut cur, path)?;
+
+            match std::fs::metadata(path) {
+                Ok(meta) if Some(file_id(&meta)) != id => {
+                    if let Ok(file) = File::open(path) {
+                        eprintln!(
+                            "slice: {}: file has been replaced; following new file",
+                            path.display()
+                        );
+                        id = Some(file_id(&file.metadata()?));
+                        cur = file;
+                        continue;
+                    }
+                }
+                Err(_) if id.is_some() => {
+                    eprintln!("slice: {}: file has become inaccessible", path.display());
+                    id = None;
+                }
+                _ => {}
+            }
+            std::thread::sleep(interval);
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    io::{BufRead, BufReader, Error, ErrorKind, Read, Write},
+    net::{IpAddr, TcpStream},
+};
+
+use crate::remote::Remote;
+#[cfg(all(feature = "tls", unix))]
+use crate::tls;
+
+/// Redirects followed before giving up.
+const MAX_REDIRECTS: usize = 8;
+
+/// An `http://` or `https://` URL split up for a request.
+pub(crate) struct Url {
+    tls: bool,
+    host: String,
+    port: u16,
+    pub(crate) target: String, // path and query
+}
+
+impl Url {
+    pub(crate) fn parse(url: &str) -> std::io::Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, "Invalid URL");
+        let (tls, rest) = match url.split_once("://") {
+            Some(("http", rest)) => (false, rest),
+            Some(("https", rest)) => (true, rest),
+            _ => return Err(invalid()),
+        };
+        if tls && !cfg!(all(feature = "tls", unix)) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "HTTPS is not supported by this build (enable the `tls` feature on Unix)",
+            ));
+        }
+        let rest = rest.split('#').next().unwrap();
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_owned()),
+            None => (rest, "/".to_owned()),
+        };
+        // credentials are not sent
+        let host = authority.rsplit('@').next().unwrap();
+        let (host, port) = match host.rfind(':') {
+            Some(i) if !host[i..].contains(']') => {
+                (&host[..i], host[i + 1..].parse().map_err(|_| invalid())?)
+            }
+            _ => (host, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        Ok(Self {
+            tls,
+            host,
+            port,
+            target,
+        })
+    }
+
+    /// Whether the server is on this machine, so nothing sent to it goes
+    /// over the network.
+    fn is_local(&self) -> bool {
+        self.host.eq_ignore_ascii_case("localhost")
+            || self.host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+    }
+
+    /// Whether `other` has the same scheme, host and port, so that what is
+    /// sent to one may be sent to the other.
+    fn same_origin(&self, other: &Url) -> bool {
+        self.tls == other.tls
+            && self.host.eq_ignore_ascii_case(&other.host)
+            && self.port == other.port
+    }
+
+    fn scheme(&self) -> &'static str {
+        if self.tls { "https" } else { "http" }
+    }
+
+    /// The value of the `Host` header for it.
+    pub(crate) fn host_header(&self) -> String {
+        let default = if self.tls { 443 } else { 80 };
+        match (self.host.contains(':'), self.port) {
+            (false, port) if port == default => self.host.clone(),
+            (false, port) => format!("{}:{}", self.host, port),
+            (true, port) => format!("[{}]:{}", self.host, port),
+        }
+    }
+
+    /// The URL a `Location` header points to from this one.
+    fn join(&self, location: &str) -> String {
+        if location.contains("://") {
+            return location.to_owned();
+        }
+        let authority = match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, self.port),
+            false => format!("{}:{}", self.host, self.port),
+        };
+        let path = match location.starts_with('/') {
+            true => location.to_owned(),
+            false => {
+                let path = self.target.split('?').next().unwrap();
+                format!("{}{}", &path[..path.rfind('/').unwrap() + 1], location)
+            }
+        };
+        // resolve . and .. segments, which servers need not take
+        let (path, query) = path.split_once('?').unwrap_or((&path, ""));
+        let mut segments = Vec::new();
+        for segment in path.split('/').skip(1) {
+            match segment {
+                "." => (),
+                ".." => drop(segments.pop()),
+                s => segments.push(s),
+            }
+        }
+        if path.ends_with("/.") || path.ends_with("/..") {
+            segments.push("");
+        }
+        let query = match query {
+            "" => String::new(),
+            q => format!("?{q}"),
+        };
+        format!(
+            "{}://{authority}/{}{query}",
+            self.scheme(),
+            segments.join("/")
+        )
+    }
+}
+
+/// A connection to a server, over TLS for `https://` URLs.
+enum Conn {
+    Plain(TcpStream),
+    #[cfg(all(feature = "tls", unix))]
+    Tls(tls::Stream),
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Conn::Plain(c) => c.read(buf),
+            #[cfg(all(feature = "tls", unix))]
+            Conn::Tls(c) => c.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Conn::Plain(c) => c.write(buf),
+            #[cfg(all(feature = "tls", unix))]
+            Conn::Tls(c) => c.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Conn::Plain(c) => c.flush(),
+            #[cfg(all(feature = "tls", unix))]
+            Conn::Tls(c) => c.flush(),
+        }
+    }
+}
+
+/// How the end of a response body is found.
+enum Framing {
+    Length(u64), // bytes left
+    Chunked { left: u64, done: bool },
+    Close,
+}
+
+/// Body of a response, read off its connection.
+pub(crate) struct Body {
+    conn: BufReader<Conn>,
+    framing: Framing,
+}
+
+impl Body {
+    /// Read the size line of the next chunk, or the trailer after the last.
+    fn next_chunk(&mut self) -> std::io::Result<u64> {
+        let mut line = String::new();
+        if self.conn.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let size = line.trim_end().split(';').next().unwrap().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid chunk size"))?;
+        if size == 0 {
+            // trailer fields, up to an empty line
+            loop {
+                line.clear();
+                if self.conn.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                    break;
+                }
+            }
+        }
+        Ok(size)
+    }
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.framing {
+            Framing::Length(0) => Ok(0),
+            Framing::Length(left) => {
+                let n = buf.len().min(left.try_into().unwrap_or(usize::MAX));
+                let n = self.conn.read(&mut buf[..n])?;
+                if n == 0 {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                self.framing = Framing::Length(left - n as u64);
+                Ok(n)
+            }
+            Framing::Chunked { done: true, .. } => Ok(0),
+            Framing::Chunked { left: 0, .. } => {
+                let size = self.next_chunk()?;
+                self.framing = Framing::Chunked {
+                    left: size,
+                    done: size == 0,
+                };
+                self.read(buf)
+            }
+            Framing::Chunked { left, .. } => {
+                let n = buf.len().min(left.try_into().unwrap_or(usize::MAX));
+                let n = self.conn.read(&mut buf[..n])?;
+                if n == 0 {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                if n as u64 == left {
+                    // the line break after the chunk
+                    let mut crlf = String::new();
+                    self.conn.read_line(&mut crlf)?;
+                }
+                self.framing = Framing::Chunked {
+                    left: left - n as u64,
+                    done: false,
+                };
+                Ok(n)
+            }
+            Framing::Close => self.conn.read(buf),
+        }
+    }
+}
+
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    headers: Vec<(String, String)>,
+    pub(crate) body: Body,
+}
+
+impl Response {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// An `http://` or `https://` URL.
+pub(crate) struct Http(pub(crate) String);
+
+impl Remote for Http {
+    fn get(&self, range: Option<(u64, Option<u64>)>) -> std::io::Result<Response> {
+        get(&self.0, &[], range)
+    }
+}
+
+/// The value of the `Range` header asking for the bytes from `start` to
+/// `end` inclusive, or to the end.
+pub(crate) fn range_header(start: u64, end: Option<u64>) -> String {
+    match end {
+        Some(end) => format!("bytes={start}-{end}"),
+        None => format!("bytes={start}-"),
+    }
+}
+
+/// GET `url` with `headers` added, following redirects, for the bytes from
+/// `start` to `end` inclusive if given. The headers, which carry
+/// credentials, are only sent over TLS or to this machine, and not on
+/// redirects to another scheme, host or port.
+pub(crate) fn get(
+    url: &str,
+    headers: &[(&str, String)],
+    range: Option<(u64, Option<u64>)>,
+) -> std::io::Result<Response> {
+    let origin = Url::parse(url)?;
+    let mut url = url.to_owned();
+    for _ in 0..=MAX_REDIRECTS {
+        let parsed = Url::parse(&url)?;
+        let headers = match parsed.same_origin(&origin) {
+            true => headers,
+            false => &[],
+        };
+        let res = request(&parsed, headers, range)?;
+        match (res.status, res.header("location")) {
+            (301 | 302 | 303 | 307 | 308, Some(location)) => url = parsed.join(location),
+            _ => return Ok(res),
+        }
+    }
+    Err(Error::other("Too many redirects"))
+}
+
+fn request(
+    url: &Url,
+    headers: &[(&str, String)],
+    range: Option<(u64, Option<u64>)>,
+) -> std::io::Result<Response> {
+    if !headers.is_empty() && !url.tls && !url.is_local() {
+        let msg = format!(
+            "Credentials are not sent to {} over plain HTTP, only over HTTPS",
+            url.host
+        );
+        return Err(Error::new(ErrorKind::PermissionDenied, msg));
+    }
+    let tcp = TcpStream::connect((url.host.as_str(), url.port))?;
+    let mut conn = match url.tls {
+        #[cfg(all(feature = "tls", unix))]
+        true => Conn::Tls(tls::Stream::connect(tcp, &url.host)?),
+        _ => Conn::Plain(tcp),
+    };
+    let mut head = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: slice/{}\r\nAccept-Encoding: identity\r\n\
+         Connection: close\r\n",
+        url.target,
+        url.host_header(),
+        env!("CARGO_PKG_VERSION"),
+    );
+    if let Some((start, end)) = range {
+        head += &format!("Range: {}\r\n", range_header(start, end));
+    }
+    for (name, value) in headers {
+        head += &format!("{name}: {value}\r\n");
+    }
+    head += "\r\n";
+    conn.write_all(head.as_bytes())?;
+
+    let mut conn = BufReader::new(conn);
+    let bad = |what| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid HTTP response {what}"),
+        )
+    };
+    let mut line = String::new();
+    conn.read_line(&mut line)?;
+    let mut parts = line.trim_end().splitn(3, ' ');
+    let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+        return Err(bad("status line"));
+    };
+    if !version.starts_with("HTTP/") {
+        return Err(bad("status line"));
+    }
+    let status: u16 = status.parse().map_err(|_| bad("status"))?;
+    let reason = parts.next().unwrap_or("").to_owned();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if conn.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let field = line.trim_end();
+        if field.is_empty() {
+            break;
+        }
+        let (name, value) = field.split_once(':').ok_or_else(|| bad("header"))?;
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+    let mut res = Response {
+        status,
+        headers,
+        body: Body {
+            conn,
+            framing: Framing::Close,
+        },
+    };
+    res.body.framing = if res
+        .header("transfer-encoding")
+        .is_some_and(|t| t.to_ascii_lowercase().contains("chunked"))
+    {
+        Framing::Chunked {
+            left: 0,
+            done: false,
+        }
+    } else if let Some(len) = res.header("content-length") {
+        Framing::Length(len.parse().map_err(|_| bad("length"))?)
+    } else {
+        Framing::Close
+    };
+    if status >= 400 && status != 416 {
+        return Err(Error::other(format!("HTTP {status} {reason}")));
+    }
+    Ok(res)
+}
@@ -0,0 +1,152 @@
+use std::{
+    fs::{File, Metadata},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{Result, mmap, scan};
+
+/// Magic number and version at the start of an index file.
+const MAGIC: &[u8; 8] = b"SLICEIX1";
+
+/// Sparse index of where the records of a file start, written next to it
+/// as `NAME.slice-idx` by `slice index`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    delim: u8,
+    interval: u64,     // records between entries
+    size: u64,         // size of the file when it was indexed
+    mtime: (u64, u32), // its modification time since the epoch
+    records: u64,      // number of records in the file
+    offsets: Vec<u64>, // start of record k * interval, for k >= 1
+}
+
+/// Modification time of a file as seconds and nanoseconds since the epoch.
+fn mtime(meta: &Metadata) -> std::io::Result<(u64, u32)> {
+    let t = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((t.as_secs(), t.subsec_nanos()))
+}
+
+impl LineIndex {
+    /// Scan `file` for records terminated by `delim`, noting where every
+    /// `interval`-th one starts.
+    pub fn build(file: &File, delim: u8, interval: u64) -> Result<Self> {
+        if interval == 0 {
+            return Err("The index interval must be at least 1".into());
+        }
+        let meta = file.metadata()?;
+        let map = mmap::map(file)?;
+        let data = map.as_deref().unwrap_or_default();
+
+        let mut offsets = Vec::new();
+        let (mut records, mut off) = (0, 0);
+        while let Some(p) = scan::nth(&data[off..], delim, interval as usize - 1) {
+            records += interval;
+            off += p + 1;
+            if off < data.len() {
+                offsets.push(off as u64);
+            }
+        }
+        records += mmap::count_records(&data[off..], delim) as u64;
+        Ok(Self {
+            delim,
+            interval,
+            size: data.len() as u64,
+            mtime: mtime(&meta)?,
+            records,
+            offsets,
+        })
+    }
+
+    /// Load the index of `path` if there is one and it still matches `file`
+    /// and records terminated by `delim`. A stale or damaged index is
+    /// ignored.
+    pub(crate) fn load(path: &Path, file: &File, delim: u8) -> Option<Self> {
+        let data = std::fs::read(Self::sidecar(path)).ok()?;
+        let index = Self::parse(&data)?;
+        let meta = file.metadata().ok()?;
+        let valid =
+            index.delim == delim && index.size == meta.len() && index.mtime == mtime(&meta).ok()?;
+        valid.then_some(index)
+    }
+
+    fn parse(mut data: &[u8]) -> Option<Self> {
+        fn take<const N: usize>(data: &mut &[u8]) -> Option<[u8; N]> {
+            let (head, rest) = data.split_first_chunk::<N>()?;
+            *data = rest;
+            Some(*head)
+        }
+        let word = |data: &mut &[u8]| take(data).map(u64::from_le_bytes);
+
+        if take(&mut data)? != *MAGIC {
+            return None;
+        }
+        let [delim] = take(&mut data)?;
+        let interval = word(&mut data).filter(|&n| n > 0)?;
+        let size = word(&mut data)?;
+        let mtime = (word(&mut data)?, u32::from_le_bytes(take(&mut data)?));
+        let records = word(&mut data)?;
+        let count = word(&mut data)?;
+        if data.len() as u64 != count.checked_mul(8)? {
+            return None;
+        }
+        let offsets = data
+            .chunks_exact(8)
+            .map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        Some(Self {
+            delim,
+            interval,
+            size,
+            mtime,
+            records,
+            offsets,
+        })
+    }
+
+    /// Number of records in the file.
+    pub(crate) fn records(&self) -> usize {
+        self.records as usize
+    }
+
+    /// The last indexed record at or before record `idx`, and its offset.
+    pub(crate) fn nearest(&self, idx: usize) -> (usize, usize) {
+        let k = (idx as u64 / self.interval).min(self.offsets.len() as u64);
+        match k {
+            0 => (0, 0),
+            k => (
+                (k * self.interval) as usize,
+                self.offsets[k as usize - 1] as usize,
+            ),
+        }
+    }
+
+    /// Where the index of `path` is kept.
+    pub fn sidecar(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".slice-idx");
+        PathBuf::from(name)
+    }
+
+    /// Write the index to `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[self.delim])?;
+        for n in [self.interval, self.size, self.mtime.0] {
+            out.write_all(&n.to_le_bytes())?;
+        }
+        out.write_all(&self.mtime.1.to_le_bytes())?;
+        out.write_all(&self.records.to_le_bytes())?;
+        out.write_all(&(self.offsets.len() as u64).to_le_bytes())?;
+        for off in &self.offsets {
+            out.write_all(&off.to_le_bytes())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    fs::{File, FileTimes, OpenOptions},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Result, Slicer, batch::Failures, compress::Compression, platform};
+
+/// Create a fresh temporary file next to `path`, so it can be renamed over it.
+fn create_temp(path: &Path) -> Result<(PathBuf, File)> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path
+        .file_name()
+        .ok_or("Invalid file name")?
+        .to_string_lossy();
+    for n in 0.. {
+        let tmp = dir.join(format!(".{}.slice-{}-{}", name, std::process::id(), n));
+        match OpenOptions::new().write(true).create_new(true).open(&tmp) {
+            Ok(f) => return Ok((tmp, f)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::at(&tmp, e)),
+        }
+    }
+    unreachable!()
+}
+
+/// Give `out` the owner, permissions and extended attributes of `input`, as
+/// far as the system lets. Done before anything is written, so that the
+/// slice of a private file is never readable by others.
+fn copy_metadata(input: &File, out: &File) -> Result<()> {
+    let meta = input.metadata()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, fchown};
+        // only root can give files away, but a group of one's own will do
+        if fchown(out, Some(meta.uid()), Some(meta.gid())).is_err() {
+            let _ = fchown(out, None, Some(meta.gid()));
+        }
+    }
+    // after changing the owner, which clears set-user-ID bits
+    out.set_permissions(meta.permissions())?;
+    platform::copy_xattrs(input, out);
+    Ok(())
+}
+
+/// Give `out` the access and modification times of `input`, once written.
+fn copy_times(input: &File, out: &File) -> Result<()> {
+    let meta = input.metadata()?;
+    let times = FileTimes::new()
+        .set_accessed(meta.accessed()?)
+        .set_modified(meta.modified()?);
+    out.set_times(times)?;
+    Ok(())
+}
+
+/// Keep the original `path` as `backup` too, by a hard link where it can be
+/// made, so that it is there before the slice replaces it.
+fn back_up(path: &Path, backup: &Path) -> Result<()> {
+    let with_path = |e| Error::at(backup, e);
+    match std::fs::remove_file(backup) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(with_path(e)),
+        _ => (),
+    }
+    if std::fs::hard_link(path, backup).is_err() {
+        std::fs::copy(path, backup).map_err(with_path)?;
+    }
+    Ok(())
+}
+
+/// Make a rename in the directory of `path` survive a crash. Only Unix can
+/// open directories to sync them.
+fn sync_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+        File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+impl Slicer {
+    /// Replace the contents of `path` with its slice, keeping the original as
+    /// `path` plus `backup`, such as `.bak`, if given, like `sed -i.bak`.
+    /// The new file keeps the owner, permissions and extended attributes of
+    /// the old one where the system allows, and with `keep_mtime` its
+    /// modification time.
+    ///
+    /// The slice is written to a temporary file in the same directory which is
+    /// then renamed over the original, so readers never see a partial file,
+    /// and a crash leaves the original or the slice, never part of one.
+    /// With [`Slicer::decompress`], a compressed file is refused, since its
+    /// slice would replace it decompressed.
+    pub fn slice_in_place(
+        &self,
+        path: impl AsRef<Path>,
+        backup: Option<&str>,
+        keep_mtime: bool,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let mut input = File::open(path).map_err(|e| Error::at(path, e))?;
+        // the slice would be decompressed, replacing the file with plain text
+        if self.decompress
+            && platform::is_seekable(&input)
+            && Compression::detect_file(&mut input, None)?.is_some()
+        {
+            let msg = "A compressed file cannot be sliced in place";
+            return Err(format!("{}: {msg}", path.display()).into());
+        }
+        let (tmp, mut out) = create_temp(path)?;
+        let res = copy_metadata(&input, &out)
+            .and_then(|()| self.slice_file(input.try_clone()?, &mut out))
+            .and_then(|()| match keep_mtime {
+                true => copy_times(&input, &out),
+                false => Ok(()),
+            })
+            .and_then(|()| Ok(out.sync_all()?))
+            .and_then(|()| match backup {
+                Some("") => Err("The backup suffix must not be empty".into()),
+                Some(suffix) => {
+                    let mut name = path.as_os_str().to_owned();
+                    name.push(suffix);
+                    back_up(path, Path::new(&name))
+                }
+                None => Ok(()),
+            })
+            .and_then(|()| Ok(std::fs::rename(&tmp, path)?));
+        if res.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        res?;
+        sync_dir(path)
+    }
+
+    /// [`Slicer::slice_in_place`] each of `paths` in turn, going on past
+    /// those that fail with [`Slicer::continue_on_error`].
+    pub fn slice_each_in_place(
+        &self,
+        paths: &[impl AsRef<Path>],
+        backup: Option<&str>,
+        keep_mtime: bool,
+    ) -> Result<()> {
+        let mut failures = Failures::new(self.continue_on_error, self.json_errors);
+        for path in paths {
+            let res = self.slice_in_place(path, backup, keep_mtime);
+            failures.check(path.as_ref(), res)?;
+        }
+        failures.finish(paths.len())
+    }
+}
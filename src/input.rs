@@ -0,0 +1,691 @@
+use std::{
+    fs::File,
+    io::{Chain, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        OnceLock,
+        mpsc::{Receiver, RecvTimeoutError, sync_channel},
+    },
+    time::Instant,
+};
+
+#[cfg(feature = "net")]
+use crate::remote;
+#[cfg(any(feature = "net", unix))]
+use crate::socket::{self, Hangup};
+use flate2::read::DeflateDecoder;
+
+use crate::{
+    Error, Result,
+    archive::{self, Entry, Window},
+    bgzf::{self, BgzfReader},
+    compress::{Compression, MAGIC_LEN, decompress_stream},
+    direct::DirectReader,
+    platform::{self, Advice},
+};
+
+enum Part {
+    File(File),
+    Advised(Advised),
+    Direct(DirectReader),
+    Bgzf(BgzfReader),
+    Window(Window),
+    #[cfg(feature = "net")]
+    Remote(remote::Ranged),
+    Stream(Box<dyn Read>),
+}
+
+impl Read for Part {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Part::File(f) => f.read(buf),
+            Part::Advised(r) => r.read(buf),
+            Part::Direct(r) => r.read(buf),
+            Part::Bgzf(r) => r.read(buf),
+            Part::Window(r) => r.read(buf),
+            #[cfg(feature = "net")]
+            Part::Remote(r) => r.read(buf),
+            Part::Stream(s) => s.read(buf),
+        }
+    }
+}
+
+/// Bytes read before the kernel is told it can drop them from the cache.
+const DROP_EVERY: u64 = 8 << 20;
+
+/// File read front to back, which the kernel is told about, dropping what
+/// was read from the page cache as it goes.
+struct Advised {
+    file: File,
+    pos: u64,
+    kept: u64, // where the bytes read and still cached start
+}
+
+impl Advised {
+    fn new(file: File) -> std::io::Result<Self> {
+        platform::advise(&file, 0, 0, Advice::Sequential);
+        let pos = { &file }.stream_position()?;
+        Ok(Self {
+            file,
+            pos,
+            kept: pos,
+        })
+    }
+
+    fn drop_read(&mut self) {
+        if self.pos > self.kept {
+            platform::advise(
+                &self.file,
+                self.kept,
+                self.pos - self.kept,
+                Advice::DontNeed,
+            );
+        }
+        self.kept = self.pos;
+    }
+}
+
+impl Read for Advised {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.pos += n as u64;
+        if n == 0 || self.pos - self.kept >= DROP_EVERY {
+            self.drop_read();
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for Advised {
+    fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+        self.drop_read();
+        self.pos = self.file.seek(from)?;
+        self.kept = self.pos;
+        Ok(self.pos)
+    }
+}
+
+impl Drop for Advised {
+    fn drop(&mut self) {
+        self.drop_read();
+    }
+}
+
+/// Stream that ends, as if at EOF, once `deadline` passes, even while its
+/// source has nothing to read. A thread reads the source ahead; it is left
+/// blocked in its read if the deadline hits.
+struct Deadline {
+    rx: Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    deadline: Instant,
+    done: bool,
+}
+
+impl Deadline {
+    fn new(mut source: impl Read + Send + 'static, deadline: Instant) -> Self {
+        let (tx, rx) = sync_channel(4);
+        std::thread::spawn(move || {
+            loop {
+                let mut chunk = vec![0; 64 * 1024];
+                let msg = match source.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        Ok(chunk)
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = msg.is_err();
+                if tx.send(msg).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+            deadline,
+            done: false,
+        }
+    }
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+            let timeout = self.deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(chunk) => (self.chunk, self.pos) = (chunk?, 0),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => {
+                    self.done = true;
+                }
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Length of the UTF-8 or UTF-16 byte order mark `data` starts with, if any.
+fn bom_len(data: &[u8]) -> usize {
+    if data.starts_with(b"\xef\xbb\xbf") {
+        3
+    } else if data.starts_with(b"\xff\xfe") || data.starts_with(b"\xfe\xff") {
+        2
+    } else {
+        0
+    }
+}
+
+/// `input` without the byte order mark it may start with.
+pub(crate) fn strip_bom<R: Read>(mut input: R) -> std::io::Result<Chain<Cursor<Vec<u8>>, R>> {
+    let mut head = Vec::with_capacity(3);
+    input.by_ref().take(3).read_to_end(&mut head)?;
+    head.drain(..bom_len(&head));
+    Ok(Cursor::new(head).chain(input))
+}
+
+/// Whether `path` is a URL rather than a file: `http://` or `https://`, an
+/// `s3://` or `gs://` object, or a `tcp://` stream.
+pub(crate) fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|p| {
+        ["http://", "https://", "s3://", "gs://", "tcp://"]
+            .iter()
+            .any(|scheme| p.starts_with(scheme))
+    })
+}
+
+/// The socket path of a `unix://` input.
+fn unix_socket(path: &Path) -> Option<&Path> {
+    path.to_str()?.strip_prefix("unix://").map(Path::new)
+}
+
+/// Name the URL an error came from.
+#[cfg(any(feature = "net", unix))]
+fn with_url(path: &Path) -> impl Fn(std::io::Error) -> std::io::Error + '_ {
+    move |e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+/// Several inputs read back to back as one stream, like `cat a b c`.
+///
+/// Files are opened one at a time, and compressed ones are decoded when
+/// `decompress` is set. When every input is seekable, such as a regular file
+/// or block device or stdin redirected from one, and either uncompressed or
+/// BGZF, the stream is also seekable, with offsets counted across all of
+/// them. Regular files are sized by their metadata, so seeking far into the
+/// stream, as ranges counted from the end of it do, skips whole files without
+/// reading them, or even opening them unless they might be compressed.
+/// Reading stdin and other streams stops at `deadline`, if given.
+pub(crate) struct Concat {
+    paths: Vec<PathBuf>, // "-" means stdin
+    stdin: Option<File>, // stdin, if it is seekable
+    decompress: bool,
+    strip_bom: bool,
+    direct: bool,
+    fadvise: bool,
+    deadline: Option<Instant>,
+    indexes: Vec<Option<Rc<bgzf::Index>>>,
+    members: Vec<Option<PathBuf>>, // read from each input, an archive
+    entries: Vec<Option<Entry>>,   // where the member is in each
+    starts: Option<Vec<u64>>,      // offset of each input, then the total size
+    idx: usize,
+    cur: Option<Part>,
+    pos: u64,
+}
+
+/// Whether `stdin` was at its start when first looked at, before reading it
+/// moved the offset. Only then can it be read from the start again.
+fn stdin_at_start(stdin: &File) -> bool {
+    static AT_START: OnceLock<bool> = OnceLock::new();
+    *AT_START.get_or_init(|| { stdin }.stream_position().is_ok_and(|p| p == 0))
+}
+
+impl Concat {
+    /// Open `paths`, or with `member` that member of each of them, which
+    /// are tar or zip archives. An input named like `logs.zip!app.log` is
+    /// that member of the zip archive in any case.
+    pub fn open(
+        paths: &[impl AsRef<Path>],
+        member: Option<&Path>,
+        decompress: bool,
+        deadline: Option<Instant>,
+    ) -> Result<Self> {
+        let mut paths: Vec<PathBuf> = paths.iter().map(|p| p.as_ref().to_owned()).collect();
+        let mut members = vec![member.map(Path::to_owned); paths.len()];
+        for (path, member) in paths.iter_mut().zip(&mut members) {
+            if let Some((zip, name)) = archive::split_zip(path) {
+                (*path, *member) = (zip, Some(name));
+            }
+        }
+        // stdin read twice, or read from partway, only works as a stream
+        let stdin = match paths.iter().filter(|p| p.as_os_str() == "-").count() {
+            1 => platform::stdin_file().filter(|f| platform::is_seekable(f) && stdin_at_start(f)),
+            _ => None,
+        };
+        let mut this = Self {
+            paths,
+            stdin,
+            decompress,
+            strip_bom: false,
+            direct: false,
+            fadvise: false,
+            deadline,
+            indexes: Vec::new(),
+            members,
+            entries: Vec::new(),
+            starts: Some(vec![0]),
+            idx: 0,
+            cur: None,
+            pos: 0,
+        };
+        let mut indexes = vec![None; this.paths.len()];
+        let mut entries = vec![None; this.paths.len()];
+        let mut starts = this.starts.take();
+        for (idx, (path, index)) in this.paths.iter().zip(&mut indexes).enumerate() {
+            if unix_socket(path).is_some() {
+                if this.members[idx].is_some() {
+                    let msg = format!(
+                        "{}: Archive members of sockets cannot be read",
+                        path.display()
+                    );
+                    return Err(msg.into());
+                }
+                #[cfg(not(unix))]
+                return Err(format!(
+                    "{}: Unix socket input is not supported on this platform",
+                    path.display()
+                )
+                .into());
+                #[cfg(unix)]
+                {
+                    starts = None;
+                    continue;
+                }
+            }
+            if is_url(path) && this.members[idx].is_some() {
+                return Err(
+                    format!("{}: Archive members of URLs cannot be read", path.display()).into(),
+                );
+            }
+            if is_url(path) {
+                #[cfg(not(feature = "net"))]
+                return Err(format!(
+                    "{}: URL input is not supported by this build (enable the `net` feature)",
+                    path.display()
+                )
+                .into());
+                // a URL is sized by asking for its first bytes, which also tell
+                // whether it is compressed and so a stream, as a TCP socket
+                // always is
+                #[cfg(feature = "net")]
+                {
+                    if socket::is_tcp(&path.to_string_lossy()) {
+                        starts = None;
+                    }
+                    if let Some(s) = starts.as_mut() {
+                        let probe = remote::open(&path.to_string_lossy())
+                            .and_then(|r| remote::probe(&*r, MAGIC_LEN))
+                            .map_err(|e| Error::at(path, e))?;
+                        match probe {
+                            Some((size, head))
+                                if !decompress || Compression::from_magic(&head).is_none() =>
+                            {
+                                s.push(s.last().unwrap() + size)
+                            }
+                            _ => starts = None,
+                        }
+                    }
+                    continue;
+                }
+            }
+            if path.as_os_str() == "-" && this.stdin.is_none() {
+                starts = None;
+                continue;
+            }
+            // regular files are sized by their metadata; those claiming to be
+            // empty, which files in /proc do, are checked by reading them
+            let mut len = None;
+            let mut seekable = true;
+            if path.as_os_str() != "-" {
+                let meta = std::fs::metadata(path).map_err(|e| Error::at(path, e))?;
+                let ftype = meta.file_type();
+                if ftype.is_file() && meta.len() > 0 {
+                    len = Some(meta.len());
+                }
+                if ftype.is_dir() {
+                    return Err(format!("{}: Input file is a directory", path.display()).into());
+                }
+                if !platform::may_seek(ftype) {
+                    starts = None;
+                    seekable = false;
+                }
+            }
+            if let Some(member) = &this.members[idx] {
+                let with_path = |e| Error::at(path, e);
+                let mut file = match seekable {
+                    true => Some(this.file(idx).map_err(with_path)?),
+                    false => None,
+                };
+                // a compressed archive can only be read through
+                if let Some(file) = file.as_mut().filter(|f| platform::is_seekable(f))
+                    && !(decompress && Compression::detect_file(file, Some(path))?.is_some())
+                {
+                    let entry = archive::find_entry(file, member).map_err(with_path)?;
+                    entries[idx] = Some(entry);
+                    let mut head = Vec::new();
+                    Window::new(file.try_clone()?, entry.start, entry.len)?
+                        .take(MAGIC_LEN as u64)
+                        .read_to_end(&mut head)?;
+                    // and so can a compressed member
+                    if entry.deflated || decompress && Compression::from_magic(&head).is_some() {
+                        starts = None;
+                    }
+                    if let Some(s) = starts.as_mut() {
+                        s.push(s.last().unwrap() + entry.len);
+                    }
+                } else {
+                    starts = None;
+                }
+                continue;
+            }
+            if let Some(s) = starts.as_mut() {
+                // only opened to see whether it is compressed
+                if let Some(len) = len
+                    && !decompress
+                {
+                    s.push(s.last().unwrap() + len);
+                    continue;
+                }
+                let mut file = this.file(idx)?;
+                if len.is_none() && !platform::is_seekable(&file) {
+                    starts = None;
+                    continue;
+                }
+                let size = match decompress
+                    .then(|| Compression::detect_file(&mut file, Some(path)))
+                    .transpose()?
+                    .flatten()
+                {
+                    None => match len {
+                        Some(len) => len,
+                        None => file.seek(SeekFrom::End(0))?,
+                    },
+                    Some(Compression::Gzip) if bgzf::is_bgzf(&mut file)? => {
+                        let idx = bgzf::Index::load(&mut file, Some(path))
+                            .map_err(|e| Error::at(path, e))?;
+                        let size = idx.size();
+                        *index = Some(Rc::new(idx));
+                        size
+                    }
+                    Some(_) => {
+                        starts = None;
+                        continue;
+                    }
+                };
+                s.push(s.last().unwrap() + size);
+            }
+        }
+        (this.indexes, this.entries, this.starts) = (indexes, entries, starts);
+        Ok(this)
+    }
+
+    /// Input `idx` opened afresh, or seekable stdin back at its start.
+    fn file(&self, idx: usize) -> std::io::Result<File> {
+        match &self.stdin {
+            Some(stdin) if self.paths[idx].as_os_str() == "-" => {
+                // the offset is shared with every earlier clone
+                let mut stdin = stdin.try_clone()?;
+                stdin.seek(SeekFrom::Start(0))?;
+                Ok(stdin)
+            }
+            _ => File::open(&self.paths[idx]),
+        }
+    }
+
+    /// Drop a byte order mark from the start of each input, which makes the
+    /// stream unseekable.
+    pub fn strip_bom(mut self, strip: bool) -> Self {
+        if strip {
+            self.strip_bom = true;
+            self.starts = None;
+        }
+        self
+    }
+
+    /// Read named files that can be seeked around the page cache, except
+    /// BGZF ones, which means they are no longer handed out as plain files.
+    pub fn direct(mut self, direct: bool) -> Self {
+        self.direct = direct;
+        self
+    }
+
+    /// Tell the kernel that named files that can be seeked are read front
+    /// to back, and drop what was read of them from the page cache.
+    pub fn fadvise(mut self, fadvise: bool) -> Self {
+        self.fadvise = fadvise;
+        self
+    }
+
+    /// Total size, if every input is seekable.
+    pub fn size(&self) -> Option<u64> {
+        self.starts.as_ref().map(|s| *s.last().unwrap())
+    }
+
+    /// The input as a plain file, if it is a single uncompressed one.
+    pub fn single_file(&self) -> std::io::Result<Option<File>> {
+        match (self.paths.as_slice(), &self.starts, self.indexes.as_slice()) {
+            ([path], Some(_), [None])
+                if !self.direct && !is_url(path) && self.members[0].is_none() =>
+            {
+                self.file(0).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether any input is a URL, which is downloaded again each time it
+    /// is read.
+    pub fn remote(&self) -> bool {
+        self.paths.iter().any(|p| is_url(p))
+    }
+
+    fn open_part(&self, idx: usize) -> std::io::Result<Part> {
+        let path = &self.paths[idx];
+        if let Some(member) = &self.members[idx] {
+            return self.member_part(idx, member);
+        }
+        #[cfg(unix)]
+        if let Some(socket) = unix_socket(path) {
+            return self.socket_part(socket::connect_unix(socket).map_err(with_url(path))?);
+        }
+        #[cfg(feature = "net")]
+        if is_url(path) {
+            let url = path.to_string_lossy();
+            if socket::is_tcp(&url) {
+                return self.socket_part(socket::connect_tcp(&url).map_err(with_url(path))?);
+            }
+            let remote = remote::open(&url).map_err(with_url(path))?;
+            return match &self.starts {
+                Some(s) => Ok(Part::Remote(remote::Ranged::new(
+                    remote,
+                    &url,
+                    s[idx + 1] - s[idx],
+                ))),
+                None => self.stream_part(remote::stream(&*remote).map_err(with_url(path))?),
+            };
+        }
+        if path.as_os_str() == "-" && self.stdin.is_none() {
+            return self.stream_part(std::io::stdin());
+        }
+        let mut file = self.file(idx)?;
+        if let Some(index) = &self.indexes[idx] {
+            return Ok(Part::Bgzf(BgzfReader::new(file, index.clone())?));
+        }
+        if !platform::is_seekable(&file) {
+            return self.stream_part(file);
+        }
+        let compression = match self.decompress {
+            true => Compression::detect_file(&mut file, Some(path))?,
+            false => None,
+        };
+        if self.direct && path.as_os_str() != "-" {
+            let direct = DirectReader::open(path)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            return Ok(match compression {
+                Some(c) => Part::Stream(c.decoder(direct)?),
+                None => Part::Direct(direct),
+            });
+        }
+        if self.fadvise && path.as_os_str() != "-" {
+            let advised = Advised::new(file)?;
+            return Ok(match compression {
+                Some(c) => Part::Stream(c.decoder(advised)?),
+                None => Part::Advised(advised),
+            });
+        }
+        Ok(match compression {
+            Some(c) => Part::Stream(c.decoder(file)?),
+            None => Part::File(file),
+        })
+    }
+
+    /// The data of `member` in archive `idx`: where it was found in the
+    /// file, or else read through to, which decodes a compressed tar archive.
+    /// A compressed member is decoded as a stream too.
+    fn member_part(&self, idx: usize, member: &Path) -> std::io::Result<Part> {
+        let path = &self.paths[idx];
+        let with_path =
+            |e: std::io::Error| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+        let data: Box<dyn Read> = match self.entries[idx] {
+            Some(entry) => {
+                let window = Window::new(self.file(idx)?, entry.start, entry.len)?;
+                if entry.deflated {
+                    Box::new(DeflateDecoder::new(window))
+                } else if self.starts.is_some() {
+                    return Ok(Part::Window(window));
+                } else {
+                    Box::new(window)
+                }
+            }
+            None => {
+                let mut archive = match path.as_os_str() == "-" && self.stdin.is_none() {
+                    true => self.stream(std::io::stdin())?,
+                    false => self.stream(self.file(idx)?)?,
+                };
+                // the directory of a zip archive is at its end
+                let mut head = Vec::new();
+                archive.by_ref().take(4).read_to_end(&mut head)?;
+                if archive::is_zip(&head) {
+                    return Err(with_path(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "Zip archives can only be read from files that can be sought",
+                    )));
+                }
+                let archive = Cursor::new(head).chain(archive);
+                Box::new(archive::tar_stream(archive, member).map_err(with_path)?)
+            }
+        };
+        Ok(Part::Stream(match self.decompress {
+            true => decompress_stream(data)?,
+            false => data,
+        }))
+    }
+
+    /// A stream read to its end, or the deadline.
+    fn stream_part(&self, source: impl Read + Send + 'static) -> std::io::Result<Part> {
+        self.stream(source).map(Part::Stream)
+    }
+
+    /// What the peer sends on `conn`, which is shut down once it is no
+    /// longer read.
+    #[cfg(any(feature = "net", unix))]
+    fn socket_part(&self, conn: socket::Conn) -> std::io::Result<Part> {
+        let hangup = conn.try_clone()?;
+        Ok(Part::Stream(Box::new(Hangup::new(
+            self.stream(conn)?,
+            hangup,
+        ))))
+    }
+
+    /// `source` read until the deadline, and decoded if compressed.
+    fn stream(&self, source: impl Read + Send + 'static) -> std::io::Result<Box<dyn Read>> {
+        let source: Box<dyn Read> = match self.deadline {
+            Some(deadline) => Box::new(Deadline::new(source, deadline)),
+            None => Box::new(source),
+        };
+        Ok(if self.decompress {
+            decompress_stream(source)?
+        } else {
+            source
+        })
+    }
+}
+
+impl Read for Concat {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.idx < self.paths.len() {
+            if self.cur.is_none() {
+                let part = self.open_part(self.idx)?;
+                self.cur = Some(match self.strip_bom {
+                    true => Part::Stream(Box::new(strip_bom(part)?)),
+                    false => part,
+                });
+            }
+            let n = self.cur.as_mut().unwrap().read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            self.cur = None;
+            self.idx += 1;
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for Concat {
+    fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+        let starts = self
+            .starts
+            .as_ref()
+            .ok_or(std::io::ErrorKind::Unsupported)?;
+        let size = *starts.last().unwrap();
+        let pos = match from {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => size.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        }
+        .ok_or(std::io::ErrorKind::InvalidInput)?;
+
+        // the last input starting at or before pos, skipping empty ones
+        let idx = starts[1..].partition_point(|&s| s <= pos);
+        if idx < self.paths.len() {
+            let mut part = self.open_part(idx)?;
+            match &mut part {
+                Part::File(f) => f.seek(SeekFrom::Start(pos - starts[idx]))?,
+                Part::Advised(r) => r.seek(SeekFrom::Start(pos - starts[idx]))?,
+                Part::Direct(r) => r.seek(SeekFrom::Start(pos - starts[idx]))?,
+                Part::Bgzf(r) => r.seek(SeekFrom::Start(pos - starts[idx]))?,
+                Part::Window(r) => r.seek(SeekFrom::Start(pos - starts[idx]))?,
+                #[cfg(feature = "net")]
+                Part::Remote(r) => r.seek(SeekFrom::Start(pos - starts[idx]))?,
+                Part::Stream(_) => unreachable!("streams are not seekable"),
+            };
+            self.cur = Some(part);
+        } else {
+            self.cur = None;
+        }
+        self.idx = idx;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::{BufRead, ErrorKind, Read};
+
+use serde_json::value::RawValue;
+
+/// What to do with a JSON Lines record that does not parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidJson {
+    /// Drop the line; it does not count towards the range.
+    Skip,
+    /// Fail with the offending line number.
+    #[default]
+    Error,
+}
+
+/// Reader yielding only the valid JSON lines of `inner`, each terminated by
+/// a newline. Blank lines are always dropped.
+pub(crate) struct JsonlReader<R> {
+    inner: R,
+    policy: InvalidJson,
+    line: Vec<u8>,
+    pos: usize,
+    lineno: usize,
+}
+
+impl<R: BufRead> JsonlReader<R> {
+    pub fn new(inner: R, policy: InvalidJson) -> Self {
+        Self {
+            inner,
+            policy,
+            line: Vec::new(),
+            pos: 0,
+            lineno: 0,
+        }
+    }
+
+    /// Load the next valid line, returning false at EOF.
+    fn next_line(&mut self) -> std::io::Result<bool> {
+        loop {
+            self.line.clear();
+            self.pos = 0;
+            if self.inner.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(false);
+            }
+            self.lineno += 1;
+            if self.line.trim_ascii().is_empty() {
+                continue;
+            }
+            match serde_json::from_slice::<&RawValue>(&self.line) {
+                Ok(_) => {
+                    if self.line.last() != Some(&b'\n') {
+                        self.line.push(b'\n');
+                    }
+                    return Ok(true);
+                }
+                Err(_) if self.policy == InvalidJson::Skip => continue,
+                Err(e) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("line {}: invalid JSON: {}", self.lineno, e),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for JsonlReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.line.len() && !self.next_line()? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,78 @@
+//! Slice readers by lines or bytes using Python-like ranges.
+//!
+//! ```no_run
+//! use slice::{Mode, SliceRange, Slicer};
+//!
+//! let range: SliceRange = "-10:".parse().unwrap();
+//! let slicer = Slicer::new([range], Mode::LINE);
+//! slicer
+//!     .slice_reader(std::io::stdin(), std::io::stdout())
+//!     .unwrap();
+//! ```
+
+mod align;
+mod anchor;
+mod archive;
+mod batch;
+mod bgzf;
+mod compress;
+mod context;
+mod direct;
+mod error;
+mod follow;
+#[cfg(feature = "net")]
+mod http;
+mod index;
+mod inplace;
+mod input;
+mod jsonl;
+mod mmap;
+mod mode;
+mod output;
+mod parallel;
+mod platform;
+mod progress;
+mod range;
+#[cfg(feature = "net")]
+mod remote;
+mod report;
+mod route;
+mod sample;
+mod scan;
+mod slicer;
+#[cfg(any(feature = "net", unix))]
+mod socket;
+#[cfg(target_os = "linux")]
+mod splice;
+mod split;
+mod spool;
+#[cfg(feature = "net")]
+mod storage;
+mod tee;
+mod throttle;
+#[cfg(all(feature = "tls", unix))]
+mod tls;
+mod trace;
+#[cfg(target_os = "linux")]
+mod uring;
+mod walk;
+mod window;
+
+pub use align::Align;
+pub use direct::DirectWriter;
+pub use error::Error;
+pub use index::LineIndex;
+pub use jsonl::InvalidJson;
+pub use mode::{Mode, Separator};
+pub use output::{Format, InvalidUtf8};
+pub use platform::IoBackend;
+pub use progress::Progress;
+pub use range::{Anchor, SliceIdx, SliceRange, parse_size};
+pub use report::{Report, Strategy};
+pub use slicer::Slicer;
+pub use throttle::RateLimit;
+pub use trace::set_verbosity;
+pub use walk::{Glob, walk};
+pub use window::TimeWindow;
+
+pub type Result<T> = std::result::Result<T, Error>;
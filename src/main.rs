@@ -1,257 +1,1422 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, error::ErrorKind};
+use slice::{
+    Align, DirectWriter, Error, Format, Glob, InvalidJson, InvalidUtf8, IoBackend, LineIndex, Mode,
+    Progress, RateLimit, Separator, SliceRange, Slicer, TimeWindow, parse_size, set_verbosity,
+    walk,
+};
 use std::{
-    collections::VecDeque,
-    io::{BufReader, BufWriter, ErrorKind, Read, Seek, Write as _},
-    os::unix::fs::FileTypeExt,
+    fs::{File, OpenOptions},
+    io::{Read, Stdout, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
 };
 
-#[derive(Debug)]
-enum SliceIdx {
-    FromStart(usize),
-    FromEnd(usize),
-}
-impl From<isize> for SliceIdx {
-    fn from(i: isize) -> Self {
-        if i >= 0 {
-            SliceIdx::FromStart(i as usize)
-        } else {
-            SliceIdx::FromEnd((-i) as usize)
-        }
-    }
-}
-
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Range in the format start:end, where start and end can be negative.
     /// If start is omitted, it defaults to 0. If end is omitted, it defaults to the length of the input.
-    range: String,
+    /// An optional third part start:end:step selects every step-th unit; a negative step reverses the output.
+    /// Several ranges can be given separated by commas, e.g. 0:10,-5:.
+    /// A bound can also be /REGEX/, the first line matching it, e.g. /^BEGIN/:/^END/,
+    /// moved N lines with /REGEX/+N or /REGEX/-N.
+    /// Not given when -n NUM or -c NUM is used instead, and optional with --since or --until.
+    range: Option<String>,
+
+    /// Input files, members of zip archives as archive.zip!path, Unix sockets as
+    /// unix:///path/to.sock, or http://, s3://, gs:// and tcp://host:port URLs with the `net`
+    /// feature and https:// ones with `tls`, read as one concatenated stream. If omitted, stdin
+    /// is used.
+    input: Vec<String>,
+
+    /// Count by bytes instead of lines. Given NUM, select bytes as head -c NUM
+    /// (or tail -c +NUM) does.
+    #[arg(
+        short = 'c',
+        long = "byte",
+        value_name = "NUM",
+        num_args = 0..=1,
+        allow_negative_numbers = true
+    )]
+    byte_mode: Option<Option<String>>,
+
+    /// How many positional arguments come before the value -c took, to put a
+    /// file it took back among them.
+    #[arg(skip)]
+    byte_value_at: usize,
+
+    /// With -c, count fixed-size records of SIZE bytes (e.g. 512 or 4K) instead of bytes, the
+    /// last of which may be short.
+    #[arg(
+        long = "record-size",
+        value_name = "SIZE",
+        value_parser = |s: &str| match parse_size(s) {
+            Ok(0) => Err("record size must not be 0".to_string()),
+            r => r.map_err(|e| e.to_string()),
+        },
+        requires = "byte_mode",
+    )]
+    record_size: Option<usize>,
+
+    /// With -c, widen the byte ranges to the nearest boundaries of UNIT around them, so that
+    /// only whole lines or UTF-8 characters are selected. Needs an input that can be sought.
+    #[arg(
+        long = "align",
+        value_name = "UNIT",
+        value_enum,
+        requires = "byte_mode",
+        conflicts_with = "record_size"
+    )]
+    align: Option<AlignArg>,
+
+    /// With --align, narrow the byte ranges to the boundaries within them instead.
+    #[arg(long = "align-in", requires = "align")]
+    align_in: bool,
+
+    /// Read RANGE as sed addresses: FIRST[,LAST] counted from 1 with an
+    /// inclusive LAST, $ for the last line, and ; between addresses.
+    #[arg(long = "sed-addresses", conflicts_with_all = ["lines"])]
+    sed_addresses: bool,
+
+    /// Select lines as head -n NUM, head -n -NUM or tail -n +NUM does.
+    #[arg(
+        short = 'n',
+        long = "lines",
+        value_name = "NUM",
+        allow_hyphen_values = true,
+        conflicts_with_all = ["byte_mode", "char_mode"],
+    )]
+    lines: Option<String>,
+
+    /// Count by UTF-8 characters instead of lines.
+    #[arg(short = 'm', long = "chars", conflicts_with = "byte_mode")]
+    char_mode: bool,
+
+    /// Count records terminated by CHAR instead of lines.
+    /// Escapes such as \t, \0 and \x1e are accepted.
+    #[arg(
+        short = 'd',
+        long = "delimiter",
+        value_name = "CHAR",
+        value_parser = parse_delimiter,
+        conflicts_with_all = ["byte_mode", "char_mode"],
+    )]
+    delimiter: Option<u8>,
+
+    /// Count records terminated by the byte sequence STR instead of lines.
+    /// Accepts the same escapes as --delimiter.
+    #[arg(
+        long = "delimiter-str",
+        value_name = "STR",
+        value_parser = parse_delimiter_str,
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter"],
+    )]
+    delimiter_str: Option<std::vec::Vec<u8>>,
+
+    /// Count records ended by lines matching REGEX, such as ^---$, instead of lines. The
+    /// matching line is the last line of its record.
+    #[arg(
+        long = "delimiter-regex",
+        value_name = "REGEX",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str"],
+    )]
+    delimiter_regex: Option<String>,
+
+    /// Count lines terminated by \r\n (Windows line endings); a bare \n does not end a line.
+    #[arg(
+        long = "crlf",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex", "csv", "jsonl", "paragraphs", "fasta", "fastq", "warc", "zero_terminated"],
+    )]
+    crlf: bool,
+
+    /// What ends a line: \n (lf, the default), \r\n (crlf), a lone \r (cr), any of them
+    /// (any), or whichever ends the first line (auto).
+    #[arg(
+        long = "line-ending",
+        value_enum,
+        value_name = "ENDING",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex", "csv", "jsonl", "paragraphs", "fasta", "fastq", "warc", "zero_terminated", "crlf"],
+    )]
+    line_ending: Option<LineEndingArg>,
+
+    /// Count CSV rows, ignoring newlines inside quoted fields.
+    #[arg(
+        long = "csv",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex"],
+    )]
+    csv: bool,
+
+    /// Count JSON Lines records and guarantee the output is valid JSON Lines.
+    #[arg(
+        long = "jsonl",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex", "csv"],
+    )]
+    jsonl: bool,
+
+    /// What to do with lines that are not valid JSON in --jsonl mode.
+    #[arg(long = "invalid-json", value_enum, default_value_t = InvalidJsonArg::Error, requires = "jsonl")]
+    invalid_json: InvalidJsonArg,
+
+    /// Count paragraphs separated by empty lines instead of lines.
+    #[arg(
+        short = 'p',
+        long = "paragraphs",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex", "csv", "jsonl"],
+    )]
+    paragraphs: bool,
+
+    /// Count FASTA sequences, each a ">" header line and the sequence lines after it.
+    #[arg(
+        long = "fasta",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex", "csv", "jsonl", "paragraphs"],
+    )]
+    fasta: bool,
+
+    /// Count FASTQ reads of four lines each: header, sequence, "+" line and quality.
+    #[arg(
+        long = "fastq",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex", "csv", "jsonl", "paragraphs", "fasta"],
+    )]
+    fastq: bool,
+
+    /// Count WARC records, each its header, the Content-Length bytes of its block and the empty
+    /// lines after it.
+    #[arg(
+        long = "warc",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex", "csv", "jsonl", "paragraphs", "fasta", "fastq"],
+    )]
+    warc: bool,
+
+    /// Resolve /REGEX/ bounds to the last matching line instead of the first.
+    #[arg(long = "last")]
+    last: bool,
+
+    /// Only slice the log lines stamped at TIME (e.g. "2024-01-01 12:00") or later,
+    /// found by binary search in seekable files. RANGE is then optional and
+    /// counts from the first line in the time window.
+    #[arg(long = "since", value_name = "TIME", conflicts_with_all = ["follow", "follow_name"])]
+    since: Option<String>,
+
+    /// Only slice the log lines stamped before TIME, or before a duration
+    /// after --since, such as +1h (with s, m, h or d).
+    #[arg(long = "until", value_name = "TIME", conflicts_with_all = ["follow", "follow_name"])]
+    until: Option<String>,
+
+    /// Read the --since/--until stamps of lines in the strftime FORMAT, e.g.
+    /// "%d/%b/%Y:%H:%M:%S", at the start of the line or of --time-field or --time-regex.
+    #[arg(long = "time-format", value_name = "FORMAT")]
+    time_format: Option<String>,
+
+    /// Look for the stamp of each line from its N-th whitespace separated field on, counted from 1.
+    #[arg(long = "time-field", value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    time_field: Option<usize>,
+
+    /// Look for the stamp of each line only in the first match of REGEX, or its first group.
+    #[arg(long = "time-regex", value_name = "REGEX")]
+    time_regex: Option<String>,
+
+    /// Print everything outside the range instead of inside it.
+    #[arg(long = "invert")]
+    invert: bool,
+
+    /// Fail if the output is not valid UTF-8.
+    #[arg(long = "validate-utf8", conflicts_with_all = ["follow", "follow_name", "split"])]
+    validate_utf8: bool,
+
+    /// With --validate-utf8, replace invalid UTF-8 with U+FFFD instead of failing.
+    #[arg(long = "lossy", requires = "validate_utf8")]
+    lossy: bool,
+
+    /// Drop a UTF-8 or UTF-16 byte order mark from the start of each input before counting.
+    #[arg(long = "strip-bom", conflicts_with_all = ["follow", "follow_name"])]
+    strip_bom: bool,
+
+    /// Read the input file names from FILE, one per line, after any given as arguments.
+    /// FILE is - for stdin, which is then not read as an input itself.
+    #[arg(long = "files-from", value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Like --files-from, with the names ended by NUL bytes, as find -print0 writes them.
+    #[arg(
+        long = "files0-from",
+        value_name = "FILE",
+        conflicts_with = "files_from"
+    )]
+    files0_from: Option<PathBuf>,
+
+    /// Do not decode compressed (e.g. gzip) inputs.
+    #[arg(long = "no-decompress")]
+    no_decompress: bool,
+
+    /// Slice the file PATH inside each input, which are tar or zip archives, without unpacking
+    /// them. An input like logs.zip!app.log is that member of a zip archive.
+    #[arg(
+        long = "archive-member",
+        value_name = "PATH",
+        conflicts_with_all = ["in_place", "follow", "follow_name", "tee"],
+    )]
+    archive_member: Option<PathBuf>,
+
+    /// Slice each input file on its own, printing a "==> name <==" banner before each.
+    #[arg(long = "separate")]
+    separate: bool,
+
+    /// Slice every file under input directories on its own, as with --separate, in order of
+    /// their paths.
+    #[arg(
+        short = 'r',
+        long = "recursive",
+        conflicts_with_all = ["follow", "follow_name", "output_map", "tee", "split"],
+    )]
+    recursive: bool,
+
+    /// Slice the files of --separate or --recursive on N threads (0 for one per CPU), still
+    /// printing them in order.
+    #[arg(short = 'j', long = "jobs", value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    /// With several files sliced on their own, report those that fail and go on with the
+    /// rest, exiting with an error at the end.
+    #[arg(long = "continue-on-error")]
+    continue_on_error: bool,
+
+    /// With --recursive, only slice files whose names match PATTERN, e.g. '*.log'.
+    #[arg(long = "glob", value_name = "PATTERN", requires = "recursive")]
+    glob: Option<String>,
+
+    /// Keep printing data appended to the input file after slicing.
+    /// --follow=name reopens the file when it is rotated or replaced.
+    #[arg(
+        short = 'f',
+        long = "follow",
+        value_enum,
+        value_name = "HOW",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "descriptor",
+        conflicts_with_all = ["separate", "in_place", "invert"],
+    )]
+    follow: Option<FollowArg>,
+
+    /// Same as --follow=name.
+    #[arg(short = 'F', conflicts_with = "follow")]
+    follow_name: bool,
+
+    /// Seconds between checks for new data with --follow.
+    #[arg(
+        short = 's',
+        long = "sleep-interval",
+        value_name = "SECS",
+        default_value_t = 1.0
+    )]
+    sleep_interval: f64,
+
+    /// Rewrite each input file to contain only its slice.
+    #[arg(long = "in-place", conflicts_with_all = ["output", "separate"])]
+    in_place: bool,
+
+    /// Keep each original --in-place file as its name plus SUFFIX, e.g. .bak, like sed -i.bak.
+    #[arg(long = "backup", value_name = "SUFFIX", requires = "in_place")]
+    backup: Option<String>,
+
+    /// Give each --in-place file back its modification time, as well as its owner, permissions
+    /// and extended attributes, which are always kept.
+    #[arg(long = "keep-mtime", requires = "in_place")]
+    keep_mtime: bool,
+
+    /// Write the slice to FILE instead of stdout.
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Write each range to a file of its own in one pass over the input, given as
+    /// RANGE=FILE pairs separated by commas, e.g. '0:100=head.txt,-100:=tail.txt'.
+    /// All positional arguments are then input files.
+    #[arg(
+        long = "output-map",
+        value_name = "MAP",
+        conflicts_with_all = [
+            "lines", "output", "in_place", "separate", "follow", "follow_name", "count", "split",
+            "tee", "sed_addresses",
+        ],
+    )]
+    output_map: Option<String>,
+
+    /// Create missing parent directories of the --output file.
+    #[arg(long = "parents", requires = "output")]
+    parents: bool,
+
+    /// Add to the end of the --output file instead of overwriting it (O_APPEND), writing whole
+    /// records at a time so that other processes appending to it do not land inside one.
+    #[arg(long = "append", requires = "output", conflicts_with_all = ["no_clobber", "direct"])]
+    append: bool,
+
+    /// Fail instead of overwriting an existing --output file.
+    #[arg(long = "no-clobber", requires = "output")]
+    no_clobber: bool,
+
+    /// Copy the whole input, as it is, to FILE while printing only the slice, reading past the
+    /// slice to the end.
+    #[arg(
+        long = "tee",
+        value_name = "FILE",
+        conflicts_with_all = [
+            "separate", "in_place", "follow", "follow_name", "count", "split",
+        ],
+    )]
+    tee: Option<PathBuf>,
+
+    /// Read input files, and write the --output file, around the page cache (O_DIRECT).
+    #[arg(long = "direct", conflicts_with_all = ["follow", "follow_name", "in_place"])]
+    direct: bool,
+
+    /// Hint to the kernel that input files are read sequentially, and drop what was read of
+    /// them from the page cache (posix_fadvise).
+    #[arg(long = "fadvise", conflicts_with_all = ["follow", "follow_name", "direct"])]
+    fadvise: bool,
+
+    /// Always print the first record (e.g. a CSV header) and count the range from the record after it.
+    #[arg(long = "header", conflicts_with_all = ["byte_mode", "char_mode"])]
+    header: bool,
+
+    /// Print only N records picked at random from the range, in input order.
+    #[arg(
+        long = "sample",
+        value_name = "N",
+        conflicts_with_all = ["follow", "follow_name"]
+    )]
+    sample: Option<usize>,
 
-    /// Input file. If omitted, stdin is used.
-    input: Option<String>,
+    /// Print how many lines (records, or bytes with -c) the range selects instead of the content.
+    #[arg(
+        long = "count",
+        conflicts_with_all = [
+            "separate", "in_place", "follow", "follow_name", "format", "hex",
+            "number", "byte_offsets", "ensure_newline", "no_trailing_newline",
+        ],
+    )]
+    count: bool,
 
-    /// Count by bytes instead of lines.
-    #[arg(short = 'c', long = "byte")]
-    byte_mode: bool,
+    /// Write the range to numbered files of SIZE lines (or bytes with -c) each, like split.
+    /// SIZE takes the same suffixes as counts in ranges, e.g. 10K.
+    #[arg(
+        long = "split",
+        value_name = "SIZE",
+        value_parser = |s: &str| parse_size(s).map_err(|e| e.to_string()),
+        conflicts_with_all = [
+            "separate", "in_place", "output", "follow", "follow_name", "count", "format", "hex",
+            "offsets", "number", "byte_offsets", "ensure_newline", "no_trailing_newline",
+        ],
+    )]
+    split: Option<usize>,
+
+    /// Start of the names of the --split files, which are numbered from 00.
+    #[arg(
+        long = "split-prefix",
+        value_name = "PREFIX",
+        default_value = "x",
+        requires = "split"
+    )]
+    split_prefix: String,
+
+    /// Print the records in random order, like shuf.
+    #[arg(long = "shuffle", conflicts_with_all = ["follow", "follow_name"])]
+    shuffle: bool,
+
+    /// Seed --sample and --shuffle, so that they pick and order the same way every run.
+    #[arg(long = "seed", value_name = "N")]
+    seed: Option<u64>,
+
+    /// Read at most N lines (records, or bytes with -c) of input, as if it ended there.
+    #[arg(
+        long = "max-lines",
+        value_name = "N",
+        conflicts_with_all = ["follow", "follow_name"]
+    )]
+    max_lines: Option<usize>,
+
+    /// Fail if a range reaches past the end of the input instead of printing what there is.
+    #[arg(long = "strict", conflicts_with_all = ["follow", "follow_name"])]
+    strict: bool,
+
+    /// Hold at most SIZE bytes of a stream in memory (e.g. 512M): the tail for a range counted
+    /// from the end goes to a temporary file past it, reversed ranges and patterns fail, and so do
+    /// --sample and --shuffle once the records they keep take more.
+    #[arg(
+        long = "max-memory",
+        value_name = "SIZE",
+        value_parser = |s: &str| parse_size(s).map_err(|e| e.to_string()),
+    )]
+    max_memory: Option<usize>,
+
+    /// Read and write through buffers of SIZE bytes (e.g. 4M) instead of 128K.
+    #[arg(
+        long = "buffer-size",
+        value_name = "SIZE",
+        value_parser = |s: &str| match parse_size(s) {
+            Ok(0) => Err("buffer size must not be 0".to_string()),
+            r => r.map_err(|e| e.to_string()),
+        },
+    )]
+    buffer_size: Option<usize>,
+
+    /// How byte ranges of a single file are moved to the output.
+    #[arg(long = "io-backend", value_enum, default_value_t = IoBackendArg::Std)]
+    io_backend: IoBackendArg,
+
+    /// Draw a progress bar on stderr, with the throughput and the time left when the size of
+    /// the slice is known up front, or a running byte count when it is not.
+    #[arg(long = "progress", conflicts_with_all = ["follow", "follow_name", "count"])]
+    progress: bool,
+
+    /// Write a summary of the run to stderr once it is done: how the input was read (seek,
+    /// map or stream), the records emitted and where they are, the bytes written and the time
+    /// taken.
+    #[arg(
+        long = "report",
+        value_name = "FORMAT",
+        value_enum,
+        conflicts_with_all = [
+            "count", "follow", "follow_name", "split", "output_map", "separate", "recursive",
+            "in_place", "tee",
+        ],
+    )]
+    report: Option<ReportArg>,
+
+    /// With --report, write the summary to PATH instead of stderr.
+    #[arg(long = "report-file", value_name = "PATH", requires = "report")]
+    report_file: Option<PathBuf>,
+
+    /// Log to stderr how the input is read, the buffer sizes and how long each phase takes;
+    /// -vv also logs every seek and copy.
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// How to report an error on stderr. Either way, the exit status tells the kind of error:
+    /// 2 for a range or other argument that cannot be parsed, 3 for nothing selected with
+    /// --fail-empty, 4 for a missing file, 5 for other I/O errors, 6 for invalid data such as
+    /// invalid UTF-8 with --validate-utf8 or invalid --jsonl input, and 1 for anything else.
+    #[arg(long = "error-format", value_name = "FORMAT", default_value = "text")]
+    error_format: ErrorFormatArg,
+
+    /// Write at most RATE bytes a second (e.g. 10M), to feed a slice to a reader that cannot
+    /// take it at full speed.
+    #[arg(
+        long = "rate-limit",
+        value_name = "RATE",
+        value_parser = |s: &str| match parse_size(s) {
+            Ok(0) => Err("rate limit must not be 0".to_string()),
+            r => r.map_err(|e| e.to_string()),
+        },
+        conflicts_with_all = ["count"],
+    )]
+    rate_limit: Option<usize>,
+
+    /// Exit with status 3 if nothing is printed, e.g. when the range selects no lines.
+    #[arg(
+        long = "fail-empty",
+        conflicts_with_all = ["follow", "follow_name", "split", "in_place"]
+    )]
+    fail_empty: bool,
+
+    /// Stop reading stdin, a pipe or a socket after SECS seconds, as if the input ended there.
+    #[arg(
+        long = "time-limit",
+        value_name = "SECS",
+        conflicts_with_all = ["follow", "follow_name"]
+    )]
+    time_limit: Option<f64>,
+
+    /// Prefix each record with its line number in the input, like grep -n.
+    #[arg(
+        short = 'N',
+        long = "number",
+        conflicts_with_all = ["byte_mode", "char_mode", "follow", "follow_name"]
+    )]
+    number: bool,
+
+    /// Prefix each record with the byte offset where it starts in the input.
+    #[arg(
+        long = "byte-offsets",
+        conflicts_with_all = ["byte_mode", "char_mode", "jsonl", "follow", "follow_name"]
+    )]
+    byte_offsets: bool,
+
+    /// Also print N records before and after the range, like grep -C, marking the selected
+    /// ones with `> `. Ranges counted from the end are resolved by reading the input first.
+    #[arg(
+        short = 'C',
+        long = "context",
+        value_name = "N",
+        conflicts_with_all = [
+            "byte_mode", "char_mode", "format", "hex", "offsets", "count", "invert", "sample",
+            "shuffle", "split", "output_map", "follow", "follow_name",
+        ],
+    )]
+    context: Option<usize>,
+
+    /// With --context, dim the context and show the range in bold instead of marking it.
+    #[arg(long = "color", requires = "context")]
+    color: bool,
+
+    /// How to write the selected records.
+    #[arg(
+        long = "format",
+        value_enum,
+        default_value_t = FormatArg::Raw,
+        conflicts_with_all = [
+            "byte_mode", "char_mode", "jsonl", "follow", "follow_name",
+            "number", "byte_offsets", "ensure_newline", "no_trailing_newline",
+        ],
+    )]
+    format: FormatArg,
+
+    /// Print the selection as an xxd style hex dump with offsets into the input.
+    #[arg(
+        long = "hex",
+        conflicts_with_all = [
+            "format", "jsonl", "follow", "follow_name",
+            "number", "byte_offsets", "ensure_newline", "no_trailing_newline",
+        ],
+    )]
+    hex: bool,
+
+    /// Print where the range is instead of its content: a line with the start and end byte
+    /// offsets and the first and end line numbers (0-based, end exclusive) of each run of
+    /// adjacent lines.
+    #[arg(
+        long = "offsets",
+        conflicts_with_all = [
+            "format", "hex", "count", "follow", "follow_name",
+            "number", "byte_offsets", "ensure_newline", "no_trailing_newline",
+        ],
+    )]
+    offsets: bool,
+
+    /// End the output with a newline (or the record delimiter) if it does not already.
+    #[arg(long = "ensure-newline")]
+    ensure_newline: bool,
+
+    /// Remove the newline (or the record delimiter) ending the output, if any.
+    #[arg(
+        long = "no-trailing-newline",
+        conflicts_with_all = ["ensure_newline", "zero_terminated"]
+    )]
+    no_trailing_newline: bool,
+
+    /// End each record written with DELIM instead of its terminator in the input, e.g. '\0' to
+    /// feed lines to xargs -0. Escapes such as \t, \0 and \x1e are accepted.
+    #[arg(
+        long = "output-delimiter",
+        value_name = "DELIM",
+        value_parser = parse_escapes,
+        conflicts_with_all = ["byte_mode", "char_mode", "format", "hex", "offsets", "follow", "follow_name"],
+    )]
+    output_delimiter: Option<std::vec::Vec<u8>>,
+
+    /// Records are NUL-terminated instead of newline-terminated, and so is the output.
+    #[arg(
+        short = 'z',
+        long = "zero-terminated",
+        conflicts_with_all = ["byte_mode", "char_mode", "delimiter", "delimiter_str", "delimiter_regex", "csv", "jsonl", "paragraphs", "fasta", "fastq", "warc"],
+    )]
+    zero_terminated: bool,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write a sparse index of where lines start next to each FILE, as
+    /// FILE.slice-idx, so that later line ranges on it can seek.
+    Index {
+        /// Files to index.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
 
-    let range_str = &args.range;
-    let (s, e) = range_str.split_once(':').ok_or("Invalid range format")?;
-    let start = if s.is_empty() {
-        SliceIdx::FromStart(0)
-    } else {
-        s.parse::<isize>()?.into()
-    };
-    let end = if e.is_empty() {
-        SliceIdx::FromEnd(0)
-    } else if let Some(v) = e.strip_prefix('+') {
-        let n: usize = v.parse()?;
-        match start {
-            SliceIdx::FromStart(m) => SliceIdx::FromStart(m + n),
-            SliceIdx::FromEnd(m) => SliceIdx::FromEnd(m - n),
+        /// Lines between index entries.
+        #[arg(long = "every", value_name = "N", default_value_t = 1000)]
+        every: u64,
+
+        /// Index records terminated by CHAR instead of lines.
+        #[arg(
+            short = 'd',
+            long = "delimiter",
+            value_name = "CHAR",
+            value_parser = parse_delimiter
+        )]
+        delimiter: Option<u8>,
+
+        /// Index NUL-terminated records.
+        #[arg(short = 'z', long = "zero-terminated", conflicts_with = "delimiter")]
+        zero_terminated: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FollowArg {
+    /// Follow the open file, even if it is renamed
+    Descriptor,
+    /// Follow the path, reopening it when it is rotated
+    Name,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum AlignArg {
+    /// The start of a line, after a newline
+    Lines,
+    /// The start of a UTF-8 character, not inside a multi-byte sequence
+    Utf8,
+}
+
+impl From<AlignArg> for Align {
+    fn from(a: AlignArg) -> Self {
+        match a {
+            AlignArg::Lines => Align::Lines,
+            AlignArg::Utf8 => Align::Utf8,
         }
-    } else {
-        e.parse::<isize>()?.into()
-    };
+    }
+}
 
-    let mut bufwriter = std::io::BufWriter::new(std::io::stdout());
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FormatArg {
+    /// The records as they are in the input
+    Raw,
+    /// A JSON object with the source, the resolved start and end, and the records
+    Json,
+}
 
-    let mode = if args.byte_mode {
-        CountModeEnum::Byte
-    } else {
-        CountModeEnum::Line
-    };
+impl From<FormatArg> for Format {
+    fn from(a: FormatArg) -> Self {
+        match a {
+            FormatArg::Raw => Format::Raw,
+            FormatArg::Json => Format::Json,
+        }
+    }
+}
 
-    if args.input.as_ref().is_none_or(|s| s == "-") {
-        // stdin
-        let mut bufreader = std::io::BufReader::new(std::io::stdin());
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum IoBackendArg {
+    /// sendfile(2) on Linux, plain reads and writes elsewhere
+    Std,
+    /// io_uring on Linux 5.6 and later, reading ahead while writing
+    Uring,
+}
 
-        slice_stream_wrapper(start, end, &mut bufreader, &mut bufwriter, mode)?;
-    } else {
-        // file
-        let mut file = std::fs::File::open(args.input.unwrap())?;
-        let ftype = file.metadata()?.file_type();
-        if ftype.is_dir() {
-            return Err("Input file is a directory".into());
+impl From<IoBackendArg> for IoBackend {
+    fn from(a: IoBackendArg) -> Self {
+        match a {
+            IoBackendArg::Std => IoBackend::Std,
+            IoBackendArg::Uring => IoBackend::Uring,
         }
+    }
+}
 
-        // let seekable = file.seek(std::io::SeekFrom::Start(0)).is_ok();
-        let seekable = ftype.is_file() || ftype.is_block_device();
-        if seekable && mode == CountModeEnum::Byte {
-            // just use seek
-            // let size = file.metadata()?.len() as isize;
-            let size = file.seek(std::io::SeekFrom::End(0))? as isize;
-            // eprintln!("size: {}", size);
-            let start = match start {
-                SliceIdx::FromStart(n) => n as isize,
-                SliceIdx::FromEnd(n) => size - n as isize,
-            }
-            .clamp(0, size);
-            let end = match end {
-                SliceIdx::FromStart(n) => n as isize,
-                SliceIdx::FromEnd(n) => size - n as isize,
-            }
-            .clamp(0, size);
-            if start >= end {
-                return Ok(());
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ReportArg {
+    /// A line of JSON
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorFormatArg {
+    /// A line of text
+    Text,
+    /// A line of JSON with the kind of error, its message and the exit status
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum InvalidJsonArg {
+    /// Drop invalid lines; they do not count towards the range
+    Skip,
+    /// Stop with an error at the first invalid line
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LineEndingArg {
+    /// The same as the first line of the input, and nothing else
+    Auto,
+    /// \n
+    Lf,
+    /// \r\n
+    Crlf,
+    /// A lone \r, as on classic Mac OS
+    Cr,
+    /// Any of \n, \r\n and a lone \r, even mixed
+    Any,
+}
+
+impl From<LineEndingArg> for Mode {
+    fn from(a: LineEndingArg) -> Self {
+        match a {
+            LineEndingArg::Auto => Mode::AutoLine,
+            LineEndingArg::Lf => Mode::LINE,
+            LineEndingArg::Crlf => Mode::crlf(),
+            LineEndingArg::Cr => Mode::Delimited(b'\r'),
+            LineEndingArg::Any => Mode::AnyLine,
+        }
+    }
+}
+
+impl From<InvalidJsonArg> for InvalidJson {
+    fn from(a: InvalidJsonArg) -> Self {
+        match a {
+            InvalidJsonArg::Skip => InvalidJson::Skip,
+            InvalidJsonArg::Error => InvalidJson::Error,
+        }
+    }
+}
+
+/// Parse a string with backslash escapes (`\n`, `\t`, `\0`, `\xHH`, `\\`) into bytes.
+fn parse_escapes(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        out.push(match bytes.next() {
+            Some(b'n') => b'\n',
+            Some(b't') => b'\t',
+            Some(b'r') => b'\r',
+            Some(b'0') => b'\0',
+            Some(b'\\') => b'\\',
+            Some(b'x') => {
+                let digit = |b: Option<u8>| b.and_then(|b| (b as char).to_digit(16));
+                match (digit(bytes.next()), digit(bytes.next())) {
+                    (Some(h), Some(l)) => (h * 16 + l) as u8,
+                    _ => return Err("\\x must be followed by two hex digits".into()),
+                }
             }
-            file.seek(std::io::SeekFrom::Start(start as u64))?;
-            let mut handle = file.take((end - start) as u64);
-            std::io::copy(&mut handle, &mut bufwriter)?;
-        } else {
-            let mut bufreader = std::io::BufReader::new(file);
-            slice_stream_wrapper(start, end, &mut bufreader, &mut bufwriter, mode)?;
+            Some(c) => return Err(format!("unknown escape \\{}", c as char)),
+            None => return Err("trailing backslash".into()),
+        });
+    }
+    Ok(out)
+}
+
+/// Parse the RANGE=FILE pairs of --output-map.
+fn parse_output_map(s: &str) -> Result<Vec<(SliceRange, PathBuf)>, Error> {
+    s.split(',')
+        .map(|pair| {
+            let (range, path) = pair
+                .split_once('=')
+                .filter(|(_, path)| !path.is_empty())
+                .ok_or_else(|| {
+                    Error::Parse(format!(
+                        "Invalid --output-map entry: {pair}, expected RANGE=FILE"
+                    ))
+                })?;
+            Ok((range.parse()?, PathBuf::from(path)))
+        })
+        .collect()
+}
+
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match parse_escapes(s)?.as_slice() {
+        [b] => Ok(*b),
+        _ => Err("delimiter must be a single byte".into()),
+    }
+}
+
+fn parse_delimiter_str(s: &str) -> Result<Vec<u8>, String> {
+    let d = parse_escapes(s)?;
+    if d.is_empty() {
+        return Err("delimiter must not be empty".into());
+    }
+    Ok(d)
+}
+
+/// Where the output goes: all are plain descriptors, which on Unix lets byte
+/// ranges skip the userspace copy.
+enum Output {
+    Stdout(Stdout),
+    File(File),
+    Direct(DirectWriter),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Stdout(s) => s.write(buf),
+            Output::File(f) => f.write(buf),
+            Output::Direct(d) => d.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Stdout(s) => s.flush(),
+            Output::File(f) => f.flush(),
+            Output::Direct(d) => d.flush(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsFd for Output {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        match self {
+            Output::Stdout(s) => s.as_fd(),
+            Output::File(f) => f.as_fd(),
+            Output::Direct(d) => d.get_ref().as_fd(),
         }
     }
+}
 
+fn slice_files(slicer: &Slicer, paths: &[PathBuf], out: Output) -> slice::Result<()> {
+    #[cfg(unix)]
+    return slicer.slice_files_fd(paths, out);
+    #[cfg(not(unix))]
+    return slicer.slice_files(paths, out);
+}
+
+fn open_output(args: &Args) -> Result<Output, Error> {
+    let Some(path) = &args.output else {
+        return Ok(Output::Stdout(std::io::stdout()));
+    };
+    if let Some(dir) = path
+        .parent()
+        .filter(|d| args.parents && !d.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut opts = OpenOptions::new();
+    opts.write(true);
+    if args.no_clobber {
+        opts.create_new(true);
+    } else if args.append {
+        opts.create(true).append(true);
+    } else {
+        opts.create(true).truncate(true);
+    }
+    let with_path = |e| Error::at(path, e);
+    if args.direct {
+        return Ok(Output::Direct(
+            DirectWriter::open(&mut opts, path).map_err(with_path)?,
+        ));
+    }
+    Ok(Output::File(opts.open(path).map_err(with_path)?))
+}
+
+/// The ranges, from a head/tail style count or the first positional argument,
+/// and the input files that follow.
+fn ranges_and_inputs(args: &Args) -> Result<(Vec<SliceRange>, Vec<String>), Error> {
+    let mut positional: Vec<String> = args.range.iter().chain(&args.input).cloned().collect();
+    if let Some(map) = &args.output_map {
+        if let Some(Some(v)) = &args.byte_mode {
+            positional.insert(args.byte_value_at, v.clone());
+        }
+        let ranges = parse_output_map(map)?.into_iter().map(|(r, _)| r).collect();
+        return Ok((ranges, positional));
+    }
+    match &args.byte_mode {
+        // -c used to take no value, so `-c 0:10` is still a range, and
+        // `0:10 -c FILE` still slices FILE
+        Some(Some(v)) if v.contains(':') || args.sed_addresses => {
+            positional.insert(args.byte_value_at, v.clone())
+        }
+        Some(Some(v)) => match SliceRange::parse_count(v) {
+            Ok(count) => return Ok((vec![count], positional)),
+            Err(_) => positional.insert(args.byte_value_at, v.clone()),
+        },
+        _ => (),
+    }
+    let windowed = args.since.is_some() || args.until.is_some();
+    let ranges = match &args.lines {
+        Some(n) => vec![SliceRange::parse_count(n)?],
+        // with a time window, the range is optional
+        None if windowed
+            && positional
+                .first()
+                .is_none_or(|p| Path::new(p).exists() || SliceRange::parse_list(p).is_err()) =>
+        {
+            vec![":".parse()?]
+        }
+        None if positional.is_empty() => usage_error(
+            Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  <RANGE>",
+            ),
+            args.error_format,
+        ),
+        None if args.sed_addresses => SliceRange::parse_sed_list(&positional.remove(0))?,
+        None => SliceRange::parse_list(&positional.remove(0))?,
+    };
+    Ok((ranges, positional))
+}
+
+/// The file names in the list at `path`, or stdin for `-`, each ended by
+/// `sep`. Empty names are skipped.
+fn read_file_list(path: &Path, sep: u8) -> Result<Vec<PathBuf>, Error> {
+    let with_path = |e| Error::at(path, e);
+    let list = if path.as_os_str() == "-" {
+        let mut list = Vec::new();
+        std::io::stdin().read_to_end(&mut list).map_err(with_path)?;
+        list
+    } else {
+        std::fs::read(path).map_err(with_path)?
+    };
+    let names = list.split(|&c| c == sep).filter(|name| !name.is_empty());
+    names
+        .map(|name| {
+            if name == b"-" && path.as_os_str() == "-" {
+                return Err("stdin cannot be listed in a file list read from stdin".into());
+            }
+            #[cfg(unix)]
+            let name: &std::ffi::OsStr = std::os::unix::ffi::OsStrExt::from_bytes(name);
+            #[cfg(not(unix))]
+            let name = std::str::from_utf8(name).map_err(|e| e.to_string())?;
+            Ok(PathBuf::from(name))
+        })
+        .collect()
+}
+
+fn index_files(files: &[PathBuf], delim: u8, every: u64) -> Result<(), Error> {
+    for path in files {
+        let file = File::open(path).map_err(|e| Error::at(path, e))?;
+        LineIndex::build(&file, delim, every)?.save(&LineIndex::sidecar(path))?;
+    }
     Ok(())
 }
 
-trait CountMode {
-    fn count(c: u8) -> usize;
+/// Records written at once, unless one is longer, when appending.
+const APPEND_BATCH: usize = 128 * 1024;
+
+/// Output to a file opened with --append, written a whole number of records at
+/// a time: each write(2) of an O_APPEND file lands at its end as one piece.
+/// Records are told apart by the byte ending them; without one, as with -c,
+/// the output goes in pieces of any size.
+struct Appended<W: Write> {
+    inner: W,
+    end: Option<u8>,
+    buf: Vec<u8>,
 }
 
-struct CountModeByte;
-impl CountMode for CountModeByte {
-    #[inline]
-    fn count(_c: u8) -> usize {
-        1
+impl<W: Write> Write for Appended<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= APPEND_BATCH {
+            let whole = match self.end {
+                Some(end) => self
+                    .buf
+                    .iter()
+                    .rposition(|&c| c == end)
+                    .map_or(0, |i| i + 1),
+                None => self.buf.len(),
+            };
+            self.inner.write_all(&self.buf[..whole])?;
+            self.buf.drain(..whole);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        self.inner.flush()
     }
 }
 
-struct CountModeLine;
-impl CountMode for CountModeLine {
-    #[inline]
-    fn count(c: u8) -> usize {
-        // NOTE: with UTF-8, comparing bytes is safe.
-        (c == b'\n') as usize
+impl<W: Write> Drop for Appended<W> {
+    fn drop(&mut self) {
+        // like BufWriter, errors here are lost; flush first to see them
+        let _ = self.flush();
     }
 }
 
-#[derive(PartialEq, Eq)]
-enum CountModeEnum {
-    Byte,
-    Line,
+/// Output noting whether anything was written to it, for --fail-empty.
+struct Watched<W> {
+    inner: W,
+    wrote: bool,
 }
 
-fn slice_stream_wrapper(
-    start: SliceIdx,
-    end: SliceIdx,
-    stream: &mut BufReader<impl std::io::Read>,
-    out: &mut BufWriter<impl std::io::Write>,
-    mode: CountModeEnum,
-) -> Result<(), Box<dyn std::error::Error>> {
-    match mode {
-        CountModeEnum::Byte => slice_stream::<CountModeByte>(start, end, stream, out),
-        CountModeEnum::Line => slice_stream::<CountModeLine>(start, end, stream, out),
+impl<W: Write> Write for Watched<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.wrote |= n > 0;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
     }
 }
 
-#[inline]
-fn read_char(
-    reader: &mut BufReader<impl std::io::Read>,
-) -> Result<Option<u8>, Box<dyn std::error::Error>> {
-    let mut c = [0; 1];
-    match reader.read_exact(&mut c) {
-        Ok(()) => Ok(Some(c[0])),
-        Err(e) => match e.kind() {
-            ErrorKind::UnexpectedEof => Ok(None),
-            _ => Err(e.into()),
-        },
+/// Write `e` to stderr as a line in `format`.
+fn report_error(e: &Error, format: ErrorFormatArg) {
+    match format {
+        // nothing selected is told by the status alone
+        ErrorFormatArg::Text if matches!(e, Error::Empty) => (),
+        ErrorFormatArg::Text => eprintln!("Error: {e}"),
+        ErrorFormatArg::Json => eprintln!("{}", e.to_json(None)),
     }
 }
 
-fn slice_stream<M: CountMode>(
-    start: SliceIdx,
-    end: SliceIdx,
-    stream: &mut BufReader<impl std::io::Read>,
-    out: &mut BufWriter<impl std::io::Write>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // let mut queue = VecDeque::new();
+/// Parse the arguments, noting where among the positional ones -c took its
+/// value.
+fn parse_args() -> Result<Args, clap::Error> {
+    let matches = Args::command().try_get_matches()?;
+    let mut args = Args::from_arg_matches(&matches)?;
+    if let (Some(Some(_)), Some(at)) = (&args.byte_mode, matches.index_of("byte_mode")) {
+        args.byte_value_at = ["range", "input"]
+            .into_iter()
+            .filter_map(|id| matches.indices_of(id))
+            .flatten()
+            .filter(|&i| i < at)
+            .count();
+    }
+    Ok(args)
+}
 
-    // start
-    match (start, end) {
-        (SliceIdx::FromStart(n), SliceIdx::FromStart(m)) => {
-            if n >= m {
-                return Ok(());
-            }
+/// Whether `--error-format json` is among the arguments, to report errors
+/// parsing the others in.
+fn wants_json_errors(args: impl Iterator<Item = std::ffi::OsString>) -> bool {
+    let mut args = args.take_while(|a| a != "--");
+    while let Some(arg) = args.next() {
+        if arg == "--error-format=json"
+            || arg == "--error-format" && args.next().is_some_and(|v| v == "json")
+        {
+            return true;
+        }
+    }
+    false
+}
 
-            let mut i = 0;
-            loop {
-                let c = if let Some(c) = read_char(stream)? {
-                    c
-                } else {
-                    return Ok(());
-                };
+/// End with the usage error `e`, as clap does, or as a line of JSON.
+fn usage_error(e: clap::Error, format: ErrorFormatArg) -> ! {
+    let help = matches!(e.kind(), ErrorKind::DisplayHelp | ErrorKind::DisplayVersion);
+    if format == ErrorFormatArg::Text || help {
+        e.exit();
+    }
+    // the message without the tips and usage clap follows it with
+    let msg = e.to_string();
+    let msg: Vec<&str> = msg
+        .lines()
+        .take_while(|l| !l.is_empty())
+        .map(str::trim)
+        .collect();
+    let msg = msg.join(" ");
+    let e = Error::Parse(msg.strip_prefix("error: ").unwrap_or(&msg).to_owned());
+    report_error(&e, format);
+    std::process::exit(e.status().into());
+}
 
-                if i >= n {
-                    out.write_all(&[c])?;
-                }
-                i += M::count(c);
-                if i >= m {
-                    return Ok(());
-                }
-            }
+fn main() -> ExitCode {
+    // like other filters, die quietly by the signal once `head` or a pager
+    // stops reading, instead of failing every write with EPIPE
+    #[cfg(unix)]
+    // SAFETY: no other thread is running yet to race on the disposition
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+
+    let args = parse_args().unwrap_or_else(|e| {
+        let format = match wants_json_errors(std::env::args_os()) {
+            true => ErrorFormatArg::Json,
+            false => ErrorFormatArg::Text,
+        };
+        usage_error(e, format)
+    });
+    let format = args.error_format;
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        // where there is no SIGPIPE, the error ends the output just as quietly
+        Err(e) if e.is_broken_pipe() => ExitCode::SUCCESS,
+        Err(e) => {
+            report_error(&e, format);
+            ExitCode::from(e.status())
         }
-        (SliceIdx::FromStart(n), SliceIdx::FromEnd(m)) => {
-            // skip first n
-            for _ in 0..n {
-                if read_char(stream)?.is_none() {
-                    return Ok(());
-                }
-            }
-            let mut q = VecDeque::new();
-            let mut qn = 0; // count in q
-            loop {
-                let c = if let Some(c) = read_char(stream)? {
-                    c
-                } else {
-                    return Ok(());
-                };
-                q.push_back(c);
-                qn += M::count(c);
-                while qn > m {
-                    let front = q.pop_front().unwrap();
-                    qn -= M::count(front);
-                    out.write_all(&[front])?;
-                }
-            }
+    }
+}
+
+fn run(mut args: Args) -> Result<(), Error> {
+    args.separate |= args.recursive;
+    set_verbosity(args.verbose);
+
+    if let Some(Command::Index {
+        files,
+        every,
+        delimiter,
+        zero_terminated,
+    }) = &args.command
+    {
+        let delim = if *zero_terminated {
+            b'\0'
+        } else {
+            delimiter.unwrap_or(b'\n')
+        };
+        return index_files(files, delim, *every);
+    }
+
+    let (ranges, inputs) = ranges_and_inputs(&args)?;
+    let mut inputs: Vec<PathBuf> = inputs.into_iter().map(PathBuf::from).collect();
+    let list = match (&args.files_from, &args.files0_from) {
+        (Some(list), _) => Some((list, b'\n')),
+        (None, Some(list)) => Some((list, b'\0')),
+        (None, None) => None,
+    };
+    if let Some((list, sep)) = list {
+        inputs.extend(read_file_list(list, sep)?);
+        if inputs.is_empty() {
+            return Ok(()); // nothing to slice, and stdin is taken
         }
-        (SliceIdx::FromEnd(n), m) => {
-            let mut i = 0;
-            let mut q = VecDeque::new();
-            let mut qn = 0;
-            loop {
-                let c = if let Some(c) = read_char(stream)? {
-                    c
-                } else {
-                    break;
-                };
-                q.push_back(c);
-                qn += M::count(c);
-                while qn > n {
-                    let front = q.pop_front().unwrap();
-                    let v = M::count(front);
-                    qn -= v;
-                    i += v;
-                }
+    }
+    if args.recursive {
+        let glob = args.glob.as_deref().map(Glob::new).transpose()?;
+        let mut files = Vec::new();
+        for path in inputs {
+            match path.is_dir() {
+                true => files.extend(walk(&path, glob.as_ref())?),
+                false => files.push(path),
             }
-            let m = match m {
-                SliceIdx::FromStart(m) => m,
-                SliceIdx::FromEnd(m) => i + n - m,
+        }
+        if files.is_empty() {
+            return Ok(()); // the directories hold nothing to slice
+        }
+        inputs = files;
+    }
+    let time_limit = args
+        .time_limit
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| Error::Parse(format!("--time-limit: {e}")))?;
+
+    let mode = if args.byte_mode.is_some() {
+        match args.record_size {
+            Some(size) if size > 1 => Mode::Record(size),
+            _ => Mode::Byte,
+        }
+    } else if args.char_mode {
+        Mode::Char
+    } else if args.csv {
+        Mode::Csv
+    } else if args.jsonl {
+        Mode::Jsonl(args.invalid_json.into())
+    } else if args.paragraphs {
+        Mode::Paragraph
+    } else if args.fasta {
+        Mode::Fasta
+    } else if args.fastq {
+        Mode::Fastq
+    } else if args.warc {
+        Mode::Warc
+    } else if args.zero_terminated {
+        Mode::Delimited(b'\0')
+    } else if let Some(d) = args.delimiter {
+        Mode::Delimited(d)
+    } else if let Some(d) = &args.delimiter_str {
+        Mode::DelimitedStr(d.clone())
+    } else if let Some(pattern) = &args.delimiter_regex {
+        Mode::DelimitedRegex(Separator::new(pattern)?)
+    } else if args.crlf {
+        Mode::crlf()
+    } else if let Some(ending) = args.line_ending {
+        ending.into()
+    } else {
+        Mode::LINE
+    };
+    // the byte each record written ends with, if any, for --append
+    let record_end = match (&args.output_delimiter, &mode) {
+        (Some(d), _) => d.last().copied(),
+        (None, Mode::Byte | Mode::Record(_) | Mode::Char) => None,
+        (None, Mode::Delimited(d)) => Some(*d),
+        (None, Mode::DelimitedStr(d)) => d.last().copied(),
+        (None, _) => Some(b'\n'),
+    };
+    let window = match (&args.since, &args.until) {
+        (None, None)
+            if args.time_format.is_some()
+                || args.time_field.is_some()
+                || args.time_regex.is_some() =>
+        {
+            return Err(
+                "--time-format, --time-field and --time-regex need --since or --until".into(),
+            );
+        }
+        (None, None) => None,
+        (since, until) => Some(
+            TimeWindow::new(since.as_deref(), until.as_deref())?
+                .format(args.time_format.as_deref())?
+                .field(args.time_field)
+                .pattern(args.time_regex.as_deref())?,
+        ),
+    };
+
+    let slicer = Slicer::new(ranges, mode)
+        .terminate(args.zero_terminated || args.ensure_newline)
+        .strip_terminator(args.no_trailing_newline)
+        .header(args.header)
+        .last_match(args.last)
+        .number(args.number)
+        .output_delimiter(args.output_delimiter.clone())
+        .byte_offsets(args.byte_offsets)
+        .format(if args.hex {
+            Format::Hex
+        } else if args.offsets {
+            Format::Offsets
+        } else {
+            args.format.into()
+        })
+        .invert(args.invert)
+        .sample(args.sample)
+        .shuffle(args.shuffle)
+        .seed(args.seed)
+        .max_records(args.max_lines)
+        .strict(args.strict)
+        .max_memory(args.max_memory)
+        .time_limit(time_limit)
+        .time_window(window)
+        .validate_utf8(args.validate_utf8.then_some(if args.lossy {
+            InvalidUtf8::Replace
+        } else {
+            InvalidUtf8::Error
+        }))
+        .strip_bom(args.strip_bom)
+        .decompress(!args.no_decompress)
+        .archive_member(args.archive_member.clone())
+        .align(args.align.map(Align::from))
+        .align_inward(args.align_in)
+        .context(args.context)
+        .color(args.color)
+        .buffer_size(args.buffer_size)
+        .io_backend(args.io_backend.into())
+        .direct(args.direct)
+        .fadvise(args.fadvise)
+        .jobs(args.jobs)
+        .continue_on_error(args.continue_on_error)
+        .json_errors(args.error_format == ErrorFormatArg::Json);
+
+    if args.continue_on_error && !args.separate && !args.in_place {
+        return Err("--continue-on-error requires --separate, --recursive or --in-place".into());
+    }
+
+    if args.in_place {
+        if inputs.is_empty() || inputs.iter().any(|s| s.as_os_str() == "-") {
+            return Err("--in-place requires input files".into());
+        }
+        return slicer.slice_each_in_place(&inputs, args.backup.as_deref(), args.keep_mtime);
+    }
+
+    let stdin = [PathBuf::from("-")];
+    let paths = if inputs.is_empty() {
+        &stdin[..]
+    } else {
+        &inputs
+    };
+    if let Some(size) = args.split {
+        return slicer.split_files(paths, &args.split_prefix, size);
+    }
+
+    if let Some(map) = &args.output_map {
+        let routes = parse_output_map(map)?
+            .into_iter()
+            .map(|(range, path)| {
+                let file = File::create(&path).map_err(|e| Error::at(&path, e))?;
+                Ok((range, file))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        return slicer.slice_routed(paths, routes);
+    }
+
+    let mut out = open_output(&args)?;
+    if args.count {
+        let n = slicer.count_files(paths)?;
+        writeln!(out, "{n}")?;
+        if args.fail_empty && n == 0 {
+            return Err(Error::Empty);
+        }
+        return Ok(());
+    }
+    let follow = args.follow.or(args.follow_name.then_some(FollowArg::Name));
+    if let Some(how) = follow {
+        let [path] = inputs.as_slice() else {
+            return Err("--follow requires exactly one input file".into());
+        };
+        let interval = Duration::try_from_secs_f64(args.sleep_interval)
+            .map_err(|e| Error::Parse(format!("--sleep-interval: {e}")))?;
+        return match how {
+            FollowArg::Descriptor => slicer.follow(File::open(path)?, out, interval),
+            FollowArg::Name => slicer.follow_name(path, out, interval),
+        };
+    }
+    if let (Output::File(file), Some(path)) = (&out, &args.output)
+        && !args.separate
+    {
+        slicer.preallocate(paths, file).map_err(|e| match e {
+            Error::NotFound(e) | Error::Io(e) | Error::Data(e) => Error::at(path, e),
+            e => e,
+        })?;
+    }
+    let tee = match &args.tee {
+        Some(path) => Some(File::create(path).map_err(|e| Error::at(path, e))?),
+        None => None,
+    };
+    if args.fail_empty
+        || args.progress
+        || args.rate_limit.is_some()
+        || args.append
+        || args.report.is_some()
+    {
+        // written through here, without the sendfile(2) path that would bypass it
+        let mut inner: Box<dyn Write> = Box::new(out);
+        if args.append {
+            inner = Box::new(Appended {
+                inner,
+                end: record_end,
+                buf: Vec::new(),
+            });
+        }
+        if let Some(rate) = args.rate_limit {
+            inner = Box::new(RateLimit::new(inner, rate as u64));
+        }
+        if args.progress {
+            let total = match args.separate {
+                true => None,
+                false => slicer.output_len(paths)?,
             };
-            while i < m {
-                if let Some(c) = q.pop_front() {
-                    out.write_all(&[c])?;
-                    i += M::count(c);
-                } else {
-                    break;
-                }
+            inner = Box::new(Progress::new(inner, total));
+        }
+        let mut out = Watched {
+            inner,
+            wrote: false,
+        };
+        if let Some(tee) = tee {
+            slicer.slice_tee(paths, tee, &mut out)?;
+        } else if args.separate && !inputs.is_empty() {
+            slicer.slice_separate(paths, &mut out)?;
+        } else if let Some(ReportArg::Json) = args.report {
+            let report = slicer.slice_files_reported(paths, &mut out)?;
+            match &args.report_file {
+                Some(path) => File::create(path)
+                    .and_then(|file| report.write_json(file))
+                    .map_err(|e| Error::at(path, e))?,
+                None => report.write_json(std::io::stderr().lock())?,
             }
+        } else {
+            slicer.slice_files(paths, &mut out)?;
         }
+        let wrote = out.wrote;
+        drop(out);
+        return match wrote || !args.fail_empty {
+            true => Ok(()),
+            false => Err(Error::Empty),
+        };
+    }
+    if let Some(tee) = tee {
+        return slicer.slice_tee(paths, tee, out);
+    }
+    match inputs.as_slice() {
+        paths if args.separate && !paths.is_empty() => slicer.slice_separate(paths, out)?,
+        // stdin
+        [] => slice_files(&slicer, &stdin, out)?,
+        // files
+        paths => slice_files(&slicer, paths, out)?,
     }
 
     Ok(())
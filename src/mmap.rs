@@ -0,0 +1,310 @@
+use std::fs::File;
+
+use memchr::{memchr, memrchr};
+use memmap2::Mmap;
+
+use crate::{
+    Result,
+    index::LineIndex,
+    output::RecordSink,
+    range::{SliceIdx, SliceRange},
+    sample::{Rng, pick},
+    scan,
+    slicer::{Selection, complement_runs, merge_runs},
+    trace::trace,
+};
+
+/// Number of records ending in `delim` in `data`, the last one perhaps
+/// unterminated.
+pub(crate) fn count_records(data: &[u8], delim: u8) -> usize {
+    let unterminated = data.last().is_some_and(|&c| c != delim);
+    scan::count(data, delim) + unterminated as usize
+}
+
+/// Map `file` into memory. Empty files, which cannot be mapped, yield None.
+pub(crate) fn map(file: &File) -> std::io::Result<Option<Mmap>> {
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the map is only read, and like every tool reading a file we
+    // cannot guard against it being truncated by another process meanwhile.
+    unsafe { Mmap::map(file) }.map(Some)
+}
+
+/// Delimited records of an in-memory input, located with memchr.
+struct Records<'a> {
+    data: &'a [u8],
+    delim: u8,
+    len: Option<usize>,     // number of records, if counted
+    cursor: (usize, usize), // a record index and the offset where it starts
+    index: Option<Sidecar<'a>>,
+}
+
+/// A sidecar index of the whole file, of which the data is a suffix.
+struct Sidecar<'a> {
+    index: &'a LineIndex,
+    skip: usize, // records before the data
+    head: usize, // bytes before the data
+}
+
+impl<'a> Records<'a> {
+    fn new(data: &'a [u8], delim: u8) -> Self {
+        Self {
+            data,
+            delim,
+            len: None,
+            cursor: (0, 0),
+            index: None,
+        }
+    }
+
+    /// Look records up in `index`, given `skip` records in `head` bytes
+    /// before the data.
+    fn with_index(self, index: &'a LineIndex, skip: usize, head: usize) -> Self {
+        let index = Some(Sidecar { index, skip, head });
+        Self { index, ..self }
+    }
+
+    fn count(&mut self) -> usize {
+        let len = match &self.index {
+            Some(s) => s.index.records().saturating_sub(s.skip),
+            None => count_records(self.data, self.delim),
+        };
+        self.len = Some(len);
+        len
+    }
+
+    /// The closest known record at or before `idx`, and its offset.
+    fn start_before(&self, idx: usize) -> (usize, usize) {
+        let known = match &self.index {
+            Some(s) => match s.index.nearest(idx.saturating_add(s.skip)) {
+                (i, off) if i >= s.skip => (i - s.skip, off - s.head),
+                _ => (0, 0),
+            },
+            None => (0, 0),
+        };
+        if idx >= self.cursor.0 && self.cursor.0 >= known.0 {
+            self.cursor
+        } else {
+            known
+        }
+    }
+
+    /// Byte offset where record `idx` starts, or the end of the data if there
+    /// is no such record. Scans from the closest known record or, if closer,
+    /// the end.
+    fn offset(&mut self, idx: usize) -> usize {
+        let (i, off) = self.start_before(idx);
+        let off = match self.len {
+            // the open end of a range when the length is not counted
+            None if idx == usize::MAX => self.data.len(),
+            Some(len) if idx >= len => self.data.len(),
+            Some(len) if len - idx < idx - i => self.offset_back(len - idx),
+            _ => self.offset_from(i, off, idx),
+        };
+        self.cursor = (idx, off);
+        off
+    }
+
+    fn offset_from(&self, i: usize, off: usize, idx: usize) -> usize {
+        if idx == i {
+            return off;
+        }
+        scan::nth(&self.data[off..], self.delim, idx - i - 1)
+            .map_or(self.data.len(), |p| off + p + 1)
+    }
+
+    /// Byte offset where the `k`-th record from the end starts, or the end
+    /// of the data for 0, found by scanning back from the end.
+    fn offset_back(&self, k: usize) -> usize {
+        // delimiters from the one ending the record before to the end
+        let terminated = self.data.last() == Some(&self.delim);
+        match k + terminated as usize {
+            0 => self.data.len(),
+            k => scan::nth_back(self.data, self.delim, k - 1).map_or(0, |p| p + 1),
+        }
+    }
+
+    /// The record, including its delimiter, that ends at `end`.
+    fn record_before(&self, end: usize) -> &'a [u8] {
+        let body = &self.data[..end];
+        let body = body.strip_suffix(&[self.delim]).unwrap_or(body);
+        let start = memrchr(self.delim, body).map_or(0, |p| p + 1);
+        &self.data[start..end]
+    }
+}
+
+/// Slice delimited records of `data` by scanning for delimiters instead of
+/// reading byte by byte. Every range must have a step of 1 or -1.
+///
+/// `reversed` is the range to emit back to front, if any, and records are
+/// looked up in `index`, if given.
+pub(crate) fn slice_records(
+    data: &[u8],
+    delim: u8,
+    index: Option<&LineIndex>,
+    sel: &Selection,
+    reversed: Option<&SliceRange>,
+    out: &mut impl RecordSink,
+) -> Result<()> {
+    let Selection {
+        ranges,
+        skip,
+        invert,
+        sample,
+        seed,
+        max,
+        strict,
+        ..
+    } = *sel;
+    // a cut off input is a shorter one, of which the index knows nothing
+    let (data, index) = match max {
+        Some(m) => {
+            let mut records = Records::new(data, delim);
+            if let Some(index) = index {
+                records = records.with_index(index, 0, 0);
+            }
+            (&data[..records.offset(m)], None)
+        }
+        None => (data, index),
+    };
+    let mut records = Records::new(data, delim);
+    let head = records.offset(skip);
+    write_run(&data[..head], delim, 0, 0, out)?;
+    let mut data = &data[head..];
+    let mut head = head;
+    let mut records = Records::new(data, delim);
+    if let Some(index) = index {
+        records = records.with_index(index, skip, head);
+    }
+
+    // ranges counted only from the end reach back a number of records,
+    // which are found by scanning back, and make the rest of the data moot
+    let from_end = |i: &Option<SliceIdx>| matches!(i, Some(SliceIdx::FromEnd(_)));
+    let tail_only = ranges
+        .iter()
+        .all(|r| from_end(&r.start) && (r.end.is_none() || from_end(&r.end)));
+    if tail_only && reversed.is_none() && sample.is_none() && !invert && !strict && !out.prefixed()
+    {
+        let depth = ranges.iter().map(SliceRange::lookahead).max().unwrap_or(0);
+        let from = records.offset_back(depth);
+        trace!("found the last {depth} records from byte {}", head + from);
+        (data, head) = (&data[from..], head + from);
+        records = Records::new(data, delim);
+    }
+
+    // without indices from the end, the number of records is never needed
+    let needs_len = strict
+        || reversed.is_some()
+        || sample.is_some()
+        || ranges.iter().any(|r| r.lookahead() > 0);
+    let len = if needs_len {
+        records.count()
+    } else {
+        usize::MAX
+    };
+    if strict {
+        sel.check(len)?;
+    }
+
+    if let Some(n) = sample {
+        let runs = match reversed {
+            Some(range) => vec![range.resolve(len)],
+            None if invert => complement_runs(&merge_runs(ranges, len), len),
+            None => merge_runs(ranges, len),
+        };
+        let total = runs.iter().map(|(s, e)| e - s).sum();
+        let picks = pick(total, n, &mut Rng::new(seed));
+
+        // the picks are positions in the selection; find their records
+        let mut idxs = Vec::with_capacity(picks.len());
+        let mut run = runs.iter();
+        let (mut first, mut lo, mut hi) = (0, 0, 0); // position of lo, current run
+        for p in picks {
+            while p >= first + hi - lo {
+                first += hi - lo;
+                (lo, hi) = *run.next().unwrap();
+            }
+            idxs.push(match reversed {
+                Some(_) => hi - 1 - (p - first),
+                None => lo + (p - first),
+            });
+        }
+        if reversed.is_some() {
+            idxs.reverse();
+        }
+
+        let mut found = Vec::with_capacity(idxs.len());
+        for i in idxs {
+            found.push((i, records.offset(i), records.offset(i + 1)));
+        }
+        if reversed.is_some() {
+            found.reverse();
+        }
+        for (i, start, end) in found {
+            trace!("copying record {} at byte {}", i + skip, head + start);
+            out.begin_record(i + skip, (head + start) as u64)?;
+            out.write_all(&data[start..end])?;
+        }
+        return Ok(());
+    }
+
+    if let Some(range) = reversed {
+        let (lo, hi) = range.resolve(len);
+        let mut end = records.offset(hi);
+        trace!(
+            "copying records {} to {} backwards from byte {}",
+            lo + skip,
+            hi + skip,
+            head + end
+        );
+        for i in (lo..hi).rev() {
+            let record = records.record_before(end);
+            out.begin_record(i + skip, (head + end - record.len()) as u64)?;
+            out.write_all(record)?;
+            end -= record.len();
+        }
+        return Ok(());
+    }
+
+    let mut runs = merge_runs(ranges, len);
+    if invert {
+        runs = complement_runs(&runs, len);
+    }
+    for (s, e) in runs {
+        let start = records.offset(s);
+        let end = records.offset(e);
+        trace!("copying {} bytes from byte {}", end - start, head + start);
+        write_run(&data[start..end], delim, s + skip, head + start, out)?;
+        if end == data.len() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Write `run`, whole records starting with record `first` at input offset
+/// `offset`, announcing each one to `out` if it prefixes them.
+fn write_run(
+    run: &[u8],
+    delim: u8,
+    first: usize,
+    offset: usize,
+    out: &mut impl RecordSink,
+) -> Result<()> {
+    if !out.prefixed() {
+        out.write_all(run)?;
+        return Ok(());
+    }
+    let mut pos = 0;
+    for i in first.. {
+        if pos == run.len() {
+            break;
+        }
+        let end = memchr(delim, &run[pos..]).map_or(run.len(), |p| pos + p + 1);
+        out.begin_record(i, (offset + pos) as u64)?;
+        out.write_all(&run[pos..end])?;
+        pos = end;
+    }
+    Ok(())
+}
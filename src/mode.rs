@@ -0,0 +1,631 @@
+use memchr::{memchr, memchr2};
+use regex::bytes::Regex;
+
+use crate::{Result, jsonl::InvalidJson, range::pattern_error};
+
+/// Unit used to count positions in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Byte,
+    /// Records of the given number of bytes each, but maybe the last, such
+    /// as the sectors of a disk image.
+    Record(usize),
+    /// Records terminated by the given byte.
+    Delimited(u8),
+    /// Records terminated by the given byte sequence.
+    DelimitedStr(Vec<u8>),
+    /// Records ended by a line the pattern matches, which is part of them.
+    DelimitedRegex(Separator),
+    /// UTF-8 encoded characters (Unicode scalar values).
+    Char,
+    /// RFC 4180 CSV rows; newlines inside quoted fields do not end a row.
+    Csv,
+    /// JSON Lines; invalid lines are handled according to the policy and
+    /// the output is always valid JSON Lines.
+    Jsonl(InvalidJson),
+    /// Paragraphs separated by one or more empty lines, like awk's `RS=""`.
+    /// The separating lines belong to the paragraph before them.
+    Paragraph,
+    /// FASTA sequences: a `>` header line and the sequence lines after it.
+    Fasta,
+    /// FASTQ reads of four lines each: header, sequence, `+` and quality.
+    Fastq,
+    /// WARC records: a header block, then as many bytes as its
+    /// `Content-Length` says, however many newlines they hold, then an
+    /// empty line.
+    Warc,
+    /// Lines ended by `\n`, `\r\n` or a lone `\r`, even mixed in one input.
+    AnyLine,
+    /// Lines ended by whichever of `\n`, `\r\n` or a lone `\r` ends the
+    /// first one, and only by that.
+    AutoLine,
+}
+
+impl Mode {
+    /// Newline-terminated lines.
+    pub const LINE: Mode = Mode::Delimited(b'\n');
+
+    /// Lines terminated by `\r\n`, as written on Windows. A bare `\n` does not
+    /// end a line.
+    pub fn crlf() -> Mode {
+        Mode::DelimitedStr(b"\r\n".to_vec())
+    }
+
+    /// What the input is counted in, for messages.
+    pub(crate) fn unit(&self) -> &'static str {
+        match self {
+            Mode::Byte => "bytes",
+            Mode::Char => "characters",
+            Mode::Delimited(b'\n' | b'\r') | Mode::AnyLine | Mode::AutoLine | Mode::Jsonl(_) => {
+                "lines"
+            }
+            Mode::DelimitedStr(d) if d == b"\r\n" => "lines",
+            Mode::Csv => "rows",
+            Mode::Paragraph => "paragraphs",
+            Mode::Fasta | Mode::Fastq => "sequences",
+            Mode::Record(_)
+            | Mode::Warc
+            | Mode::Delimited(_)
+            | Mode::DelimitedStr(_)
+            | Mode::DelimitedRegex(_) => "records",
+        }
+    }
+
+    /// The size of every record, 1 for bytes, if they all have one, which
+    /// makes ranges plain byte offsets.
+    pub(crate) fn record_size(&self) -> Option<usize> {
+        match self {
+            Mode::Byte => Some(1),
+            Mode::Record(size) => Some(*size),
+            _ => None,
+        }
+    }
+
+    /// The bytes that end a record, if records have a fixed terminator.
+    pub(crate) fn terminator(&self) -> Option<&[u8]> {
+        match self {
+            Mode::Delimited(d) => Some(std::slice::from_ref(d)),
+            Mode::DelimitedStr(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Length of what ends `record`: its terminator, or for paragraphs all
+    /// the empty lines after them. 0 for an unterminated record, and for
+    /// bytes, characters and fixed-size records, which have none.
+    pub(crate) fn end_len(&self, record: &[u8]) -> usize {
+        let line = || match record {
+            [.., b'\r', b'\n'] => 2,
+            [.., b'\n' | b'\r'] => 1,
+            _ => 0,
+        };
+        match self {
+            Mode::Byte | Mode::Record(_) | Mode::Char => 0,
+            Mode::Delimited(d) => record.ends_with(&[*d]) as usize,
+            Mode::DelimitedStr(d) => d.len() * record.ends_with(d) as usize,
+            Mode::Jsonl(_) => record.ends_with(b"\n") as usize,
+            Mode::Csv
+            | Mode::DelimitedRegex(_)
+            | Mode::Fasta
+            | Mode::Fastq
+            | Mode::AnyLine
+            | Mode::AutoLine => line(),
+            Mode::Warc => {
+                let blank = record.iter().rev().take(4);
+                blank.take_while(|&&c| c == b'\n' || c == b'\r').count()
+            }
+            Mode::Paragraph => {
+                let blank = record
+                    .iter()
+                    .rev()
+                    .take_while(|&&c| c == b'\n' || c == b'\r');
+                blank.count()
+            }
+        }
+    }
+
+    /// What must follow `record` for a record written after it to be apart
+    /// from it: nothing for one that is terminated, or records without
+    /// terminators.
+    pub(crate) fn missing_end(&self, record: &[u8]) -> &[u8] {
+        let end = self.end_len(record);
+        match self {
+            Mode::Byte | Mode::Record(_) | Mode::Char => b"",
+            // an empty line ends a paragraph, and its last line may be ended
+            Mode::Paragraph if end >= 2 => b"",
+            Mode::Paragraph if end == 1 => b"\n",
+            Mode::Paragraph => b"\n\n",
+            Mode::Warc if end > 0 => b"",
+            Mode::Warc => b"\r\n\r\n",
+            _ if end > 0 => b"",
+            _ => self.terminator().unwrap_or(b"\n"),
+        }
+    }
+
+    /// Most bytes [`Mode::end_len`] can find, but paragraphs, which end in
+    /// any number of empty lines.
+    pub(crate) fn max_end_len(&self) -> usize {
+        match self {
+            Mode::DelimitedStr(d) => d.len(),
+            Mode::Warc => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// A regular expression matching the lines that end records, without their
+/// line break. Two are equal if their patterns are.
+#[derive(Debug, Clone)]
+pub struct Separator(Regex);
+
+impl Separator {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let regex = Regex::new(pattern).map_err(|e| pattern_error(pattern, e))?;
+        Ok(Self(regex))
+    }
+}
+
+impl PartialEq for Separator {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Separator {}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::LINE
+    }
+}
+
+pub(crate) trait CountMode {
+    /// Number of units completed by `c`.
+    fn count(&mut self, c: u8) -> usize;
+
+    /// Whether the current unit ended right before `c`, for boundaries that
+    /// are only known once the next byte is seen. Called before `count`.
+    #[inline]
+    fn ends_before(&mut self, _c: u8) -> bool {
+        false
+    }
+
+    /// Number of leading bytes of `buf` that neither end nor complete a unit
+    /// and leave the state as it is, so they can be skipped as a whole.
+    #[inline]
+    fn inert(&self, _buf: &[u8]) -> usize {
+        0
+    }
+}
+
+pub(crate) struct CountModeByte;
+impl CountMode for CountModeByte {
+    #[inline]
+    fn count(&mut self, _c: u8) -> usize {
+        1
+    }
+}
+
+pub(crate) struct CountModeDelim(pub u8);
+impl CountMode for CountModeDelim {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        // NOTE: with UTF-8, comparing bytes is safe for ASCII delimiters.
+        (c == self.0) as usize
+    }
+
+    #[inline]
+    fn inert(&self, buf: &[u8]) -> usize {
+        memchr(self.0, buf).unwrap_or(buf.len())
+    }
+}
+
+pub(crate) struct CountModeRecord {
+    size: usize,
+    left: usize, // bytes to the end of the record
+}
+impl CountModeRecord {
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "record size must not be 0");
+        Self { size, left: size }
+    }
+}
+impl CountMode for CountModeRecord {
+    #[inline]
+    fn count(&mut self, _c: u8) -> usize {
+        self.left -= 1;
+        if self.left == 0 {
+            self.left = self.size;
+            1
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CountModeChar {
+    remaining: u8, // continuation bytes still expected
+    bom: u8,       // bytes of a leading byte order mark seen, u8::MAX past it
+}
+impl CountModeChar {
+    const BOM: &[u8] = b"\xef\xbb\xbf";
+}
+impl CountMode for CountModeChar {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        if self.bom != u8::MAX {
+            self.bom = match Self::BOM.get(self.bom as usize) {
+                Some(&b) if b == c => self.bom + 1,
+                _ => u8::MAX,
+            };
+            // a leading byte order mark is no character, but part of the first
+            if self.bom as usize == Self::BOM.len() {
+                (self.bom, self.remaining) = (u8::MAX, 0);
+                return 0;
+            }
+        }
+        // NOTE: a truncated sequence is glued to the character that follows,
+        // and a stray continuation byte counts on its own.
+        if c & 0xC0 == 0x80 {
+            self.remaining = self.remaining.saturating_sub(1);
+            return (self.remaining == 0) as usize;
+        }
+        self.remaining = match c {
+            0xC0..0xE0 => 1,
+            0xE0..0xF0 => 2,
+            0xF0.. => 3,
+            _ => 0,
+        };
+        (self.remaining == 0) as usize
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CountModeCsv {
+    quoted: bool,
+}
+impl CountMode for CountModeCsv {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        // an escaped quote ("") toggles twice, so it needs no special case
+        match c {
+            b'"' => {
+                self.quoted = !self.quoted;
+                0
+            }
+            b'\n' => !self.quoted as usize,
+            _ => 0,
+        }
+    }
+
+    #[inline]
+    fn inert(&self, buf: &[u8]) -> usize {
+        memchr2(b'"', b'\n', buf).unwrap_or(buf.len())
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CountModeParagraph {
+    content: bool, // a non-empty line was seen in this paragraph
+    newline: bool, // the previous byte was a newline
+    blank: bool,   // inside the empty lines after the content
+}
+impl CountMode for CountModeParagraph {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        if c == b'\n' {
+            if self.newline && self.content {
+                self.blank = true;
+            }
+            self.newline = true;
+        } else {
+            self.newline = false;
+            self.content = true;
+        }
+        0
+    }
+
+    #[inline]
+    fn ends_before(&mut self, c: u8) -> bool {
+        if self.blank && c != b'\n' {
+            self.blank = false;
+            self.content = false;
+            return true;
+        }
+        false
+    }
+
+    #[inline]
+    fn inert(&self, buf: &[u8]) -> usize {
+        // only newlines matter in the middle of a non-empty line
+        if self.content && !self.newline && !self.blank {
+            memchr(b'\n', buf).unwrap_or(buf.len())
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CountModeFasta {
+    newline: bool, // the previous byte was a newline
+}
+impl CountMode for CountModeFasta {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        self.newline = c == b'\n';
+        0
+    }
+
+    #[inline]
+    fn ends_before(&mut self, c: u8) -> bool {
+        // anything before the first header is a record of its own
+        self.newline && c == b'>'
+    }
+
+    #[inline]
+    fn inert(&self, buf: &[u8]) -> usize {
+        // only the first byte of a line matters
+        if self.newline {
+            0
+        } else {
+            memchr(b'\n', buf).unwrap_or(buf.len())
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CountModeFastq {
+    lines: u8, // lines of the read so far
+}
+impl CountMode for CountModeFastq {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        if c != b'\n' {
+            return 0;
+        }
+        self.lines += 1;
+        if self.lines == 4 {
+            self.lines = 0;
+            1
+        } else {
+            0
+        }
+    }
+
+    #[inline]
+    fn inert(&self, buf: &[u8]) -> usize {
+        memchr(b'\n', buf).unwrap_or(buf.len())
+    }
+}
+
+/// Where in a WARC record the next byte is.
+#[derive(Default)]
+enum Warc {
+    #[default]
+    Header,
+    Block(u64), // bytes of it left
+    End(u8),    // bytes of the \r\n\r\n after the block seen
+}
+
+/// Longest header line kept, enough for `Content-Length`.
+const WARC_LINE: usize = 64;
+
+#[derive(Default)]
+pub(crate) struct CountModeWarc {
+    at: Warc,
+    line: Vec<u8>, // the header line so far, or its start
+    fields: bool,  // a header line was seen
+    length: u64,   // of the block
+}
+impl CountMode for CountModeWarc {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        match &mut self.at {
+            Warc::Header => {
+                if self.line.len() < WARC_LINE {
+                    self.line.push(c);
+                }
+                if c != b'\n' {
+                    return 0;
+                }
+                let line = self.line.trim_ascii();
+                if line.is_empty() {
+                    // the empty line after the fields starts the block
+                    if self.fields {
+                        self.at = match self.length {
+                            0 => Warc::End(0),
+                            n => Warc::Block(n),
+                        };
+                    }
+                } else {
+                    self.fields = true;
+                    let name = b"content-length:";
+                    if line.len() > name.len() && line[..name.len()].eq_ignore_ascii_case(name) {
+                        let value = std::str::from_utf8(&line[name.len()..]).unwrap_or_default();
+                        self.length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+                self.line.clear();
+                0
+            }
+            Warc::Block(left) => {
+                *left -= 1;
+                if *left == 0 {
+                    self.at = Warc::End(0);
+                }
+                0
+            }
+            Warc::End(seen) => {
+                *seen += 1;
+                if *seen < 4 {
+                    return 0;
+                }
+                *self = Self::default();
+                1
+            }
+        }
+    }
+
+    #[inline]
+    fn ends_before(&mut self, c: u8) -> bool {
+        // a record ended by fewer newlines than it should be
+        if matches!(self.at, Warc::End(_)) && c != b'\r' && c != b'\n' {
+            *self = Self::default();
+            return true;
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+/// Lines ended by `\n`, `\r\n` or a lone `\r`, or with `auto`, the same way
+/// as the first one.
+#[derive(Default)]
+pub(crate) struct CountModeLines {
+    auto: bool,
+    ending: Option<LineEnding>, // once the first line has set it
+    cr: bool,                   // the previous byte was a \r
+}
+impl CountModeLines {
+    pub fn new(auto: bool) -> Self {
+        Self {
+            auto,
+            ..Self::default()
+        }
+    }
+
+    fn found(&mut self, ending: LineEnding) {
+        if self.auto {
+            self.ending = Some(ending);
+        }
+    }
+}
+impl CountMode for CountModeLines {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        let cr = std::mem::replace(&mut self.cr, c == b'\r');
+        match (self.ending, c) {
+            (None, b'\n') => {
+                self.found(if cr { LineEnding::Crlf } else { LineEnding::Lf });
+                1
+            }
+            (Some(LineEnding::Lf), b'\n') | (Some(LineEnding::Cr), b'\r') => 1,
+            (Some(LineEnding::Crlf), b'\n') => cr as usize,
+            _ => 0,
+        }
+    }
+
+    #[inline]
+    fn ends_before(&mut self, c: u8) -> bool {
+        // a lone \r is only known once the next byte is seen
+        if self.ending.is_none() && self.cr && c != b'\n' {
+            self.cr = false;
+            self.found(LineEnding::Cr);
+            return true;
+        }
+        false
+    }
+
+    #[inline]
+    fn inert(&self, buf: &[u8]) -> usize {
+        match self.ending {
+            Some(LineEnding::Lf) => memchr(b'\n', buf),
+            Some(LineEnding::Cr) => memchr(b'\r', buf),
+            _ if self.cr => return 0,
+            _ => memchr2(b'\r', b'\n', buf),
+        }
+        .unwrap_or(buf.len())
+    }
+}
+
+/// Lines gathered one at a time, each ending a record if the separator
+/// matches it.
+pub(crate) struct CountModeRegex {
+    regex: Regex,
+    line: Vec<u8>,
+}
+impl CountModeRegex {
+    pub fn new(separator: &Separator) -> Self {
+        Self {
+            regex: separator.0.clone(),
+            line: Vec::new(),
+        }
+    }
+}
+impl CountMode for CountModeRegex {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        if c != b'\n' {
+            self.line.push(c);
+            return 0;
+        }
+        let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+        let end = self.regex.is_match(line);
+        self.line.clear();
+        end as usize
+    }
+}
+
+/// Streaming KMP matcher for multi-byte delimiters.
+pub(crate) struct CountModeDelimStr {
+    pat: Vec<u8>,
+    fail: Vec<usize>, // length of the longest proper border of pat[..=i]
+    matched: usize,
+}
+impl CountModeDelimStr {
+    pub fn new(pat: Vec<u8>) -> Self {
+        assert!(!pat.is_empty(), "delimiter must not be empty");
+        let mut fail = vec![0; pat.len()];
+        let mut k = 0;
+        for i in 1..pat.len() {
+            while k > 0 && pat[i] != pat[k] {
+                k = fail[k - 1];
+            }
+            if pat[i] == pat[k] {
+                k += 1;
+            }
+            fail[i] = k;
+        }
+        Self {
+            pat,
+            fail,
+            matched: 0,
+        }
+    }
+}
+impl CountMode for CountModeDelimStr {
+    #[inline]
+    fn count(&mut self, c: u8) -> usize {
+        while self.matched > 0 && self.pat[self.matched] != c {
+            self.matched = self.fail[self.matched - 1];
+        }
+        if self.pat[self.matched] == c {
+            self.matched += 1;
+        }
+        if self.matched == self.pat.len() {
+            // delimiters do not overlap
+            self.matched = 0;
+            1
+        } else {
+            0
+        }
+    }
+
+    #[inline]
+    fn inert(&self, buf: &[u8]) -> usize {
+        // with nothing matched, only the first byte of the pattern matters
+        if self.matched == 0 {
+            memchr(self.pat[0], buf).unwrap_or(buf.len())
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,747 @@
+use std::io::{BufWriter, ErrorKind, Write};
+
+use crate::{Mode, report::Strategy};
+
+/// Bytes of a long record gathered before they are written on.
+const BATCH: usize = 64 * 1024;
+
+/// How the selected records are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// As they are in the input.
+    #[default]
+    Raw,
+    /// As one JSON object per input: the source, the resolved `start` and
+    /// `end` (a record index and byte offset each), and the `records` as an
+    /// array of strings.
+    Json,
+    /// As an `xxd` style hex dump, with offsets into the input.
+    Hex,
+    /// Not at all: only where they are in the input, as one line per run of
+    /// adjacent records with its start and end byte offset, then its first
+    /// and end record index, counting from 0 with exclusive ends.
+    Offsets,
+}
+
+/// Writer that remembers the last few bytes passed through it, or with `hold`
+/// keeps them back until more output follows.
+pub(crate) struct TrackLast<W> {
+    inner: W,
+    keep: usize,
+    hold: bool,
+    tail: Vec<u8>, // up to `keep` bytes written last
+}
+
+impl<W: Write> TrackLast<W> {
+    pub fn new(inner: W, keep: usize, hold: bool) -> Self {
+        Self {
+            inner,
+            keep,
+            hold,
+            tail: Vec::with_capacity(keep),
+        }
+    }
+
+    /// Write `terminator` unless the output is empty or already ends with it.
+    /// The terminator must not be longer than the tracked tail.
+    pub fn terminate(&mut self, terminator: &[u8]) -> std::io::Result<()> {
+        debug_assert!(terminator.len() <= self.keep && !self.hold);
+        if self.tail.is_empty() || self.tail.ends_with(terminator) {
+            return Ok(());
+        }
+        self.write_all(terminator)
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Write out the held back tail, minus `terminator` if it ends with it.
+    pub fn strip(&mut self, terminator: &[u8]) -> std::io::Result<()> {
+        debug_assert!(terminator.len() <= self.keep && self.hold);
+        if self.tail.ends_with(terminator) {
+            self.tail.truncate(self.tail.len() - terminator.len());
+        }
+        self.inner.write_all(&self.tail)?;
+        self.tail.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for TrackLast<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.hold {
+            if buf.len() >= self.keep {
+                let (out, tail) = buf.split_at(buf.len() - self.keep);
+                self.inner.write_all(&self.tail)?;
+                self.inner.write_all(out)?;
+                self.tail.clear();
+                self.tail.extend_from_slice(tail);
+            } else {
+                self.tail.extend_from_slice(buf);
+                let excess = self.tail.len() - self.keep.min(self.tail.len());
+                self.inner.write_all(&self.tail[..excess])?;
+                self.tail.drain(..excess);
+            }
+            return Ok(buf.len());
+        }
+
+        let n = self.inner.write(buf)?;
+        let written = &buf[..n];
+        if written.len() >= self.keep {
+            self.tail.clear();
+            self.tail.extend_from_slice(&written[n - self.keep..]);
+        } else {
+            let excess = (self.tail.len() + n).saturating_sub(self.keep);
+            self.tail.drain(..excess);
+            self.tail.extend_from_slice(written);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// What to do with output that is not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+    /// Write U+FFFD in place of each invalid sequence.
+    Replace,
+    /// Fail at the first invalid byte.
+    Error,
+}
+
+/// Writer checking that everything written through it is valid UTF-8, if
+/// asked to. A sequence split across writes is held back until it is
+/// complete, so [`Utf8Check::finish`] must be called at the end.
+pub(crate) struct Utf8Check<W> {
+    inner: W,
+    policy: Option<InvalidUtf8>,
+    partial: Vec<u8>, // start of a sequence the last write ended in
+    pos: u64,         // bytes checked so far
+}
+
+impl<W: Write> Utf8Check<W> {
+    pub fn new(inner: W, policy: Option<InvalidUtf8>) -> Self {
+        Self {
+            inner,
+            policy,
+            partial: Vec::with_capacity(4),
+            pos: 0,
+        }
+    }
+
+    /// Deal with a sequence left incomplete at the end of the output.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if self.partial.is_empty() {
+            return Ok(());
+        }
+        self.partial.clear();
+        self.invalid()
+    }
+
+    fn invalid(&mut self) -> std::io::Result<()> {
+        match self.policy {
+            Some(InvalidUtf8::Replace) => self.inner.write_all("\u{FFFD}".as_bytes()),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid UTF-8 in the output at byte {}", self.pos),
+            )),
+        }
+    }
+
+    /// Write out the valid start of `buf`, holding back a sequence it ends
+    /// in, and return how much of it was taken. When failing, that stops at
+    /// an invalid sequence unless it is at the start, like a short write.
+    fn check(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        loop {
+            let e = match std::str::from_utf8(rest) {
+                Ok(_) => {
+                    self.inner.write_all(rest)?;
+                    self.pos += rest.len() as u64;
+                    return Ok(buf.len());
+                }
+                Err(e) => e,
+            };
+            let (valid, bad) = rest.split_at(e.valid_up_to());
+            self.inner.write_all(valid)?;
+            self.pos += valid.len() as u64;
+            let Some(n) = e.error_len() else {
+                self.partial.extend_from_slice(bad);
+                return Ok(buf.len());
+            };
+            let taken = buf.len() - bad.len();
+            if self.policy == Some(InvalidUtf8::Error) && taken > 0 {
+                return Ok(taken);
+            }
+            self.invalid()?;
+            self.pos += n as u64;
+            rest = &bad[n..];
+        }
+    }
+}
+
+impl<W: Write> Write for Utf8Check<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.policy.is_none() {
+            return self.inner.write(buf);
+        }
+        if self.partial.is_empty() {
+            return self.check(buf);
+        }
+
+        // finish the held back sequence, which needs at most 3 more bytes
+        let mut head = std::mem::take(&mut self.partial);
+        let held = head.len();
+        head.extend_from_slice(&buf[..buf.len().min(4 - held)]);
+        let end = match std::str::from_utf8(&head) {
+            Ok(_) => head.len(),
+            Err(e) if e.valid_up_to() > 0 => e.valid_up_to(),
+            Err(e) => match e.error_len() {
+                Some(n) => {
+                    self.invalid()?;
+                    self.pos += n as u64;
+                    let taken = n - held;
+                    return Ok(taken + self.check(&buf[taken..])?);
+                }
+                None => {
+                    self.partial = head;
+                    return Ok(buf.len());
+                }
+            },
+        };
+        self.inner.write_all(&head[..end])?;
+        self.pos += end as u64;
+        Ok(end - held)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Output that can be told where each emitted record starts.
+pub(crate) trait RecordSink: Write {
+    /// Whether [`RecordSink::begin_record`] does anything.
+    fn prefixed(&self) -> bool {
+        false
+    }
+
+    /// Record `i` of the input, counting from 0 and starting at byte
+    /// `offset`, is written next.
+    fn begin_record(&mut self, _i: usize, _offset: u64) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// The input is read this way from now on.
+    fn read_by(&mut self, _how: Strategy) {}
+}
+
+impl<W: Write> RecordSink for BufWriter<W> {}
+
+/// Writer prefixing each record with its number and/or byte offset in the
+/// input, like `grep -n -b`.
+pub(crate) struct Prefixed<W> {
+    inner: W,
+    number: bool,
+    offset: bool,
+}
+
+impl<W: Write> Prefixed<W> {
+    pub fn new(inner: W, number: bool, offset: bool) -> Self {
+        Self {
+            inner,
+            number,
+            offset,
+        }
+    }
+}
+
+impl<W: Write> Write for Prefixed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> RecordSink for Prefixed<W> {
+    fn prefixed(&self) -> bool {
+        true
+    }
+
+    fn begin_record(&mut self, i: usize, offset: u64) -> std::io::Result<()> {
+        if self.number {
+            write!(self.inner, "{}:", i + 1)?;
+        }
+        if self.offset {
+            write!(self.inner, "{offset}:")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sink writing each record with its terminator replaced by `delim`. The end
+/// of a record is held back until the next one begins, or the output is
+/// finished, as only then it is known to end it.
+pub(crate) struct Redelimited<S> {
+    inner: S,
+    mode: Mode,
+    delim: Vec<u8>,
+    pending: Vec<u8>, // end of the record being written
+}
+
+impl<S: RecordSink> Redelimited<S> {
+    pub fn new(inner: S, mode: Mode, delim: Vec<u8>) -> Self {
+        Self {
+            inner,
+            mode,
+            delim,
+            pending: Vec::new(),
+        }
+    }
+
+    fn end_record(&mut self) -> std::io::Result<()> {
+        let end = self.mode.end_len(&self.pending);
+        self.inner
+            .write_all(&self.pending[..self.pending.len() - end])?;
+        if end > 0 {
+            self.inner.write_all(&self.delim)?;
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Write out the end of the last record.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.end_record()
+    }
+}
+
+impl<S: RecordSink> Write for Redelimited<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        // only what could still be part of the terminator is kept
+        let keep = self
+            .mode
+            .max_end_len()
+            .max(self.mode.end_len(&self.pending));
+        if self.pending.len() > keep + BATCH {
+            let done = self.pending.len() - keep;
+            self.inner.write_all(&self.pending[..done])?;
+            self.pending.drain(..done);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: RecordSink> RecordSink for Redelimited<S> {
+    fn prefixed(&self) -> bool {
+        true
+    }
+
+    fn begin_record(&mut self, i: usize, offset: u64) -> std::io::Result<()> {
+        self.end_record()?;
+        self.inner.begin_record(i, offset)
+    }
+}
+
+/// Sink counting what is written to it instead of keeping it: records, or
+/// just bytes.
+pub(crate) struct Counter {
+    records: bool,
+    count: u64,
+}
+
+impl Counter {
+    pub fn new(records: bool) -> Self {
+        Self { records, count: 0 }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.records {
+            self.count += buf.len() as u64;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl RecordSink for Counter {
+    fn prefixed(&self) -> bool {
+        self.records
+    }
+
+    fn begin_record(&mut self, _i: usize, _offset: u64) -> std::io::Result<()> {
+        self.count += self.records as u64;
+        Ok(())
+    }
+}
+
+/// Writer collecting records into a JSON envelope, written one record at a
+/// time and completed by [`JsonRecords::finish`].
+pub(crate) struct JsonRecords<W: Write> {
+    inner: W,
+    record: Vec<u8>,           // bytes of the record being written
+    cur: Option<(usize, u64)>, // its index and offset
+    count: usize,
+    first: Option<(usize, u64)>, // the earliest record written
+    last: Option<(usize, u64)>,  // the end of the latest one
+}
+
+impl<W: Write> JsonRecords<W> {
+    pub fn new(mut inner: W, source: &[String]) -> std::io::Result<Self> {
+        inner.write_all(br#"{"source":"#)?;
+        match source {
+            [] => inner.write_all(b"null")?,
+            [s] => serde_json::to_writer(&mut inner, s)?,
+            s => serde_json::to_writer(&mut inner, s)?,
+        }
+        inner.write_all(br#","records":["#)?;
+        Ok(Self {
+            inner,
+            record: Vec::new(),
+            cur: None,
+            count: 0,
+            first: None,
+            last: None,
+        })
+    }
+
+    fn end_record(&mut self) -> std::io::Result<()> {
+        let Some((i, offset)) = self.cur.take() else {
+            return Ok(());
+        };
+        if self.count > 0 {
+            self.inner.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.inner, &String::from_utf8_lossy(&self.record))?;
+        self.count += 1;
+
+        // records are written in reverse for a negative step
+        let (start, end) = ((i, offset), (i + 1, offset + self.record.len() as u64));
+        self.first = Some(self.first.map_or(start, |f| f.min(start)));
+        self.last = self.last.max(Some(end));
+        self.record.clear();
+        Ok(())
+    }
+
+    /// Close the records array and write the resolved bounds.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.end_record()?;
+        let bound = |b: Option<(usize, u64)>| match b {
+            Some((i, offset)) => format!(r#"{{"record":{i},"byte":{offset}}}"#),
+            None => "null".to_owned(),
+        };
+        let (start, end) = (bound(self.first), bound(self.last));
+        writeln!(self.inner, r#"],"start":{start},"end":{end}}}"#)
+    }
+}
+
+impl<W: Write> Write for JsonRecords<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.record.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> RecordSink for JsonRecords<W> {
+    fn prefixed(&self) -> bool {
+        true
+    }
+
+    fn begin_record(&mut self, i: usize, offset: u64) -> std::io::Result<()> {
+        self.end_record()?;
+        self.cur = Some((i, offset));
+        Ok(())
+    }
+}
+
+/// Bytes on one line of a hex dump.
+const HEX_WIDTH: usize = 16;
+
+/// Writer rendering bytes as an `xxd` style dump: up to 16 bytes per line,
+/// in hex and as ASCII, after the input offset of the first. A line ends
+/// early where the input skips ahead.
+pub(crate) struct HexDump<W> {
+    inner: W,
+    line: Vec<u8>, // bytes of the line being filled
+    offset: u64,   // input offset of its first byte
+}
+
+impl<W: Write> HexDump<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            line: Vec::with_capacity(HEX_WIDTH),
+            offset: 0,
+        }
+    }
+
+    fn write_line(&mut self) -> std::io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let mut text = Vec::with_capacity(80);
+        write!(text, "{:08x}:", self.offset)?;
+        for (j, b) in self.line.iter().enumerate() {
+            if j % 2 == 0 {
+                text.push(b' ');
+            }
+            write!(text, "{b:02x}")?;
+        }
+        // hex columns as wide as a full line, then two spaces
+        let hex = 2 * HEX_WIDTH + HEX_WIDTH / 2;
+        let pad = hex - 2 * self.line.len() - self.line.len().div_ceil(2) + 2;
+        text.resize(text.len() + pad, b' ');
+        text.extend(self.line.iter().map(|&b| match b {
+            0x20..=0x7e => b,
+            _ => b'.',
+        }));
+        text.push(b'\n');
+        self.inner.write_all(&text)?;
+
+        self.offset += self.line.len() as u64;
+        self.line.clear();
+        Ok(())
+    }
+
+    /// Continue the dump at input offset `offset`.
+    fn seek(&mut self, offset: u64) -> std::io::Result<()> {
+        if offset != self.offset + self.line.len() as u64 {
+            self.write_line()?;
+            self.offset = offset;
+        }
+        Ok(())
+    }
+
+    /// Write out the last, partial line.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.write_line()
+    }
+}
+
+impl<W: Write> Write for HexDump<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let n = rest.len().min(HEX_WIDTH - self.line.len());
+            self.line.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            if self.line.len() == HEX_WIDTH {
+                self.write_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> RecordSink for HexDump<W> {
+    fn prefixed(&self) -> bool {
+        true
+    }
+
+    fn begin_record(&mut self, _i: usize, offset: u64) -> std::io::Result<()> {
+        self.seek(offset)
+    }
+}
+
+/// Extent of adjacent records in the input.
+#[derive(Clone, Copy)]
+struct Extent {
+    bytes: (u64, u64),
+    records: (usize, usize),
+}
+
+/// Writer printing where the records written to it are instead of what they
+/// contain, merging adjacent ones into a single `START END FIRST LAST` line.
+/// In byte mode, where records are bytes, a record may be written as a whole
+/// run of them.
+pub(crate) struct Offsets<W> {
+    inner: W,
+    bytes: bool,
+    run: Option<Extent>, // the run being gathered
+    cur: Option<Extent>, // the record being written
+}
+
+impl<W: Write> Offsets<W> {
+    pub fn new(inner: W, bytes: bool) -> Self {
+        Self {
+            inner,
+            bytes,
+            run: None,
+            cur: None,
+        }
+    }
+
+    /// Add the record being written to the run, or start a new run with it.
+    fn end_record(&mut self) -> std::io::Result<()> {
+        let Some(mut cur) = self.cur.take() else {
+            return Ok(());
+        };
+        if self.bytes {
+            cur.records = (cur.bytes.0 as usize, cur.bytes.1 as usize);
+        }
+        match &mut self.run {
+            Some(run) if run.bytes.1 == cur.bytes.0 && run.records.1 == cur.records.0 => {
+                (run.bytes.1, run.records.1) = (cur.bytes.1, cur.records.1);
+            }
+            // records emitted back to front
+            Some(run) if cur.bytes.1 == run.bytes.0 && cur.records.1 == run.records.0 => {
+                (run.bytes.0, run.records.0) = (cur.bytes.0, cur.records.0);
+            }
+            _ => {
+                self.write_run()?;
+                self.run = Some(cur);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_run(&mut self) -> std::io::Result<()> {
+        if let Some(Extent { bytes, records }) = self.run.take() {
+            writeln!(
+                self.inner,
+                "{} {} {} {}",
+                bytes.0, bytes.1, records.0, records.1
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write out the last run.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.end_record()?;
+        self.write_run()
+    }
+}
+
+impl<W: Write> Write for Offsets<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(cur) = &mut self.cur {
+            cur.bytes.1 += buf.len() as u64;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> RecordSink for Offsets<W> {
+    fn prefixed(&self) -> bool {
+        true
+    }
+
+    fn begin_record(&mut self, i: usize, offset: u64) -> std::io::Result<()> {
+        self.end_record()?;
+        self.cur = Some(Extent {
+            bytes: (offset, offset),
+            records: (i, i + 1),
+        });
+        Ok(())
+    }
+}
+
+/// The sink records are written to, chosen by the slicer's options.
+pub(crate) enum Sink<'a, W: Write> {
+    Plain(&'a mut W),
+    Prefixed(Prefixed<&'a mut W>),
+    Redelimited(Redelimited<Prefixed<&'a mut W>>),
+    Json(JsonRecords<&'a mut W>),
+    Hex(HexDump<&'a mut W>),
+    Offsets(Offsets<&'a mut W>),
+}
+
+impl<W: Write> Sink<'_, W> {
+    /// Complete the output, which only JSON, hex dumps, offsets and a new
+    /// delimiter need.
+    pub fn finish(self) -> std::io::Result<()> {
+        match self {
+            Sink::Json(json) => json.finish(),
+            Sink::Hex(hex) => hex.finish(),
+            Sink::Offsets(offsets) => offsets.finish(),
+            Sink::Redelimited(w) => w.finish(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for Sink<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::Plain(w) => w.write(buf),
+            Sink::Prefixed(w) => w.write(buf),
+            Sink::Redelimited(w) => w.write(buf),
+            Sink::Json(w) => w.write(buf),
+            Sink::Hex(w) => w.write(buf),
+            Sink::Offsets(w) => w.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(w) => w.write_all(buf),
+            Sink::Prefixed(w) => w.write_all(buf),
+            Sink::Redelimited(w) => w.write_all(buf),
+            Sink::Json(w) => w.write_all(buf),
+            Sink::Hex(w) => w.write_all(buf),
+            Sink::Offsets(w) => w.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(w) => w.flush(),
+            Sink::Prefixed(w) => w.flush(),
+            Sink::Redelimited(w) => w.flush(),
+            Sink::Json(w) => w.flush(),
+            Sink::Hex(w) => w.flush(),
+            Sink::Offsets(w) => w.flush(),
+        }
+    }
+}
+
+impl<W: Write> RecordSink for Sink<'_, W> {
+    fn prefixed(&self) -> bool {
+        !matches!(self, Sink::Plain(_))
+    }
+
+    fn begin_record(&mut self, i: usize, offset: u64) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(_) => Ok(()),
+            Sink::Prefixed(w) => w.begin_record(i, offset),
+            Sink::Redelimited(w) => w.begin_record(i, offset),
+            Sink::Json(w) => w.begin_record(i, offset),
+            Sink::Hex(w) => w.begin_record(i, offset),
+            Sink::Offsets(w) => w.begin_record(i, offset),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::Path,
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+};
+
+use crate::{Result, Slicer, batch::Failures};
+
+/// Files sliced ahead of the one being written, per thread, which bounds how
+/// much output is held in memory.
+const AHEAD: usize = 2;
+
+impl Slicer {
+    /// [`Slicer::slice_separate`] on [`Slicer::jobs`] threads. Each file is
+    /// sliced into memory, and written out once those before it are.
+    pub(crate) fn slice_parallel(
+        &self,
+        paths: &[impl AsRef<Path> + Sync],
+        mut out: impl Write,
+    ) -> Result<()> {
+        let next = AtomicUsize::new(0);
+        // files written so far, or usize::MAX once writing stopped
+        let written = Mutex::new(0usize);
+        let moved = Condvar::new();
+        let window = self.jobs * AHEAD;
+        std::thread::scope(|s| {
+            let (tx, rx) = mpsc::channel();
+            for _ in 0..self.jobs.min(paths.len()) {
+                let tx = tx.clone();
+                let (next, written, moved) = (&next, &written, &moved);
+                s.spawn(move || {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(i) else {
+                            break;
+                        };
+                        let done = moved
+                            .wait_while(written.lock().unwrap(), |w| i >= w.saturating_add(window))
+                            .unwrap();
+                        if *done == usize::MAX {
+                            break;
+                        }
+                        drop(done);
+                        let mut buf = Vec::new();
+                        let res = self.slice_banner(i, path.as_ref(), &mut buf);
+                        if tx.send((i, buf, res)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            let mut write = || -> Result<()> {
+                let mut ready = BTreeMap::new();
+                let mut failures = Failures::new(self.continue_on_error, self.json_errors);
+                for (i, path) in paths.iter().enumerate() {
+                    while !ready.contains_key(&i) {
+                        let (j, buf, res) = rx.recv().map_err(|e| e.to_string())?;
+                        ready.insert(j, (buf, res));
+                    }
+                    // what came before an error goes out, as it would serially
+                    let (buf, res) = ready.remove(&i).unwrap();
+                    out.write_all(&buf)?;
+                    failures.check(path.as_ref(), res)?;
+                    *written.lock().unwrap() = i + 1;
+                    moved.notify_all();
+                }
+                out.flush()?;
+                failures.finish(paths.len())
+            };
+            let res = write();
+            // let waiting threads go on a failure
+            *written.lock().unwrap() = usize::MAX;
+            moved.notify_all();
+            res
+        })
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    fs::{File, FileType, Metadata, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// How byte ranges of a single file are moved to a descriptor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IoBackend {
+    /// `sendfile(2)` on Linux, plain reads and writes elsewhere.
+    #[default]
+    Std,
+    /// io_uring on Linux 5.6 and later, reading ahead while writing; other
+    /// systems use [`IoBackend::Std`].
+    Uring,
+}
+
+/// Whether a file of this type might be seekable. Rules out pipes and
+/// sockets, which are best not opened just to find out, as that can block.
+pub(crate) fn may_seek(ftype: FileType) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        !ftype.is_dir() && !ftype.is_fifo() && !ftype.is_socket()
+    }
+    #[cfg(not(unix))]
+    {
+        ftype.is_file()
+    }
+}
+
+/// Whether `file` can be read at arbitrary offsets, found by seeking to its
+/// end and back. An end at 0 is only believed if nothing can be read there:
+/// devices such as `/dev/zero` and files in `/proc` report it, though they
+/// are not empty.
+pub(crate) fn is_seekable(file: &File) -> bool {
+    let Ok(meta) = file.metadata() else {
+        return false;
+    };
+    if !may_seek(meta.file_type()) {
+        return false;
+    }
+    let mut file = file;
+    let Ok(pos) = file.stream_position() else {
+        return false;
+    };
+    let sized = match file.seek(SeekFrom::End(0)) {
+        Ok(0) => file.read(&mut [0]).is_ok_and(|n| n == 0),
+        end => end.is_ok(),
+    };
+    file.seek(SeekFrom::Start(pos)).is_ok() && sized
+}
+
+/// A new file in the temporary directory, gone once it is closed: on Unix
+/// it is removed right away, on Windows when its handle closes.
+pub(crate) fn temp_file() -> std::io::Result<File> {
+    let dir = std::env::temp_dir();
+    for n in 0.. {
+        let path = dir.join(format!(".slice-{}-{}", std::process::id(), n));
+        let mut opts = OpenOptions::new();
+        opts.read(true).write(true).create_new(true);
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+            const FILE_FLAG_DELETE_ON_CLOSE: u32 = 0x0400_0000;
+            opts.custom_flags(FILE_FLAG_DELETE_ON_CLOSE);
+        }
+        match opts.open(&path) {
+            Ok(file) => {
+                #[cfg(unix)]
+                std::fs::remove_file(&path)?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+/// Stdin as a file of its own, to see whether it was redirected from one.
+#[cfg(unix)]
+pub(crate) fn stdin_file() -> Option<File> {
+    use std::os::fd::AsFd;
+    let fd = std::io::stdin().as_fd().try_clone_to_owned().ok()?;
+    Some(File::from(fd))
+}
+
+/// Stdin as a file of its own, to see whether it was redirected from one.
+#[cfg(windows)]
+pub(crate) fn stdin_file() -> Option<File> {
+    use std::os::windows::io::AsHandle;
+    let handle = std::io::stdin().as_handle().try_clone_to_owned().ok()?;
+    Some(File::from(handle))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn stdin_file() -> Option<File> {
+    None
+}
+
+/// Identity of a file that survives renames but not replacement.
+#[cfg(unix)]
+pub(crate) fn file_id(meta: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+
+/// Identity of a file that survives renames but not replacement. Without
+/// inode numbers the creation time stands in, which a replacing file created
+/// within the same clock tick would share.
+#[cfg(not(unix))]
+pub(crate) fn file_id(meta: &Metadata) -> (u64, u64) {
+    let created = meta
+        .created()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    (created.as_secs(), created.subsec_nanos() as u64)
+}
+
+/// Open `path` with `opts` so that reads and writes bypass the page cache:
+/// with `O_DIRECT` on Linux, which needs aligned buffers, offsets and
+/// lengths, and `F_NOCACHE` on macOS, which does not.
+#[cfg(target_os = "linux")]
+pub(crate) fn open_direct(opts: &mut OpenOptions, path: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    opts.custom_flags(libc::O_DIRECT)
+        .open(path)
+        .map_err(|e| match e.raw_os_error() {
+            Some(libc::EINVAL) => std::io::Error::other("Direct IO is not supported here"),
+            _ => e,
+        })
+}
+
+/// Open `path` with `opts` so that reads and writes bypass the page cache:
+/// with `O_DIRECT` on Linux, which needs aligned buffers, offsets and
+/// lengths, and `F_NOCACHE` on macOS, which does not.
+#[cfg(target_os = "macos")]
+pub(crate) fn open_direct(opts: &mut OpenOptions, path: &Path) -> std::io::Result<File> {
+    use std::os::fd::AsRawFd;
+    let file = opts.open(path)?;
+    // SAFETY: the descriptor is open for the whole call
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn open_direct(_opts: &mut OpenOptions, _path: &Path) -> std::io::Result<File> {
+    Err(std::io::Error::new(
+        ErrorKind::Unsupported,
+        "Direct IO is only supported on Linux and macOS",
+    ))
+}
+
+/// Go back to cached IO on a file opened by [`open_direct`], to write an
+/// unaligned tail.
+#[cfg(target_os = "linux")]
+pub(crate) fn end_direct(file: &File) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let fd = file.as_raw_fd();
+    // SAFETY: the descriptor is open for the whole call
+    let ok = unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        flags >= 0 && libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) >= 0
+    };
+    match ok {
+        true => Ok(()),
+        false => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Go back to cached IO on a file opened by [`open_direct`], to write an
+/// unaligned tail.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn end_direct(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// A hint about how a file is read.
+#[derive(Clone, Copy)]
+pub(crate) enum Advice {
+    Sequential,
+    DontNeed,
+}
+
+/// Pass `advice` about the `len` bytes of `file` from `offset`, or the rest
+/// of it for 0, to the kernel, if it takes such hints. They are only hints,
+/// so failures are ignored.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub(crate) fn advise(file: &File, offset: u64, len: u64, advice: Advice) {
+    use std::os::fd::AsRawFd;
+    let advice = match advice {
+        Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+        Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+    };
+    // SAFETY: the descriptor is open for the whole call
+    unsafe {
+        libc::posix_fadvise(
+            file.as_raw_fd(),
+            offset as libc::off_t,
+            len as libc::off_t,
+            advice,
+        )
+    };
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+pub(crate) fn advise(_file: &File, _offset: u64, _len: u64, _advice: Advice) {}
+
+/// Reserve `len` bytes of disk past the end of `file` without changing its
+/// size, so that writing them cannot run out of space halfway and they are
+/// laid out in one piece. Filesystems that cannot do it are left alone.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let end = file.metadata()?.len();
+    // SAFETY: the descriptor is open for the whole call
+    let r = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_KEEP_SIZE,
+            end as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if r < 0 {
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() == Some(libc::ENOSPC) {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_vendor = "apple")]
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // counted from the end of the file
+    let mut store = libc::fstore_t {
+        fst_flags: libc::F_ALLOCATEALL,
+        fst_posmode: libc::F_PEOFPOSMODE,
+        fst_offset: 0,
+        fst_length: len as libc::off_t,
+        fst_bytesalloc: 0,
+    };
+    // SAFETY: the descriptor is open and store lives for the whole call
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_PREALLOCATE, &mut store) } < 0 {
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() == Some(libc::ENOSPC) {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// elsewhere space cannot be reserved without growing the file
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+pub(crate) fn preallocate(_file: &File, _len: u64) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Copy the extended attributes of `from` to `to`, as far as the filesystem
+/// and privileges allow; the rest are left behind.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn copy_xattrs(from: &File, to: &File) {
+    use std::os::fd::AsRawFd;
+    let (src, dst) = (from.as_raw_fd(), to.as_raw_fd());
+    // SAFETY: the descriptor is open and the buffer as long as given
+    let Ok(names) = xattr_value(|buf, len| unsafe { libc::flistxattr(src, buf.cast(), len) })
+    else {
+        return;
+    };
+    for name in names.split(|&c| c == 0).filter(|n| !n.is_empty()) {
+        let Ok(name) = std::ffi::CString::new(name) else {
+            continue;
+        };
+        // SAFETY: as above, and the name is NUL-terminated
+        let value =
+            xattr_value(|buf, len| unsafe { libc::fgetxattr(src, name.as_ptr(), buf.cast(), len) });
+        if let Ok(value) = value {
+            // SAFETY: as above
+            unsafe { libc::fsetxattr(dst, name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        }
+    }
+}
+
+/// The bytes `call` fills a buffer of the given size with, asking for the
+/// size first, and again if it grew in between.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn xattr_value(mut call: impl FnMut(*mut u8, usize) -> isize) -> std::io::Result<Vec<u8>> {
+    loop {
+        let len = call(std::ptr::null_mut(), 0);
+        if len < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut buf = vec![0; len as usize];
+        let n = call(buf.as_mut_ptr(), buf.len());
+        if n >= 0 {
+            buf.truncate(n as usize);
+            return Ok(buf);
+        }
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::ERANGE) {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn copy_xattrs(_from: &File, _to: &File) {}
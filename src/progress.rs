@@ -0,0 +1,143 @@
+use std::{
+    io::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// Time between redraws of the progress line.
+const TICK: Duration = Duration::from_millis(200);
+
+/// Cells of the bar drawn when the total is known.
+const BAR: usize = 24;
+
+/// Writer counting the bytes written through it, with a progress line on
+/// stderr redrawn a few times a second, like pv: a bar, the throughput and
+/// the time left if the total is known, a spinner and a byte count if not.
+/// The line is drawn one last time when it is dropped.
+pub struct Progress<W> {
+    inner: W,
+    done: Arc<AtomicU64>,
+    meter: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl<W: Write> Progress<W> {
+    /// Count the bytes written to `inner`, out of `total` if known.
+    pub fn new(inner: W, total: Option<u64>) -> Self {
+        let done = Arc::new(AtomicU64::new(0));
+        let (stop, stopped) = mpsc::channel();
+        let count = Arc::clone(&done);
+        let meter = std::thread::spawn(move || {
+            let start = Instant::now();
+            for tick in 0.. {
+                let last = !matches!(stopped.recv_timeout(TICK), Err(RecvTimeoutError::Timeout));
+                let line = render(
+                    count.load(Ordering::Relaxed),
+                    total,
+                    start.elapsed(),
+                    tick,
+                    last,
+                );
+                let mut err = std::io::stderr().lock();
+                // like pv, the meter never fails the copy
+                let _ = write!(err, "\r{line}\x1b[K{}", if last { "\n" } else { "" });
+                if last {
+                    break;
+                }
+            }
+        });
+        Self {
+            inner,
+            done,
+            meter: Some((stop, meter)),
+        }
+    }
+}
+
+impl<W: Write> Write for Progress<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.done.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> Drop for Progress<W> {
+    fn drop(&mut self) {
+        if let Some((stop, meter)) = self.meter.take() {
+            let _ = stop.send(());
+            let _ = meter.join();
+        }
+    }
+}
+
+/// The progress line after `done` bytes in `elapsed`; the last one shows the
+/// time taken instead of the time left.
+fn render(done: u64, total: Option<u64>, elapsed: Duration, tick: usize, last: bool) -> String {
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { done as f64 / secs } else { 0.0 };
+    let rate = format!("{}/s", human(rate as u64));
+    let Some(total) = total else {
+        let spin = if last {
+            ' '
+        } else {
+            ['|', '/', '-', '\\'][tick % 4]
+        };
+        return format!("{spin} {} {rate} {}", human(done), clock(elapsed));
+    };
+    let frac = if total == 0 {
+        1.0
+    } else {
+        (done as f64 / total as f64).min(1.0)
+    };
+    let full = (frac * BAR as f64) as usize;
+    let bar = match BAR - full {
+        0 => "=".repeat(BAR),
+        rest => format!("{}>{}", "=".repeat(full), " ".repeat(rest - 1)),
+    };
+    let time = if last || done >= total {
+        clock(elapsed)
+    } else if done > 0 {
+        let left = (total - done) as f64 * secs / done as f64;
+        format!("ETA {}", clock(Duration::from_secs_f64(left)))
+    } else {
+        "ETA -:--".to_owned()
+    };
+    format!(
+        "{} / {} [{bar}] {:>3}% {rate} {time}",
+        human(done),
+        human(total),
+        (frac * 100.0) as u32,
+    )
+}
+
+/// `n` bytes in binary units, e.g. 1.5MiB.
+fn human(n: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let (mut v, mut unit) = (n as f64, 0);
+    while v >= 1024.0 && unit < UNITS.len() - 1 {
+        v /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{n}B"),
+        _ => format!("{v:.1}{}", UNITS[unit]),
+    }
+}
+
+/// A duration as m:ss, or h:mm:ss from an hour on.
+fn clock(d: Duration) -> String {
+    let s = d.as_secs();
+    match s / 3600 {
+        0 => format!("{}:{:02}", s / 60, s % 60),
+        h => format!("{h}:{:02}:{:02}", s / 60 % 60, s % 60),
+    }
+}
@@ -0,0 +1,498 @@
+use std::{fmt, str::FromStr};
+
+use regex::bytes::Regex;
+
+use crate::Error;
+
+/// An index counted from either end of the input, or as a share of it, or
+/// found by searching it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SliceIdx {
+    FromStart(usize),
+    FromEnd(usize),
+    /// A percentage of the length, from 0 to 100, rounded down.
+    Percent(u8),
+    /// The first record matching a pattern, see [`Anchor`].
+    Match(Anchor),
+}
+
+/// A regular expression locating a range bound: the first record, without
+/// its terminator, that it matches, or the last one with
+/// [`crate::Slicer::last_match`], moved by a number of records. An end is
+/// looked for from the start on. Anchors are resolved by reading the input
+/// once before slicing it, and one that matches nowhere stands for the end
+/// of the input.
+#[derive(Debug, Clone)]
+pub struct Anchor {
+    regex: Regex,
+    offset: isize,
+}
+
+impl Anchor {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let regex = Regex::new(pattern)?;
+        Ok(Self { regex, offset: 0 })
+    }
+
+    /// The same pattern, moved `offset` more records towards the end.
+    pub fn shifted(self, offset: isize) -> Self {
+        let offset = self.offset.saturating_add(offset);
+        Self { offset, ..self }
+    }
+
+    pub(crate) fn is_match(&self, record: &[u8]) -> bool {
+        self.regex.is_match(record)
+    }
+
+    /// The bound for a match in record `i`.
+    pub(crate) fn at(&self, i: usize) -> usize {
+        i.saturating_add_signed(self.offset)
+    }
+}
+
+impl PartialEq for Anchor {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str() && self.offset == other.offset
+    }
+}
+
+impl Eq for Anchor {}
+
+impl fmt::Display for Anchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}/", self.regex.as_str().replace('/', "\\/"))?;
+        match self.offset {
+            0 => Ok(()),
+            n if n > 0 => write!(f, "+{n}"),
+            n => write!(f, "{n}"),
+        }
+    }
+}
+
+/// Split `s` at up to `n - 1` occurrences of `sep` that are not inside a
+/// `/pattern/` starting one of the parts.
+fn split_outside_patterns(s: &str, sep: char, n: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut in_pattern, mut escaped) = (0, false, false);
+    for (i, c) in s.char_indices() {
+        if parts.len() + 1 == n {
+            break;
+        }
+        if in_pattern {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '/' => in_pattern = false,
+                _ => (),
+            }
+        } else if c == '/' && i == start {
+            in_pattern = true;
+        } else if c == sep {
+            parts.push(&s[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// The error for a `pattern` that does not compile.
+pub(crate) fn pattern_error(pattern: &str, e: regex::Error) -> Error {
+    // the last line of a syntax error says what is wrong
+    let e = e.to_string();
+    let msg = e.lines().last().unwrap_or_default();
+    let msg = msg.strip_prefix("error: ").unwrap_or(msg);
+    Error::Parse(format!("Invalid pattern {pattern}: {msg}"))
+}
+
+/// Parse `/pattern/`, where `\/` stands for a slash in the pattern, with an
+/// optional `+N` or `-N` offset after it.
+fn parse_anchor(s: &str) -> Result<Anchor, Error> {
+    let body = &s[1..];
+    let mut pattern = String::with_capacity(body.len());
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '/' => {
+                let anchor = Anchor::new(&pattern).map_err(|e| pattern_error(s, e))?;
+                let offset = match &body[i + 1..] {
+                    "" => 0,
+                    rest if rest.starts_with('+') => parse_size(&rest[1..])? as isize,
+                    rest if rest.starts_with('-') => -(parse_size(&rest[1..])? as isize),
+                    _ => return Err(Error::Parse(format!("Unexpected text after pattern: {s}"))),
+                };
+                return Ok(anchor.shifted(offset));
+            }
+            '\\' => match chars.next() {
+                Some((_, '/')) => pattern.push('/'),
+                Some((_, c)) => {
+                    pattern.push('\\');
+                    pattern.push(c);
+                }
+                None => break,
+            },
+            c => pattern.push(c),
+        }
+    }
+    Err(Error::Parse(format!("Unterminated pattern: {s}")))
+}
+
+impl From<isize> for SliceIdx {
+    fn from(i: isize) -> Self {
+        if i >= 0 {
+            SliceIdx::FromStart(i as usize)
+        } else {
+            SliceIdx::FromEnd((-i) as usize)
+        }
+    }
+}
+
+impl fmt::Display for SliceIdx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SliceIdx::FromStart(n) => write!(f, "{n}"),
+            SliceIdx::FromEnd(n) => write!(f, "-{n}"),
+            SliceIdx::Percent(p) => write!(f, "{p}%"),
+            SliceIdx::Match(a) => write!(f, "{a}"),
+        }
+    }
+}
+
+impl SliceIdx {
+    /// Absolute position for an input of `len` units, clamped to `0..=len`.
+    /// An anchor that has not been resolved yet is at the end.
+    pub fn resolve(&self, len: usize) -> usize {
+        match *self {
+            SliceIdx::FromStart(n) => n.min(len),
+            SliceIdx::FromEnd(n) => len.saturating_sub(n),
+            SliceIdx::Percent(p) => (len as u128 * p as u128 / 100) as usize,
+            SliceIdx::Match(_) => len,
+        }
+    }
+
+    /// One past the absolute position, used when the index is an inclusive
+    /// bound (the start of a reversed range).
+    fn resolve_inclusive(&self, len: usize) -> usize {
+        match *self {
+            SliceIdx::FromStart(n) => n.saturating_add(1).min(len),
+            SliceIdx::FromEnd(n) => (len + 1).saturating_sub(n).min(len),
+            SliceIdx::Percent(_) | SliceIdx::Match(_) => {
+                self.resolve(len).saturating_add(1).min(len)
+            }
+        }
+    }
+
+    fn end_offset(&self) -> usize {
+        match *self {
+            SliceIdx::FromStart(_) => 0,
+            SliceIdx::FromEnd(n) => n,
+            // only known once the whole input has been seen
+            SliceIdx::Percent(_) | SliceIdx::Match(_) => usize::MAX,
+        }
+    }
+}
+
+impl FromStr for SliceIdx {
+    type Err = Error;
+
+    /// Parse `N`, `-N`, a percentage like `25%`, where `-25%` is the same
+    /// as `75%`, or an [`Anchor`] like `/^BEGIN/`. `N` may have a size
+    /// suffix, see [`parse_size`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('/') {
+            return Ok(SliceIdx::Match(parse_anchor(s)?));
+        }
+        let (neg, n) = match s.strip_prefix('-') {
+            Some(n) => (true, n),
+            None => (false, s),
+        };
+        let Some(p) = n.strip_suffix('%') else {
+            return Ok(match parse_size(n)? {
+                n if neg && n > 0 => SliceIdx::FromEnd(n),
+                n => SliceIdx::FromStart(n),
+            });
+        };
+        let p: u8 = p.parse()?;
+        if p > 100 {
+            return Err(Error::Parse("A percentage must be at most 100%".into()));
+        }
+        Ok(SliceIdx::Percent(if neg { 100 - p } else { p }))
+    }
+}
+
+/// Parse a count with an optional size suffix, as GNU `head -c` accepts:
+/// `K`, `M`, `G`, `T`, `P` and `E` (or `KiB`, `MiB`, ...) are powers of 1024,
+/// `KB`, `MB`, ... powers of 1000. A `0x`, `0o` or `0b` prefix gives the
+/// count in hex, octal or binary instead, without a suffix.
+pub fn parse_size(s: &str) -> Result<usize, Error> {
+    let radix = match s.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0o" | "0O") => 8,
+        Some("0b" | "0B") => 2,
+        _ => 10,
+    };
+    if radix != 10 {
+        let digits = &s[2..];
+        if digits.starts_with('+') {
+            return Err(Error::Parse(format!("Invalid number: {s}")));
+        }
+        return Ok(usize::from_str_radix(digits, radix)?);
+    }
+
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, suffix) = s.split_at(split);
+    let n: usize = digits.parse()?;
+    if suffix.is_empty() {
+        return Ok(n);
+    }
+    let mut chars = suffix.chars();
+    let exp = chars
+        .next()
+        .and_then(|c| "KMGTPE".find(c.to_ascii_uppercase()))
+        .ok_or_else(|| Error::Parse(format!("Invalid size suffix: {suffix}")))?
+        as u32
+        + 1;
+    let base: usize = match chars.as_str() {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return Err(Error::Parse(format!("Invalid size suffix: {suffix}"))),
+    };
+    base.checked_pow(exp)
+        .and_then(|unit| n.checked_mul(unit))
+        .ok_or_else(|| Error::Parse(format!("Size is too large: {s}")))
+}
+
+/// A range `start:end[:step]`, parsed from the same syntax as the CLI.
+///
+/// Omitted bounds follow Python: with a positive step they default to the
+/// beginning and the end of the input, with a negative step the range runs
+/// backwards from the last unit down to the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceRange {
+    pub start: Option<SliceIdx>,
+    pub end: Option<SliceIdx>,
+    pub step: isize,
+}
+
+impl SliceRange {
+    pub fn new(start: SliceIdx, end: SliceIdx) -> Self {
+        Self {
+            start: Some(start),
+            end: Some(end),
+            step: 1,
+        }
+    }
+
+    pub fn with_step(self, step: isize) -> Self {
+        Self { step, ..self }
+    }
+
+    /// Parse a comma-separated list of ranges, e.g. `0:10,50:60,-20:`.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, Error> {
+        split_outside_patterns(s, ',', usize::MAX)
+            .into_iter()
+            .map(str::parse)
+            .collect()
+    }
+
+    /// Parse `sed` style addresses separated by semicolons, e.g. `5,10;$`.
+    /// See [`SliceRange::parse_sed`].
+    pub fn parse_sed_list(s: &str) -> Result<Vec<Self>, Error> {
+        s.split(';').map(Self::parse_sed).collect()
+    }
+
+    /// Parse a `sed` style address `FIRST[,LAST]`: both bounds count from 1
+    /// and are inclusive, `$` is the last unit and `-N` the N-th from the
+    /// end. `LAST` may be `+N` for N more units, and if it comes before
+    /// `FIRST`, only `FIRST` is selected, as in sed.
+    pub fn parse_sed(s: &str) -> Result<Self, Error> {
+        let (first, last) = s.split_once(',').unwrap_or((s, "+0"));
+        let parse = |a: &str| match a {
+            "$" => Ok(SliceIdx::FromEnd(1)),
+            a if a.starts_with('/') => Err(Error::Parse(
+                "Patterns are not supported in sed addresses".into(),
+            )),
+            a => a.parse::<SliceIdx>(),
+        };
+        let start = match parse(first)? {
+            SliceIdx::FromStart(0) => return Err(Error::Parse("Addresses count from 1".into())),
+            SliceIdx::FromStart(n) => SliceIdx::FromStart(n - 1),
+            i => i,
+        };
+        let end = if let Some(n) = last.strip_prefix('+') {
+            let n = parse_size(n)?.saturating_add(1);
+            match start {
+                SliceIdx::FromStart(m) => Some(SliceIdx::FromStart(m.saturating_add(n))),
+                SliceIdx::FromEnd(m) => Some(SliceIdx::FromEnd(m.saturating_sub(n))),
+                SliceIdx::Percent(_) | SliceIdx::Match(_) => {
+                    return Err(Error::Parse("A relative end needs a fixed start".into()));
+                }
+            }
+        } else {
+            match parse(last)? {
+                SliceIdx::FromEnd(1) => None,
+                SliceIdx::FromEnd(n) => Some(SliceIdx::FromEnd(n - 1)),
+                i => Some(i),
+            }
+        };
+        let end = match (&start, end) {
+            (SliceIdx::FromStart(m), Some(SliceIdx::FromStart(n))) if n <= *m => {
+                Some(SliceIdx::FromStart(m + 1))
+            }
+            (_, end) => end,
+        };
+        Ok(Self {
+            start: Some(start),
+            end,
+            step: 1,
+        })
+    }
+
+    /// Parse a `head`/`tail` style count: `N` selects the first N units,
+    /// `-N` all but the last N, and `+N` everything from the N-th (counting
+    /// from 1) on.
+    pub fn parse_count(s: &str) -> Result<Self, Error> {
+        let start = SliceIdx::FromStart(0);
+        Ok(if let Some(n) = s.strip_prefix('+') {
+            let n = parse_size(n)?;
+            Self {
+                start: Some(SliceIdx::FromStart(n.saturating_sub(1))),
+                end: None,
+                step: 1,
+            }
+        } else if let Some(n) = s.strip_prefix('-') {
+            Self::new(start, SliceIdx::FromEnd(parse_size(n)?))
+        } else {
+            Self::new(start, SliceIdx::FromStart(parse_size(s)?))
+        })
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.step < 0
+    }
+
+    /// Absolute `(start, end)` covering the selection for an input of `len`
+    /// units, ascending even for reversed ranges.
+    pub fn resolve(&self, len: usize) -> (usize, usize) {
+        if self.is_reversed() {
+            let lo = self.end.as_ref().map_or(0, |e| e.resolve_inclusive(len));
+            let hi = self
+                .start
+                .as_ref()
+                .map_or(len, |s| s.resolve_inclusive(len));
+            (lo, hi)
+        } else {
+            let lo = self.start.as_ref().map_or(0, |s| s.resolve(len));
+            let hi = self.end.as_ref().map_or(len, |e| e.resolve(len));
+            (lo, hi)
+        }
+    }
+
+    pub fn contains(&self, i: usize, len: usize) -> bool {
+        let (s, e) = self.resolve(len);
+        if !(s <= i && i < e) {
+            return false;
+        }
+        // a reversed range steps down from its last unit
+        let offset = if self.is_reversed() { e - 1 - i } else { i - s };
+        offset.is_multiple_of(self.step.unsigned_abs())
+    }
+
+    /// How many units from the end must be seen before membership is known.
+    pub(crate) fn lookahead(&self) -> usize {
+        let offset = |i: &Option<SliceIdx>| i.as_ref().map_or(0, SliceIdx::end_offset);
+        offset(&self.start).max(offset(&self.end))
+    }
+
+    /// Exclusive bound on selected positions that holds for any input length.
+    /// It also covers a start counted from the beginning, so an input read
+    /// that far is long enough for [`SliceRange::required_len`]. A reversed
+    /// range stopping at a bound that depends on the length has none, as
+    /// reading less would move that bound.
+    pub(crate) fn upper_bound(&self) -> Option<usize> {
+        let inclusive = self.is_reversed() as usize;
+        let from_start = |i: &Option<SliceIdx>| match i {
+            Some(SliceIdx::FromStart(n)) => Some(n.saturating_add(inclusive)),
+            _ => None,
+        };
+        let (first, last) = if self.is_reversed() {
+            if !matches!(self.end, None | Some(SliceIdx::FromStart(_))) {
+                return None;
+            }
+            (&self.end, &self.start)
+        } else {
+            (&self.start, &self.end)
+        };
+        from_start(last).map(|l| l.max(from_start(first).unwrap_or(0)))
+    }
+
+    /// How long the input must be for both bounds to fall inside it, as
+    /// [`crate::Slicer::strict`] checks. A bound may be at the very end,
+    /// except in a reversed range, which resolves them one further.
+    pub(crate) fn required_len(&self) -> usize {
+        let inclusive = self.is_reversed() as usize;
+        let need = |i: &Option<SliceIdx>| match i {
+            Some(SliceIdx::FromStart(n)) => n.saturating_add(inclusive),
+            Some(SliceIdx::FromEnd(n)) => *n,
+            _ => 0,
+        };
+        need(&self.start).max(need(&self.end))
+    }
+}
+
+impl fmt::Display for SliceRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(start) = &self.start {
+            write!(f, "{start}")?;
+        }
+        f.write_str(":")?;
+        if let Some(end) = &self.end {
+            write!(f, "{end}")?;
+        }
+        match self.step {
+            1 => Ok(()),
+            step => write!(f, ":{step}"),
+        }
+    }
+}
+
+impl FromStr for SliceRange {
+    type Err = Error;
+
+    fn from_str(range_str: &str) -> Result<Self, Self::Err> {
+        let mut parts = split_outside_patterns(range_str, ':', 3).into_iter();
+        let s = parts.next().unwrap_or_default();
+        let e = parts
+            .next()
+            .ok_or_else(|| Error::Parse("Invalid range format".into()))?;
+        let step = match parts.next() {
+            None | Some("") => 1,
+            Some(v) => v.parse::<isize>()?,
+        };
+        if step == 0 {
+            return Err(Error::Parse("Step must not be zero".into()));
+        }
+        let start = if s.is_empty() {
+            None
+        } else {
+            Some(s.parse::<SliceIdx>()?)
+        };
+        let end = if e.is_empty() {
+            None
+        } else if let Some(v) = e.strip_prefix('+') {
+            let n = parse_size(v)?;
+            Some(match start.clone().unwrap_or(SliceIdx::FromStart(0)) {
+                SliceIdx::FromStart(m) => SliceIdx::FromStart(m.saturating_add(n)),
+                SliceIdx::FromEnd(m) => SliceIdx::FromEnd(m.saturating_sub(n)),
+                // the end is looked for from the start on, so it is the same match
+                SliceIdx::Match(a) => SliceIdx::Match(a.shifted(n as isize)),
+                SliceIdx::Percent(_) => {
+                    return Err(Error::Parse("A relative end needs a fixed start".into()));
+                }
+            })
+        } else {
+            Some(e.parse::<SliceIdx>()?)
+        };
+        Ok(Self { start, end, step })
+    }
+}
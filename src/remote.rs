@@ -0,0 +1,128 @@
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+
+use crate::{
+    http::{self, Body, Response},
+    storage::Object,
+};
+
+/// Where a remote input is read from: each read is a GET for a range of it,
+/// which the backend turns into the request its server takes.
+pub(crate) trait Remote {
+    /// GET the bytes from `start` to `end` inclusive, or to the end without
+    /// `end`, or the whole input without a range.
+    fn get(&self, range: Option<(u64, Option<u64>)>) -> std::io::Result<Response>;
+}
+
+/// The backend for `url`: `s3://` and `gs://` objects, or plain HTTP.
+pub(crate) fn open(url: &str) -> std::io::Result<Box<dyn Remote>> {
+    Ok(match url.split_once("://").map(|(scheme, _)| scheme) {
+        Some("s3") => Box::new(Object::s3(url)?),
+        Some("gs") => Box::new(Object::gs(url)?),
+        _ => Box::new(http::Http(url.to_owned())),
+    })
+}
+
+/// Total size from the `Content-Range` of a partial response, like
+/// `bytes 0-5/1234`, or `bytes */1234` when asked for too much.
+fn total_size(res: &Response) -> Option<u64> {
+    let range = res.header("content-range")?.strip_prefix("bytes ")?;
+    range.split_once('/')?.1.trim().parse().ok()
+}
+
+/// Size of the input, and its first `n` bytes, if it is served in byte
+/// ranges.
+pub(crate) fn probe(remote: &dyn Remote, n: usize) -> std::io::Result<Option<(u64, Vec<u8>)>> {
+    let mut res = remote.get(Some((0, Some(n as u64 - 1))))?;
+    let Some(size) = total_size(&res) else {
+        return Ok(None);
+    };
+    match res.status {
+        206 => {
+            let mut head = Vec::with_capacity(n);
+            res.body.by_ref().take(n as u64).read_to_end(&mut head)?;
+            Ok(Some((size, head)))
+        }
+        // empty, so there is no byte 0 to send
+        416 => Ok(Some((size, Vec::new()))),
+        _ => Ok(None),
+    }
+}
+
+/// The whole input, as a stream.
+pub(crate) fn stream(remote: &dyn Remote) -> std::io::Result<Body> {
+    let res = remote.get(None)?;
+    match res.status {
+        200..300 => Ok(res.body),
+        status => Err(Error::other(format!("Unexpected HTTP status {status}"))),
+    }
+}
+
+/// The input at `url`, `size` bytes long, read with byte range requests so
+/// that only what is read is downloaded. The first read after a seek asks
+/// for just as much as it can take, which is all of a short range; reading
+/// on, the rest is streamed until the next seek.
+pub(crate) struct Ranged {
+    remote: Box<dyn Remote>,
+    url: String,
+    size: u64,
+    pos: u64,
+    body: Option<Body>,
+    sought: bool,
+}
+
+impl Ranged {
+    pub(crate) fn new(remote: Box<dyn Remote>, url: &str, size: u64) -> Self {
+        Self {
+            remote,
+            url: url.to_owned(),
+            size,
+            pos: 0,
+            body: None,
+            sought: true,
+        }
+    }
+}
+
+impl Read for Ranged {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos < self.size && !buf.is_empty() {
+            if self.body.is_none() {
+                let end = match std::mem::take(&mut self.sought) {
+                    true => Some((self.pos + buf.len() as u64).min(self.size) - 1),
+                    false => None,
+                };
+                let with_url = |e: Error| Error::new(e.kind(), format!("{}: {}", self.url, e));
+                let res = self.remote.get(Some((self.pos, end))).map_err(with_url)?;
+                if res.status != 206 {
+                    return Err(with_url(Error::other(
+                        "The server stopped sending byte ranges",
+                    )));
+                }
+                self.body = Some(res.body);
+            }
+            match self.body.as_mut().unwrap().read(buf)? {
+                0 => self.body = None,
+                n => {
+                    self.pos += n as u64;
+                    return Ok(n);
+                }
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for Ranged {
+    fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+        let pos = match from {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.size.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        }
+        .ok_or(ErrorKind::InvalidInput)?;
+        if pos != self.pos {
+            (self.pos, self.body, self.sought) = (pos, None, true);
+        }
+        Ok(pos)
+    }
+}
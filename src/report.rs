@@ -0,0 +1,174 @@
+use std::{io::Write, time::Duration};
+
+use crate::output::RecordSink;
+
+/// How the input of a slice was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// By seeking to the byte offsets of the ranges.
+    Seek,
+    /// Through a memory map, looking for delimiters.
+    Map,
+    /// From the start as a stream.
+    Stream,
+}
+
+impl Strategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Strategy::Seek => "seek",
+            Strategy::Map => "map",
+            Strategy::Stream => "stream",
+        }
+    }
+}
+
+/// What [`crate::Slicer::slice_files_reported`] did.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub strategy: Strategy,
+    /// What records are, such as lines or bytes.
+    pub unit: &'static str,
+    /// Records emitted.
+    pub records: u64,
+    /// The record and byte offset in the input where the earliest record
+    /// emitted starts, if known, which it is unless bytes are streamed.
+    pub start: Option<(usize, u64)>,
+    /// Where the latest record emitted ends, if known.
+    pub end: Option<(usize, u64)>,
+    /// Bytes written to the output.
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Write the report as a line of JSON.
+    pub fn write_json(&self, mut out: impl Write) -> std::io::Result<()> {
+        let bound = |b: Option<(usize, u64)>| match b {
+            Some((i, offset)) => format!(r#"{{"record":{i},"byte":{offset}}}"#),
+            None => "null".to_owned(),
+        };
+        writeln!(
+            out,
+            r#"{{"strategy":"{}","unit":"{}","records":{},"start":{},"end":{},"bytes":{},"seconds":{}}}"#,
+            self.strategy.as_str(),
+            self.unit,
+            self.records,
+            bound(self.start),
+            bound(self.end),
+            self.bytes,
+            self.elapsed.as_secs_f64(),
+        )
+    }
+}
+
+/// Sink tallying the records written through it to `inner` and where they
+/// are in the input, like [`crate::output::JsonRecords`] finds its bounds.
+/// Records are counted as they begin, or in bytes when that is what they
+/// are, so that bytes are not begun one by one.
+pub(crate) struct Tally<S> {
+    inner: S,
+    records: bool,
+    strategy: Strategy,
+    count: u64,
+    cur: Option<(usize, u64)>, // the record being written
+    len: u64,                  // and its bytes so far
+    first: Option<(usize, u64)>,
+    last: Option<(usize, u64)>,
+}
+
+impl<S: RecordSink> Tally<S> {
+    pub fn new(inner: S, records: bool) -> Self {
+        Self {
+            inner,
+            records,
+            strategy: Strategy::Stream,
+            count: 0,
+            cur: None,
+            len: 0,
+            first: None,
+            last: None,
+        }
+    }
+
+    fn end_record(&mut self) {
+        let Some((i, offset)) = self.cur.take() else {
+            return;
+        };
+        // a run of bytes is begun only once
+        let n = match self.records {
+            true => 1,
+            false => self.len as usize,
+        };
+        let (start, end) = ((i, offset), (i + n, offset + self.len));
+        self.first = Some(self.first.map_or(start, |f| f.min(start)));
+        self.last = self.last.max(Some(end));
+        self.len = 0;
+    }
+
+    /// The inner sink, and the report so far without the bytes written and
+    /// the time taken.
+    pub fn finish(mut self, unit: &'static str) -> (S, Report) {
+        self.end_record();
+        let report = Report {
+            strategy: self.strategy,
+            unit,
+            records: self.count,
+            start: self.first,
+            end: self.last,
+            bytes: 0,
+            elapsed: Duration::ZERO,
+        };
+        (self.inner, report)
+    }
+}
+
+impl<S: RecordSink> Write for Tally<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if !self.records {
+            self.count += n as u64;
+        }
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: RecordSink> RecordSink for Tally<S> {
+    fn prefixed(&self) -> bool {
+        self.records || self.inner.prefixed()
+    }
+
+    fn begin_record(&mut self, i: usize, offset: u64) -> std::io::Result<()> {
+        self.end_record();
+        (self.cur, self.len) = (Some((i, offset)), 0);
+        self.count += self.records as u64;
+        self.inner.begin_record(i, offset)
+    }
+
+    fn read_by(&mut self, how: Strategy) {
+        self.strategy = how;
+    }
+}
+
+/// Writer counting the bytes written through it.
+pub(crate) struct Counted<W> {
+    pub(crate) inner: W,
+    pub(crate) count: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
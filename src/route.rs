@@ -0,0 +1,103 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    path::Path,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver},
+    },
+};
+
+use crate::{Result, SliceRange, Slicer};
+
+/// Chunks of input queued for each range before reading waits for it.
+const DEPTH: usize = 16;
+
+/// Reader of the input chunks sent to one range's thread.
+struct Feed {
+    chunks: Receiver<Arc<[u8]>>,
+    cur: Arc<[u8]>,
+    pos: usize,
+}
+
+impl Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.cur.len() {
+            // the sender is gone at the end of the input
+            let Ok(chunk) = self.chunks.recv() else {
+                return Ok(0);
+            };
+            (self.cur, self.pos) = (chunk, 0);
+        }
+        let n = buf.len().min(self.cur.len() - self.pos);
+        buf[..n].copy_from_slice(&self.cur[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Slicer {
+    /// Slice the concatenated `paths` for each of `routes`, writing what its
+    /// range selects to its output, in a single pass over the input. The
+    /// ranges of the slicer itself are not used.
+    ///
+    /// Every range is sliced on a thread of its own, as a stream fed with the
+    /// input as it is read, so one counted from the end keeps its tail like
+    /// any stream would. Reading stops early once all of them are done.
+    pub fn slice_routed<W: Write + Send>(
+        &self,
+        paths: &[impl AsRef<Path>],
+        routes: Vec<(SliceRange, W)>,
+    ) -> Result<()> {
+        let mut input = self.open_files(paths)?;
+        // the input is decoded, and cut off at the deadline, once for all
+        let plain = self
+            .clone()
+            .decompress(false)
+            .strip_bom(false)
+            .time_limit(None);
+        std::thread::scope(|s| {
+            let mut feeds = Vec::new();
+            let mut workers = Vec::new();
+            for (range, out) in routes {
+                let (tx, rx) = mpsc::sync_channel(DEPTH);
+                let mut slicer = plain.clone();
+                slicer.ranges = vec![range];
+                let feed = Feed {
+                    chunks: rx,
+                    cur: Arc::new([]),
+                    pos: 0,
+                };
+                // errors are not Send, so only their messages leave the thread
+                workers.push(
+                    s.spawn(move || slicer.slice_reader(feed, out).map_err(|e| e.to_string())),
+                );
+                feeds.push(Some(tx));
+            }
+
+            let mut buf = vec![0; self.buf_len()];
+            while feeds.iter().any(Option::is_some) {
+                let n = match input.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                let chunk: Arc<[u8]> = buf[..n].into();
+                for feed in &mut feeds {
+                    // a range that is done no longer takes any
+                    if feed
+                        .as_ref()
+                        .is_some_and(|tx| tx.send(Arc::clone(&chunk)).is_err())
+                    {
+                        *feed = None;
+                    }
+                }
+            }
+            drop(feeds);
+            for worker in workers {
+                worker.join().unwrap()?;
+            }
+            Ok(())
+        })
+    }
+}
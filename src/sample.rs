@@ -0,0 +1,199 @@
+use std::{
+    collections::{HashSet, hash_map::RandomState},
+    hash::{BuildHasher, Hasher},
+    io::Write,
+};
+
+use crate::{Mode, output::RecordSink};
+
+/// Small, fast pseudo-random generator (SplitMix64). Random enough for
+/// sampling, not for anything secret.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    /// A generator seeded with `seed`, or differently on every run if there
+    /// is none.
+    pub fn new(seed: Option<u64>) -> Self {
+        Self(seed.unwrap_or_else(|| RandomState::new().build_hasher().finish()))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, which must not be empty.
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+/// `n` distinct positions in `0..total` chosen uniformly at random, sorted,
+/// or all of them if there are not that many (Floyd's algorithm).
+pub(crate) fn pick(total: usize, n: usize, rng: &mut Rng) -> Vec<usize> {
+    if n >= total {
+        return (0..total).collect();
+    }
+    let mut chosen = HashSet::with_capacity(n);
+    for j in total - n..total {
+        let t = rng.below(j + 1);
+        if !chosen.insert(t) {
+            chosen.insert(j);
+        }
+    }
+    let mut picks: Vec<usize> = chosen.into_iter().collect();
+    picks.sort_unstable();
+    picks
+}
+
+/// A record kept in the reservoir.
+struct Kept {
+    seq: usize, // position among the sampled records
+    i: usize,
+    offset: u64,
+    data: Vec<u8>,
+}
+
+/// Sink passing on a uniform random sample of `n` records, in the order
+/// they were written or shuffled, once [`Sampler::finish`] is called. Only
+/// the sample is held in memory. The first `skip` records, e.g. a header,
+/// always pass straight through.
+pub(crate) struct Sampler<S> {
+    inner: S,
+    n: usize,
+    skip: usize,
+    rng: Rng,
+    shuffle: bool,
+    mode: Mode,
+    memory: Option<usize>,
+    held: usize, // bytes of the records kept
+    seen: usize, // records offered to the reservoir so far
+    kept: Vec<Kept>,
+    cur: Option<usize>, // slot of the record being written, if it is kept
+    pass: bool,         // whether the record being written passes through
+}
+
+impl<S: RecordSink> Sampler<S> {
+    pub fn new(inner: S, n: usize, skip: usize, rng: Rng) -> Self {
+        Self {
+            inner,
+            n,
+            skip,
+            rng,
+            shuffle: false,
+            mode: Mode::Byte,
+            memory: None,
+            held: 0,
+            seen: 0,
+            kept: Vec::with_capacity(n.min(1 << 16)),
+            cur: None,
+            pass: false,
+        }
+    }
+
+    /// Write out the sample in random order instead.
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    /// Count records in `mode`, so that one left unterminated at the end of
+    /// the input is ended when others are written after it.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Fail once the records kept take more than `bytes`.
+    pub fn max_memory(mut self, bytes: Option<usize>) -> Self {
+        self.memory = bytes;
+        self
+    }
+
+    /// Write out the sample, returning the sink it went to.
+    pub fn finish(mut self) -> std::io::Result<S> {
+        if self.shuffle {
+            // Fisher-Yates
+            for j in (1..self.kept.len()).rev() {
+                self.kept.swap(j, self.rng.below(j + 1));
+            }
+        } else {
+            self.kept.sort_unstable_by_key(|k| k.seq);
+        }
+        let last = self.kept.len().saturating_sub(1);
+        for (j, k) in self.kept.iter().enumerate() {
+            self.inner.begin_record(k.i, k.offset)?;
+            self.inner.write_all(&k.data)?;
+            if j < last {
+                self.inner.write_all(self.mode.missing_end(&k.data))?;
+            }
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<S: RecordSink> Write for Sampler<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.pass {
+            self.inner.write_all(buf)?;
+        } else if let Some(j) = self.cur {
+            self.held += buf.len();
+            if let Some(cap) = self.memory
+                && self.held > cap
+            {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::OutOfMemory,
+                    format!("Keeping the records takes more than {cap} bytes of memory"),
+                ));
+            }
+            self.kept[j].data.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: RecordSink> RecordSink for Sampler<S> {
+    fn prefixed(&self) -> bool {
+        true
+    }
+
+    fn begin_record(&mut self, i: usize, offset: u64) -> std::io::Result<()> {
+        self.pass = i < self.skip;
+        if self.pass {
+            self.cur = None;
+            return self.inner.begin_record(i, offset);
+        }
+
+        // reservoir sampling: record k replaces a random one with odds n/k
+        let seq = self.seen;
+        self.seen += 1;
+        let slot = if seq < self.n {
+            self.kept.push(Kept {
+                seq,
+                i,
+                offset,
+                data: Vec::new(),
+            });
+            Some(seq)
+        } else {
+            Some(self.rng.below(seq + 1)).filter(|&j| j < self.n)
+        };
+        if let Some(j) = slot
+            && j < seq
+        {
+            let k = &mut self.kept[j];
+            (k.seq, k.i, k.offset) = (seq, i, offset);
+            self.held -= k.data.len();
+            k.data.clear();
+        }
+        self.cur = slot;
+        Ok(())
+    }
+}
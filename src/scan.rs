@@ -0,0 +1,56 @@
+use memchr::{memchr_iter, memrchr_iter};
+
+/// Inputs from this size on are counted on several threads.
+const PARALLEL_MIN: usize = 64 << 20;
+
+/// Bytes counted at once while looking for the n-th delimiter.
+const BLOCK: usize = 16 * 1024;
+
+// memchr picks the widest vector routines the CPU has (AVX2 or SSE2 on
+// x86_64, NEON on aarch64) at run time. Counting the delimiters of a whole
+// block with them does no work per delimiter, so blocks without the one
+// looked for are skipped about twice as fast as by stepping through them.
+
+/// Number of `delim` bytes in `data`. Large inputs are split into chunks
+/// counted in parallel.
+pub(crate) fn count(data: &[u8], delim: u8) -> usize {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if threads == 1 || data.len() < PARALLEL_MIN {
+        return memchr_iter(delim, data).count();
+    }
+    std::thread::scope(|s| {
+        let counts: Vec<_> = data
+            .chunks(data.len().div_ceil(threads))
+            .map(|chunk| s.spawn(move || memchr_iter(delim, chunk).count()))
+            .collect();
+        counts.into_iter().map(|c| c.join().unwrap()).sum()
+    })
+}
+
+/// Offset of the `n`-th `delim` byte in `data`, counted from 0.
+pub(crate) fn nth(data: &[u8], delim: u8, mut n: usize) -> Option<usize> {
+    let mut off = 0;
+    for block in data.chunks(BLOCK) {
+        let k = memchr_iter(delim, block).count();
+        if n < k {
+            return memchr_iter(delim, block).nth(n).map(|p| off + p);
+        }
+        n -= k;
+        off += block.len();
+    }
+    None
+}
+
+/// Offset of the `n`-th `delim` byte from the end of `data`, counted from 0.
+pub(crate) fn nth_back(data: &[u8], delim: u8, mut n: usize) -> Option<usize> {
+    let mut start = data.len();
+    for block in data.rchunks(BLOCK) {
+        start -= block.len();
+        let k = memchr_iter(delim, block).count();
+        if n < k {
+            return memrchr_iter(delim, block).nth(n).map(|p| start + p);
+        }
+        n -= k;
+    }
+    None
+}
@@ -0,0 +1,1640 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Result,
+    align::{self, Align},
+    batch::{Checked, Failures},
+    bgzf::{self, BgzfReader},
+    compress::{Compression, decompress_stream},
+    context::{self, Marked},
+    index::LineIndex,
+    input::{Concat, strip_bom},
+    jsonl::JsonlReader,
+    mmap,
+    mode::{
+        CountMode, CountModeByte, CountModeChar, CountModeCsv, CountModeDelim, CountModeDelimStr,
+        CountModeFasta, CountModeFastq, CountModeLines, CountModeParagraph, CountModeRecord,
+        CountModeRegex, CountModeWarc, Mode,
+    },
+    output::{
+        Counter, Format, HexDump, InvalidUtf8, JsonRecords, Offsets, Prefixed, RecordSink,
+        Redelimited, Sink, TrackLast, Utf8Check,
+    },
+    platform::{self, Advice, IoBackend},
+    range::{SliceIdx, SliceRange},
+    report::{Counted, Report, Strategy, Tally},
+    sample::{Rng, Sampler},
+    scan,
+    spool::Spool,
+    trace::{self, debug, trace},
+    window::{self, TimeWindow},
+};
+
+/// Extracts one or more [`SliceRange`]s from readers and files.
+///
+/// Overlapping ranges are merged and the selection is always emitted in input
+/// order, so any set of ranges is handled in a single pass. The exception is a
+/// single range with a negative step, which is emitted back to front.
+#[derive(Debug, Clone)]
+pub struct Slicer {
+    pub(crate) ranges: Vec<SliceRange>,
+    pub(crate) mode: Mode,
+    terminate: bool,
+    strip: bool,
+    number: bool,
+    byte_offsets: bool,
+    pub(crate) format: Format,
+    source: Vec<String>, // input names for JSON output
+    pub(crate) header: bool,
+    pub(crate) last_match: bool,
+    pub(crate) invert: bool,
+    pub(crate) sample: Option<usize>,
+    pub(crate) shuffle: bool,
+    seed: Option<u64>,
+    pub(crate) max_records: Option<usize>,
+    pub(crate) strict: bool,
+    max_memory: Option<usize>,
+    pub(crate) time_limit: Option<Duration>,
+    pub(crate) window: Option<TimeWindow>,
+    pub(crate) utf8: Option<InvalidUtf8>,
+    strip_bom: bool,
+    pub(crate) decompress: bool,
+    pub(crate) io_backend: IoBackend,
+    pub(crate) direct: bool,
+    pub(crate) fadvise: bool,
+    buffer_size: Option<usize>,
+    output_delimiter: Option<Vec<u8>>,
+    pub(crate) jobs: usize,
+    pub(crate) continue_on_error: bool,
+    pub(crate) json_errors: bool,
+    pub(crate) archive_member: Option<PathBuf>,
+    pub(crate) align: Option<Align>,
+    align_inward: bool,
+    pub(crate) context: Option<usize>,
+    color: bool,
+}
+
+/// Size of read and write buffers unless set: big enough that fast disks
+/// are not held back by system calls, small enough for many of them.
+const AUTO_BUFFER: usize = 128 * 1024;
+
+impl Slicer {
+    pub fn new(ranges: impl Into<Vec<SliceRange>>, mode: Mode) -> Self {
+        Self {
+            ranges: ranges.into(),
+            mode,
+            terminate: false,
+            strip: false,
+            number: false,
+            byte_offsets: false,
+            format: Format::Raw,
+            source: Vec::new(),
+            header: false,
+            last_match: false,
+            invert: false,
+            sample: None,
+            shuffle: false,
+            seed: None,
+            max_records: None,
+            strict: false,
+            max_memory: None,
+            time_limit: None,
+            window: None,
+            utf8: None,
+            strip_bom: false,
+            decompress: false,
+            io_backend: IoBackend::Std,
+            direct: false,
+            fadvise: false,
+            buffer_size: None,
+            output_delimiter: None,
+            jobs: 1,
+            continue_on_error: false,
+            json_errors: false,
+            archive_member: None,
+            align: None,
+            align_inward: false,
+            context: None,
+            color: false,
+        }
+    }
+
+    /// Make sure non-empty output ends with the record delimiter, even when
+    /// the last selected record was unterminated in the input. Modes without
+    /// a fixed delimiter, such as bytes, use a newline.
+    pub fn terminate(mut self, terminate: bool) -> Self {
+        self.terminate = terminate;
+        self
+    }
+
+    /// Drop the record delimiter (or newline, as for [`Slicer::terminate`])
+    /// from the end of the output, if it is there.
+    pub fn strip_terminator(mut self, strip: bool) -> Self {
+        self.strip = strip;
+        self
+    }
+
+    /// Always emit the first record (e.g. a CSV header) and count ranges
+    /// from the record after it.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Resolve [`crate::Anchor`] bounds to the last record matching them
+    /// instead of the first.
+    pub fn last_match(mut self, last: bool) -> Self {
+        self.last_match = last;
+        self
+    }
+
+    /// Emit everything outside the ranges instead of inside them.
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    /// Emit only `n` records picked uniformly at random from the selection,
+    /// in the order they would otherwise be emitted. Header records are still
+    /// always emitted.
+    pub fn sample(mut self, n: Option<usize>) -> Self {
+        self.sample = n;
+        self
+    }
+
+    /// Emit the selected records in random order.
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    /// Seed the sampling and shuffling, so the same input is always picked
+    /// and ordered the same way.
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Read at most `n` records of the input and slice them as if the input
+    /// ended there.
+    pub fn max_records(mut self, n: Option<usize>) -> Self {
+        self.max_records = n;
+        self
+    }
+
+    /// Fail when a range reaches past the input, e.g. lines `5000:6000` of a
+    /// 100-line file, instead of selecting what there is. Streams may have
+    /// written part of the selection by the time that is known.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Hold at most `bytes` of a stream in memory. Records waiting on a range
+    /// counted from the end then go to a temporary file, while reversed
+    /// ranges and patterns, which keep the whole input, fail instead, as do
+    /// samples and shuffles keeping more than that of records.
+    pub fn max_memory(mut self, bytes: Option<usize>) -> Self {
+        self.max_memory = bytes;
+        self
+    }
+
+    /// Stop reading stdin and other streams given to [`Slicer::slice_files`]
+    /// once `limit` has passed, and slice what was read as if the input
+    /// ended there.
+    pub fn time_limit(mut self, limit: Option<Duration>) -> Self {
+        self.time_limit = limit;
+        self
+    }
+
+    /// Slice only the records of a log within `window`, as if the input
+    /// were just those, with ranges counted from the first of them. Seekable
+    /// line-based files are binary searched for it, other inputs scanned.
+    pub fn time_window(mut self, window: Option<TimeWindow>) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Prefix each emitted record with its 1-based number in the input,
+    /// followed by a colon.
+    pub fn number(mut self, number: bool) -> Self {
+        self.number = number;
+        self
+    }
+
+    /// Prefix each emitted record with the byte offset where it starts in
+    /// the input, followed by a colon.
+    pub fn byte_offsets(mut self, byte_offsets: bool) -> Self {
+        self.byte_offsets = byte_offsets;
+        self
+    }
+
+    /// Write the records as raw bytes, as a JSON envelope, as a hex dump or
+    /// only as their offsets.
+    /// The other formats take the place of record prefixes and are never
+    /// terminated or stripped like raw output.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Check that the output is valid UTF-8, failing at the first invalid
+    /// sequence or replacing each one with U+FFFD as `policy` says.
+    pub fn validate_utf8(mut self, policy: Option<InvalidUtf8>) -> Self {
+        self.utf8 = policy;
+        self
+    }
+
+    /// Drop a UTF-8 or UTF-16 byte order mark from the start of each input
+    /// before counting, so it does not stick to the first record. Inputs
+    /// are then streamed rather than seeked or mapped. Otherwise a UTF-8
+    /// mark is counted as part of the first character, not as one.
+    pub fn strip_bom(mut self, strip: bool) -> Self {
+        self.strip_bom = strip;
+        self
+    }
+
+    /// Transparently decode compressed inputs, detected by their magic
+    /// number or, for named files, their extension.
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.decompress = decompress;
+        self
+    }
+
+    /// Move byte ranges of a single file to the descriptor given to
+    /// [`Slicer::slice_files_fd`] with `backend`.
+    pub fn io_backend(mut self, backend: IoBackend) -> Self {
+        self.io_backend = backend;
+        self
+    }
+
+    /// Read the named files given to [`Slicer::slice_files`] around the
+    /// page cache where they can be seeked, so slicing a big one leaves the
+    /// cache to others. They are then neither mapped nor sent by the kernel.
+    pub fn direct(mut self, direct: bool) -> Self {
+        self.direct = direct;
+        self
+    }
+
+    /// Tell the kernel that the named files given to [`Slicer::slice_files`]
+    /// are read front to back, and drop what was read of them from the page
+    /// cache, so a one-off slice of a big log does not evict everything else.
+    pub fn fadvise(mut self, fadvise: bool) -> Self {
+        self.fadvise = fadvise;
+        self
+    }
+
+    /// End each record written with `delim` instead of the terminator it has
+    /// in the input, e.g. to read lines and write NUL-terminated records.
+    /// Unterminated records stay so. Only applies to raw output.
+    pub fn output_delimiter(mut self, delim: Option<Vec<u8>>) -> Self {
+        self.output_delimiter = delim;
+        self
+    }
+
+    /// Slice the files of [`Slicer::slice_separate`] on `n` threads, or one
+    /// for each CPU with 0. Their output is still written in order.
+    pub fn jobs(mut self, n: usize) -> Self {
+        self.jobs = match n {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        self
+    }
+
+    /// Go on with the other files when one of [`Slicer::slice_separate`] or
+    /// [`Slicer::slice_each_in_place`] fails, reporting it on stderr, and
+    /// fail once all are done instead. Errors writing the output still stop
+    /// at once.
+    pub fn continue_on_error(mut self, go_on: bool) -> Self {
+        self.continue_on_error = go_on;
+        self
+    }
+
+    /// Report the files [`Slicer::continue_on_error`] goes on from as lines
+    /// of JSON, see [`Error::to_json`], instead of text.
+    ///
+    /// [`Error::to_json`]: crate::Error::to_json
+    pub fn json_errors(mut self, json: bool) -> Self {
+        self.json_errors = json;
+        self
+    }
+
+    /// Slice the file `member` inside each input, which are tar or zip
+    /// archives, instead of the inputs themselves. In an uncompressed tar
+    /// archive it is found by seeking from header to header, and in a zip
+    /// archive from its central directory; either way it can be sought in
+    /// itself unless it is compressed. Inputs named like `logs.zip!app.log`
+    /// are a member of a zip archive without this.
+    pub fn archive_member(mut self, member: Option<PathBuf>) -> Self {
+        self.archive_member = member;
+        self
+    }
+
+    /// Widen byte ranges to the nearest boundaries of `align` around them,
+    /// such as whole lines. Only contiguous byte ranges read forward from an
+    /// input that can be sought are aligned, which are found by peeking
+    /// around their ends.
+    pub fn align(mut self, align: Option<Align>) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// With [`Slicer::align`], narrow byte ranges to the boundaries within
+    /// them instead, dropping those left empty.
+    pub fn align_inward(mut self, inward: bool) -> Self {
+        self.align_inward = inward;
+        self
+    }
+
+    /// Also emit `n` records before and after the ranges as context, told
+    /// apart from the selection like `grep -C` does: the selected records
+    /// are marked with `> `, or with [`Slicer::color`] set in bold. Ranges
+    /// counted from the end are resolved by reading the input first.
+    pub fn context(mut self, n: Option<usize>) -> Self {
+        self.context = n;
+        self
+    }
+
+    /// With [`Slicer::context`], dim the context and set the selection in
+    /// bold with terminal escapes instead of marking it.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Read and write through buffers of `bytes`, and scan streams in blocks
+    /// that big; None picks 128 KiB.
+    pub fn buffer_size(mut self, bytes: Option<usize>) -> Self {
+        self.buffer_size = bytes;
+        self
+    }
+
+    pub(crate) fn buf_len(&self) -> usize {
+        self.buffer_size.unwrap_or(AUTO_BUFFER).max(1)
+    }
+
+    /// Slice a non-seekable stream, writing the selected region to `out`.
+    pub fn slice_reader(&self, input: impl Read, out: impl Write) -> Result<()> {
+        if self.decompress {
+            self.slice_unmarked(decompress_stream(input)?, out)
+        } else {
+            self.slice_unmarked(input, out)
+        }
+    }
+
+    /// Slice a decoded stream, dropping its byte order mark if asked to.
+    fn slice_unmarked(&self, input: impl Read, out: impl Write) -> Result<()> {
+        if self.strip_bom {
+            self.slice_plain(strip_bom(input)?, out)
+        } else {
+            self.slice_plain(input, out)
+        }
+    }
+
+    /// Slice a stream that is already decoded.
+    fn slice_plain(&self, input: impl Read, out: impl Write) -> Result<()> {
+        let mut bufwriter = BufWriter::with_capacity(self.buf_len(), self.track_last(out));
+        let sink = self.sink(&mut bufwriter)?;
+        self.emit_plain(input, sink)?.finish()?;
+        self.finish(bufwriter)
+    }
+
+    /// Emit the selected records of a decoded stream to `sink`, which is
+    /// handed back afterwards.
+    fn emit_plain<S: RecordSink>(&self, mut input: impl Read, mut sink: S) -> Result<S> {
+        if self.align.is_some() {
+            return Err("Byte ranges can only be aligned in inputs that can be sought".into());
+        }
+        sink.read_by(Strategy::Stream);
+        debug!(
+            "streaming {} through a buffer of {} bytes",
+            self.mode.unit(),
+            self.buf_len()
+        );
+        if self.selects_all() && !sink.prefixed() {
+            std::io::copy(&mut input, &mut sink)?;
+            return Ok(sink);
+        }
+        if self.needs_scan() {
+            // the input is read twice, so a stream is kept in memory
+            let mut data = Vec::new();
+            let cap = self.max_memory.map_or(u64::MAX, |m| m as u64 + 1);
+            input.take(cap).read_to_end(&mut data)?;
+            self.selection().check_memory(data.len())?;
+            let this = self.resolve_bounds(|scan, found| scan.emit_plain(&data[..], found))?;
+            return this.emit_plain(&data[..], sink);
+        }
+        let mut bufreader = BufReader::with_capacity(self.buf_len(), input);
+        if self.sample.is_some() || self.shuffle {
+            let mut sampler = self.sampler(sink, self.sample);
+            self.slice_stream(&mut bufreader, &mut sampler)?;
+            sink = sampler.finish()?;
+        } else {
+            self.slice_stream(&mut bufreader, &mut sink)?;
+        }
+        Ok(sink)
+    }
+
+    /// What ends the output when it is normalized.
+    fn terminator(&self) -> &[u8] {
+        match &self.output_delimiter {
+            Some(delim) if self.format == Format::Raw => delim,
+            _ => self.mode.terminator().unwrap_or(b"\n"),
+        }
+    }
+
+    /// Whether the end of the output is adjusted, which needs its last bytes.
+    pub(crate) fn normalizes_end(&self) -> bool {
+        self.terminates() || self.strips()
+    }
+
+    fn terminates(&self) -> bool {
+        self.terminate && self.format == Format::Raw
+    }
+
+    fn strips(&self) -> bool {
+        self.strip && self.format == Format::Raw
+    }
+
+    fn track_last<W: Write>(&self, out: W) -> TrackLast<Utf8Check<W>> {
+        let out = Utf8Check::new(out, self.utf8);
+        TrackLast::new(out, self.terminator().len(), self.strips())
+    }
+
+    /// Flush the output, adjusting its end first if requested.
+    pub(crate) fn finish(
+        &self,
+        bufwriter: BufWriter<TrackLast<Utf8Check<impl Write>>>,
+    ) -> Result<()> {
+        let mut out = bufwriter.into_inner().map_err(|e| e.into_error())?;
+        if self.strips() {
+            out.strip(self.terminator())?;
+        } else if self.terminates() {
+            out.terminate(self.terminator())?;
+        }
+        out.get_mut().finish()?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Whether data appended to the input would continue the selection.
+    pub(crate) fn open_ended(&self) -> bool {
+        match self.ranges.as_slice() {
+            [r] => r.end.is_none() && r.step == 1 && !self.invert,
+            _ => false,
+        }
+    }
+
+    /// The range to emit back to front, if any.
+    fn reversed(&self) -> Result<Option<&SliceRange>> {
+        if self.invert {
+            // the complement is always emitted in input order
+            return Ok(None);
+        }
+        match self.ranges.as_slice() {
+            [r] if r.is_reversed() => Ok(Some(r)),
+            rs if rs.iter().any(SliceRange::is_reversed) => {
+                Err("A negative step cannot be combined with other ranges".into())
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Slice a file, seeking directly to the range when possible.
+    pub fn slice_file(&self, file: File, out: impl Write) -> Result<()> {
+        let mut bufwriter = BufWriter::with_capacity(self.buf_len(), self.track_last(out));
+        let sink = self.sink(&mut bufwriter)?;
+        self.emit_file(file, sink)?.finish()?;
+        self.finish(bufwriter)
+    }
+
+    /// Emit the selected records of a file to `sink` like
+    /// [`Slicer::slice_file`], handing it back afterwards.
+    fn emit_file<S: RecordSink>(&self, mut file: File, sink: S) -> Result<S> {
+        let ftype = file.metadata()?.file_type();
+        if ftype.is_dir() {
+            return Err("Input file is a directory".into());
+        }
+        if self.strip_bom {
+            // the mark would shift every offset, so the file is streamed
+            return if self.decompress {
+                self.emit_plain(strip_bom(decompress_stream(file)?)?, sink)
+            } else {
+                self.emit_plain(strip_bom(file)?, sink)
+            };
+        }
+
+        if !platform::is_seekable(&file) {
+            return if self.decompress {
+                self.emit_plain(decompress_stream(file)?, sink)
+            } else {
+                self.emit_plain(file, sink)
+            };
+        }
+        if let Some(this) = self.search_window(&file)? {
+            return this.emit_file(file, sink);
+        }
+        if self.needs_scan() {
+            let scanned = file.try_clone()?;
+            let this = self.resolve_bounds(|scan, found| scan.emit_file(scanned, found))?;
+            file.seek(std::io::SeekFrom::Start(0))?;
+            return this.emit_file(file, sink);
+        }
+        if self.decompress
+            && let Some(c) = Compression::detect_file(&mut file, None)?
+        {
+            if c == Compression::Gzip && self.can_seek() && bgzf::is_bgzf(&mut file)? {
+                let index = bgzf::Index::load(&mut file, None)?;
+                let size = index.size();
+                let input = BgzfReader::new(file, index.into())?;
+                return self.emit_seekable(input, size, sink);
+            }
+            return self.emit_plain(c.decoder(file)?, sink);
+        }
+        if let Some(delim) = self.map_delimiter() {
+            return self.emit_mapped(&file, delim, None, sink);
+        }
+        if !self.can_seek() {
+            return self.emit_plain(file, sink);
+        }
+        let size = file.seek(std::io::SeekFrom::End(0))?;
+        self.emit_seekable(file, size, sink)
+    }
+
+    /// Slice several files as if they were concatenated. `-` stands for stdin.
+    ///
+    /// When every input is seekable, byte ranges are resolved against the
+    /// total size and only the files they cover are read. A single file with
+    /// an up to date `slice index` sidecar is looked up in it instead of
+    /// scanned from the start.
+    pub fn slice_files(&self, paths: &[impl AsRef<Path>], out: impl Write) -> Result<()> {
+        let this = self.with_source(paths);
+        let input = this.open_files(paths)?;
+        let mut bufwriter = BufWriter::with_capacity(this.buf_len(), this.track_last(out));
+        debug!("writing through a buffer of {} bytes", this.buf_len());
+        let sink = this.sink(&mut bufwriter)?;
+        trace::timed("slicing", || this.emit_files(paths, input, sink))?.finish()?;
+        this.finish(bufwriter)
+    }
+
+    /// Like [`Slicer::slice_files`], reporting how the inputs were read, what
+    /// was emitted and how long it took. Reports cost a little more for
+    /// records other than bytes, which are then begun one by one.
+    pub fn slice_files_reported(
+        &self,
+        paths: &[impl AsRef<Path>],
+        out: impl Write,
+    ) -> Result<Report> {
+        let started = Instant::now();
+        let this = self.with_source(paths);
+        let input = this.open_files(paths)?;
+        let mut out = Counted {
+            inner: out,
+            count: 0,
+        };
+        let mut bufwriter = BufWriter::with_capacity(this.buf_len(), this.track_last(&mut out));
+        let sink = Tally::new(this.sink(&mut bufwriter)?, this.mode != Mode::Byte);
+        let (sink, report) = this
+            .emit_files(paths, input, sink)?
+            .finish(this.mode.unit());
+        sink.finish()?;
+        this.finish(bufwriter)?;
+        Ok(Report {
+            bytes: out.count,
+            elapsed: started.elapsed(),
+            ..report
+        })
+    }
+
+    /// Open `paths` as one stream, to be read until the time limit, if any.
+    pub(crate) fn open_files(&self, paths: &[impl AsRef<Path>]) -> Result<Concat> {
+        let deadline = self.time_limit.map(|t| Instant::now() + t);
+        let input = Concat::open(
+            paths,
+            self.archive_member.as_deref(),
+            self.decompress,
+            deadline,
+        )?
+        .strip_bom(self.strip_bom)
+        .direct(self.direct)
+        .fadvise(self.fadvise);
+        match input.size() {
+            Some(size) => debug!("opened {} input(s) of {size} bytes", paths.len()),
+            None => debug!("opened {} input(s) of unknown size", paths.len()),
+        }
+        Ok(input)
+    }
+
+    /// Emit the selected records of `input`, the concatenated `paths`, to
+    /// `sink` like [`Slicer::slice_files`], handing it back afterwards.
+    pub(crate) fn emit_files<S: RecordSink>(
+        &self,
+        paths: &[impl AsRef<Path>],
+        input: Concat,
+        sink: S,
+    ) -> Result<S> {
+        if self.window.is_some()
+            && let Some(file) = input.single_file()?
+            && let Some(this) = self.search_window(&file)?
+        {
+            return this.emit_files(paths, input, sink);
+        }
+        // a download is not read twice, so URLs are only sought in byte ranges
+        let size = input.size().filter(|_| self.can_seek() || !input.remote());
+        if self.needs_scan() && size.is_some() {
+            let this = self.resolve_bounds(|scan, found| scan.emit_files(paths, input, found))?;
+            return this.emit_files(paths, self.open_files(paths)?, sink);
+        }
+        if let Some(delim) = self.map_delimiter()
+            && let Some(file) = input.single_file()?
+        {
+            let index = match paths {
+                [path] => LineIndex::load(path.as_ref(), &file, delim),
+                _ => None,
+            };
+            return self.emit_mapped(&file, delim, index.as_ref(), sink);
+        }
+        match size {
+            Some(size) if self.can_seek() => self.emit_seekable(input, size, sink),
+            Some(_) => self.emit_counted(input, sink),
+            None => self.emit_plain(input, sink),
+        }
+    }
+
+    /// Emit the selected records of a seekable input to `sink` like
+    /// [`Slicer::emit_plain`], counting them first when ranges count from
+    /// the end, which is quicker than holding back records until their
+    /// distance from the end is known.
+    fn emit_counted<S: RecordSink>(&self, mut input: impl Read + Seek, sink: S) -> Result<S> {
+        match trace::timed("counting records", || self.count_first(&mut input))? {
+            Some(this) => this.emit_plain(input, sink),
+            None => self.emit_plain(input, sink),
+        }
+    }
+
+    /// This slicer with its ranges over the records of `input` counted from
+    /// the start, for records ending in a byte and ranges counted from the
+    /// end. `input` is rewound afterwards.
+    fn count_first(&self, input: &mut (impl Read + Seek)) -> Result<Option<Slicer>> {
+        let Mode::Delimited(delim) = self.mode else {
+            return Ok(None);
+        };
+        if self.selection().depth() == 0 {
+            return Ok(None);
+        }
+        let mut buf = vec![0; self.buf_len()];
+        let (mut len, mut last) = (0, delim);
+        loop {
+            let n = match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            len += scan::count(&buf[..n], delim);
+            last = buf[n - 1];
+        }
+        len += (last != delim) as usize;
+        input.seek(SeekFrom::Start(0))?;
+
+        let len = len.min(self.max_records.unwrap_or(usize::MAX));
+        let len = len.saturating_sub(self.header as usize);
+        self.selection().check(len)?;
+        Ok(Some(Slicer {
+            ranges: window::within(&self.ranges, 0, len),
+            ..self.clone()
+        }))
+    }
+
+    /// Count the units, records or bytes in byte mode, that
+    /// [`Slicer::slice_files`] would emit, without reading more than that
+    /// needs: byte ranges are resolved against the size of seekable inputs,
+    /// and delimiters are counted in a memory map.
+    pub fn count_files(&self, paths: &[impl AsRef<Path>]) -> Result<u64> {
+        let mut input = self.open_files(paths)?;
+        if let Some(size) = input.size()
+            && self.can_seek()
+        {
+            let runs = self.seek_runs(size as usize)?;
+            let runs = self.align_runs(runs, &mut input, size as usize)?;
+            return Ok(runs.iter().map(|(s, e)| e.saturating_sub(*s) as u64).sum());
+        }
+        if let Some(n) = self.count_mapped(&input)? {
+            return Ok(n);
+        }
+        let counter = Counter::new(self.mode != Mode::Byte);
+        Ok(self.emit_files(paths, input, counter)?.count())
+    }
+
+    /// The number of bytes the slice of `paths` takes, if known before reading
+    /// them: for byte ranges of seekable inputs, written as they are.
+    pub fn output_len(&self, paths: &[impl AsRef<Path>]) -> Result<Option<u64>> {
+        if !self.can_seek()
+            || self.format != Format::Raw
+            || self.normalizes_end()
+            || self.utf8.is_some()
+        {
+            return Ok(None);
+        }
+        let mut input = self.open_files(paths)?;
+        let Some(size) = input.size() else {
+            return Ok(None);
+        };
+        let (size, unit) = (size as usize, self.unit_size());
+        let runs = self.align_runs(self.seek_runs(size)?, &mut input, size)?;
+        let len = |&(s, e): &(usize, usize)| {
+            e.saturating_mul(unit)
+                .min(size)
+                .saturating_sub(s.saturating_mul(unit)) as u64
+        };
+        Ok(Some(runs.iter().map(len).sum()))
+    }
+
+    /// Reserve room on disk in `out` for the slice of `paths`, when its size
+    /// is known up front by [`Slicer::output_len`]. Fails if the disk is too
+    /// full to take it.
+    pub fn preallocate(&self, paths: &[impl AsRef<Path>], out: &File) -> Result<()> {
+        if !out.metadata()?.is_file() {
+            return Ok(());
+        }
+        if let Some(len) = self.output_len(paths)? {
+            platform::preallocate(out, len)?;
+        }
+        Ok(())
+    }
+
+    /// The number of records selected from a single mapped file, resolved
+    /// from its number of records alone, on several threads if it is big.
+    fn count_mapped(&self, input: &Concat) -> Result<Option<u64>> {
+        let Some(delim) = self.map_delimiter() else {
+            return Ok(None);
+        };
+        if self.needs_scan() || self.strip_bom {
+            return Ok(None);
+        }
+        let Some(file) = input
+            .single_file()?
+            .filter(|f| f.metadata().is_ok_and(|m| m.is_file()))
+        else {
+            return Ok(None);
+        };
+        let map = mmap::map(&file)?;
+        let len = mmap::count_records(map.as_deref().unwrap_or_default(), delim);
+        let len = len.min(self.max_records.unwrap_or(usize::MAX));
+        let skip = len.min(self.header as usize);
+        let len = len - skip;
+        self.selection().check(len)?;
+        let runs = match self.reversed()? {
+            Some(range) => vec![range.resolve(len)],
+            None if self.invert => complement_runs(&merge_runs(&self.ranges, len), len),
+            None => merge_runs(&self.ranges, len),
+        };
+        let n: usize = runs.iter().map(|(s, e)| e.saturating_sub(*s)).sum();
+        Ok(Some(
+            (skip + n.min(self.sample.unwrap_or(usize::MAX))) as u64,
+        ))
+    }
+
+    /// Like [`Slicer::slice_files`], but writing to a file or pipe. On Linux,
+    /// byte ranges are then moved with `copy_file_range(2)` to a regular file,
+    /// or `sendfile(2)` or `splice(2)`, without copying them through
+    /// userspace, or read ahead through io_uring with [`IoBackend::Uring`].
+    #[cfg(unix)]
+    pub fn slice_files_fd(
+        &self,
+        paths: &[impl AsRef<Path>],
+        out: impl Write + std::os::fd::AsFd,
+    ) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        let mut out = out;
+        #[cfg(target_os = "linux")]
+        if self.splice_files(paths, &mut out)? {
+            return Ok(());
+        }
+        self.slice_files(paths, out)
+    }
+
+    /// Slice each input on its own, preceded by a `==> name <==` banner as
+    /// `head` and `tail` print for multiple files. `-` stands for stdin.
+    ///
+    /// JSON output has no banners, just one envelope per input.
+    ///
+    /// With [`Slicer::jobs`], files are sliced on that many threads. With
+    /// [`Slicer::continue_on_error`], those that fail are reported and
+    /// skipped.
+    pub fn slice_separate(&self, paths: &[impl AsRef<Path> + Sync], out: impl Write) -> Result<()> {
+        if self.jobs > 1 && paths.len() > 1 {
+            return self.slice_parallel(paths, out);
+        }
+        let mut failures = Failures::new(self.continue_on_error, self.json_errors);
+        let mut out = Checked {
+            inner: out,
+            failed: false,
+        };
+        for (i, path) in paths.iter().enumerate() {
+            let res = self.slice_banner(i, path.as_ref(), &mut out);
+            if out.failed {
+                return res;
+            }
+            failures.check(path.as_ref(), res)?;
+        }
+        failures.finish(paths.len())
+    }
+
+    /// Slice input `i` of [`Slicer::slice_separate`] after its banner.
+    pub(crate) fn slice_banner(&self, i: usize, path: &Path, mut out: impl Write) -> Result<()> {
+        if self.format == Format::Json {
+            return self.slice_files(&[path], &mut out);
+        }
+        if i > 0 {
+            writeln!(out)?;
+        }
+        if path.as_os_str() == "-" {
+            writeln!(out, "==> standard input <==")?;
+        } else {
+            writeln!(out, "==> {} <==", path.display())?;
+        }
+        self.slice_files(&[path], &mut out)
+    }
+
+    /// The delimiter to scan for when slicing a mapped file, if the selection
+    /// can be resolved that way.
+    fn map_delimiter(&self) -> Option<u8> {
+        let contiguous = self.ranges.iter().all(|r| r.step.abs() == 1);
+        match self.mode {
+            // context is marked while streaming
+            Mode::Delimited(_) if self.context.is_some() => None,
+            Mode::Delimited(d) if contiguous => Some(d),
+            _ => None,
+        }
+    }
+
+    /// Emit the selected records of a regular file to `sink` through a memory
+    /// map, falling back to streaming for anything else, and hand it back.
+    fn emit_mapped<S: RecordSink>(
+        &self,
+        file: &File,
+        delim: u8,
+        index: Option<&LineIndex>,
+        mut sink: S,
+    ) -> Result<S> {
+        if !file.metadata()?.is_file() {
+            return self.emit_counted(file, sink);
+        }
+        let map = mmap::map(file)?;
+        let data = map.as_deref().unwrap_or_default();
+        sink.read_by(Strategy::Map);
+        let indexed = if index.is_some() {
+            ", with an index"
+        } else {
+            ""
+        };
+        debug!(
+            "looking for {:?} in a memory map of {} bytes{indexed}",
+            delim as char,
+            data.len()
+        );
+        let reversed = self.reversed()?;
+        let sel = self.selection();
+        if self.shuffle {
+            // the sample, if any, is picked before it is shuffled
+            let mut sampler = self.sampler(sink, None);
+            mmap::slice_records(data, delim, index, &sel, reversed, &mut sampler)?;
+            sink = sampler.finish()?;
+        } else {
+            mmap::slice_records(data, delim, index, &sel, reversed, &mut sink)?;
+        }
+        // leave the offset past what was mapped, as reading would, which
+        // --follow goes on from
+        let mut file = file;
+        file.seek(SeekFrom::Start(data.len() as u64))?;
+        if self.fadvise {
+            // pages are only dropped once nothing maps them
+            drop(map);
+            platform::advise(file, 0, 0, Advice::DontNeed);
+        }
+        Ok(sink)
+    }
+
+    /// The selection as sorted byte runs of an input of `len` bytes, if it is
+    /// that simple, as it is for a whole input whatever it is counted in.
+    pub(crate) fn byte_runs(&self, len: usize) -> Result<Option<Vec<(usize, usize)>>> {
+        if self.selects_all() && !self.prefixes() && !self.strip_bom {
+            return Ok(Some(vec![(0, len)]));
+        }
+        if !self.can_seek() || self.reversed()?.is_some() {
+            return Ok(None);
+        }
+        let unit = self.unit_size();
+        let runs = self.unit_runs(self.units(len));
+        // the last record may be short
+        let bytes = |(s, e): (usize, usize)| {
+            (
+                s.saturating_mul(unit).min(len),
+                e.saturating_mul(unit).min(len),
+            )
+        };
+        Ok(Some(runs.into_iter().map(bytes).collect()))
+    }
+
+    /// The selection as sorted runs of `units` records, or bytes, of an
+    /// input that can seek.
+    fn unit_runs(&self, units: usize) -> Vec<(usize, usize)> {
+        let runs = merge_runs(&self.ranges, units);
+        match self.invert {
+            true => complement_runs(&runs, units),
+            false => runs,
+        }
+    }
+
+    /// The runs of records, or bytes, selected from an input of `len` bytes
+    /// that can seek, reversed ones as they are, after checking that there
+    /// are enough of them.
+    fn seek_runs(&self, len: usize) -> Result<Vec<(usize, usize)>> {
+        let units = self.units(len);
+        self.selection().check(units)?;
+        Ok(match self.reversed()? {
+            Some(range) => vec![range.resolve(units)],
+            None => self.unit_runs(units),
+        })
+    }
+
+    /// Byte `runs` of `input`, of `len` bytes, snapped to the boundaries
+    /// of [`Slicer::align`] if set.
+    pub(crate) fn align_runs(
+        &self,
+        runs: Vec<(usize, usize)>,
+        input: &mut (impl Read + Seek),
+        len: usize,
+    ) -> Result<Vec<(usize, usize)>> {
+        let Some(align) = self.align else {
+            return Ok(runs);
+        };
+        if self.mode != Mode::Byte {
+            return Err("Only byte ranges can be aligned".into());
+        }
+        if self.reversed()?.is_some() {
+            return Err("Byte ranges read backwards cannot be aligned".into());
+        }
+        let len = self.units(len);
+        Ok(align::snap(&runs, align, self.align_inward, input, len)?)
+    }
+
+    /// Bytes in each unit ranges count when they can seek: 1, or the size
+    /// of fixed-size records.
+    fn unit_size(&self) -> usize {
+        self.mode.record_size().unwrap_or(1)
+    }
+
+    /// The number of units, counting a short last record, in `len` bytes, up
+    /// to the most that are read.
+    pub(crate) fn units(&self, len: usize) -> usize {
+        let units = len.div_ceil(self.unit_size());
+        units.min(self.max_records.unwrap_or(usize::MAX))
+    }
+
+    /// Whether the ranges can only be resolved once the input length is known.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn needs_len(&self) -> bool {
+        self.ranges.iter().any(|r| r.lookahead() > 0)
+    }
+
+    pub(crate) fn selection(&self) -> Selection<'_> {
+        Selection {
+            ranges: &self.ranges,
+            skip: self.header as usize,
+            invert: self.invert,
+            sample: self.sample,
+            seed: self.seed,
+            max: self.max_records,
+            strict: self.strict,
+            unit: self.mode.unit(),
+            memory: self.max_memory,
+        }
+    }
+
+    /// A sink keeping `n` of the records written to `sink`, or all of them,
+    /// and shuffling them if asked to.
+    fn sampler<S: RecordSink>(&self, sink: S, n: Option<usize>) -> Sampler<S> {
+        let n = n.unwrap_or(usize::MAX);
+        Sampler::new(sink, n, self.header as usize, Rng::new(self.seed))
+            .shuffle(self.shuffle)
+            .mode(self.mode.clone())
+            .max_memory(self.max_memory)
+    }
+
+    /// Whether the selection is the whole input as it is, which can then be
+    /// copied without looking for records.
+    fn selects_all(&self) -> bool {
+        let whole = match self.ranges.as_slice() {
+            [r] => {
+                matches!(r.start, None | Some(SliceIdx::FromStart(0)))
+                    && r.end.is_none()
+                    && r.step == 1
+            }
+            _ => false,
+        };
+        // JSON Lines are checked and blank ones dropped
+        whole
+            && !self.invert
+            && self.sample.is_none()
+            && !self.shuffle
+            && self.max_records.is_none()
+            && !self.needs_scan()
+            && !matches!(self.mode, Mode::Jsonl(_))
+    }
+
+    /// Whether records are written with something about them, like a
+    /// number or another terminator, which byte ranges cannot tell.
+    fn prefixes(&self) -> bool {
+        self.number
+            || self.byte_offsets
+            || self.format == Format::Json
+            || (self.output_delimiter.is_some() && self.format == Format::Raw)
+            || self.context.is_some()
+    }
+
+    fn sink<'a, W: Write>(&self, out: &'a mut W) -> std::io::Result<Sink<'a, W>> {
+        Ok(match self.format {
+            Format::Json => Sink::Json(JsonRecords::new(out, &self.source)?),
+            Format::Hex => Sink::Hex(HexDump::new(out)),
+            Format::Offsets => Sink::Offsets(Offsets::new(out, self.mode == Mode::Byte)),
+            Format::Raw if let Some(delim) = &self.output_delimiter => {
+                let out = Prefixed::new(out, self.number, self.byte_offsets);
+                Sink::Redelimited(Redelimited::new(out, self.mode.clone(), delim.clone()))
+            }
+            Format::Raw if self.prefixes() => {
+                Sink::Prefixed(Prefixed::new(out, self.number, self.byte_offsets))
+            }
+            Format::Raw => Sink::Plain(out),
+        })
+    }
+
+    /// This slicer, naming `paths` as the source in JSON output.
+    fn with_source(&self, paths: &[impl AsRef<Path>]) -> Cow<'_, Slicer> {
+        if self.format != Format::Json {
+            return Cow::Borrowed(self);
+        }
+        let source = paths
+            .iter()
+            .map(|p| p.as_ref().to_string_lossy().into_owned())
+            .collect();
+        Cow::Owned(Slicer {
+            source,
+            ..self.clone()
+        })
+    }
+
+    /// Whether the selection maps to plain byte offsets.
+    fn can_seek(&self) -> bool {
+        // strided ranges are not contiguous, so they are streamed instead
+        let contiguous = self.ranges.iter().all(|r| r.step.abs() == 1);
+        contiguous
+            && self.mode.record_size().is_some()
+            && !self.header
+            && !self.prefixes()
+            && self.sample.is_none()
+            && !self.shuffle
+            && !self.needs_scan()
+            && !self.strip_bom
+    }
+
+    fn emit_seekable<S: RecordSink>(
+        &self,
+        mut input: impl Read + Seek,
+        size: u64,
+        mut sink: S,
+    ) -> Result<S> {
+        // just use seek
+        sink.read_by(Strategy::Seek);
+        let (size, unit) = (size as usize, self.unit_size());
+        debug!(
+            "seeking in {size} bytes, {unit} to each of the {}",
+            self.mode.unit()
+        );
+        let units = self.units(size);
+        self.selection().check(units)?;
+        if let Some(range) = self.reversed()?
+            && self.align.is_none()
+        {
+            match unit {
+                1 => reverse_seek(range, units, self.buf_len(), &mut input, &mut sink)?,
+                _ => reverse_records(range, units, unit, size, &mut input, &mut sink)?,
+            }
+        } else {
+            let runs = self.byte_runs(size)?.unwrap_or_default();
+            for (start, end) in self.align_runs(runs, &mut input, size)? {
+                trace!("seek to byte {start}, copying {} bytes", end - start);
+                input.seek(std::io::SeekFrom::Start(start as u64))?;
+                // a run of several records is begun record by record
+                let step = if unit > 1 && sink.prefixed() {
+                    unit
+                } else {
+                    end - start
+                };
+                for pos in (start..end).step_by(step.max(1)) {
+                    sink.begin_record(pos / unit, pos as u64)?;
+                    let mut handle = (&mut input).take(step.min(end - pos) as u64);
+                    std::io::copy(&mut handle, &mut sink)?;
+                }
+            }
+        }
+        Ok(sink)
+    }
+
+    fn slice_stream(
+        &self,
+        stream: &mut BufReader<impl Read>,
+        out: &mut impl RecordSink,
+    ) -> Result<()> {
+        match self.mode {
+            Mode::Byte => self.slice_stream_with(CountModeByte, stream, out),
+            Mode::Record(size) => self.slice_stream_with(CountModeRecord::new(size), stream, out),
+            Mode::Delimited(d) => self.slice_stream_with(CountModeDelim(d), stream, out),
+            Mode::DelimitedStr(ref d) => {
+                self.slice_stream_with(CountModeDelimStr::new(d.clone()), stream, out)
+            }
+            Mode::Char => self.slice_stream_with(CountModeChar::default(), stream, out),
+            Mode::Csv => self.slice_stream_with(CountModeCsv::default(), stream, out),
+            Mode::Paragraph => self.slice_stream_with(CountModeParagraph::default(), stream, out),
+            Mode::DelimitedRegex(ref separator) => {
+                self.slice_stream_with(CountModeRegex::new(separator), stream, out)
+            }
+            Mode::Fasta => self.slice_stream_with(CountModeFasta::default(), stream, out),
+            Mode::Fastq => self.slice_stream_with(CountModeFastq::default(), stream, out),
+            Mode::Warc => self.slice_stream_with(CountModeWarc::default(), stream, out),
+            Mode::AnyLine => self.slice_stream_with(CountModeLines::new(false), stream, out),
+            Mode::AutoLine => self.slice_stream_with(CountModeLines::new(true), stream, out),
+            Mode::Jsonl(policy) => {
+                let reader = JsonlReader::new(stream, policy);
+                let mut stream = BufReader::with_capacity(self.buf_len(), reader);
+                self.slice_stream_with(CountModeDelim(b'\n'), &mut stream, out)
+            }
+        }
+    }
+
+    fn slice_stream_with(
+        &self,
+        mode: impl CountMode,
+        stream: &mut BufReader<impl Read>,
+        out: &mut impl RecordSink,
+    ) -> Result<()> {
+        if let Some(n) = self.context {
+            self.check_context()?;
+            let ranges = context::widen(&self.ranges, n);
+            // the context may reach past the input
+            let widened = Selection {
+                ranges: &ranges,
+                strict: false,
+                ..self.selection()
+            };
+            let mut marked = Marked::new(out, self.selection(), self.color);
+            slice_stream(&widened, mode, stream, &mut marked)?;
+            return Ok(marked.finish()?);
+        }
+        match self.reversed()? {
+            Some(range) => reverse_stream(range, &self.selection(), mode, stream, out),
+            None => slice_stream(&self.selection(), mode, stream, out),
+        }
+    }
+}
+
+/// Resolve ranges against a known length into sorted, disjoint `(start, end)` runs.
+pub(crate) fn merge_runs(ranges: &[SliceRange], len: usize) -> Vec<(usize, usize)> {
+    let mut runs: Vec<_> = ranges
+        .iter()
+        .map(|r| r.resolve(len))
+        .filter(|(s, e)| s < e)
+        .collect();
+    runs.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(runs.len());
+    for (s, e) in runs {
+        match merged.last_mut() {
+            Some(last) if s <= last.1 => last.1 = last.1.max(e),
+            _ => merged.push((s, e)),
+        }
+    }
+    merged
+}
+
+/// The gaps between sorted, disjoint runs within `0..len`.
+pub(crate) fn complement_runs(runs: &[(usize, usize)], len: usize) -> Vec<(usize, usize)> {
+    let mut gaps = Vec::with_capacity(runs.len() + 1);
+    let mut pos = 0;
+    for &(s, e) in runs {
+        if pos < s {
+            gaps.push((pos, s));
+        }
+        pos = e;
+    }
+    if pos < len {
+        gaps.push((pos, len));
+    }
+    gaps
+}
+
+/// Read the byte range backwards in blocks of `block` bytes, so reversing
+/// never buffers more than one.
+fn reverse_seek(
+    range: &SliceRange,
+    size: usize,
+    block: usize,
+    file: &mut (impl Read + Seek),
+    out: &mut impl RecordSink,
+) -> Result<()> {
+    let (lo, hi) = range.resolve(size);
+    let mut buf = vec![0; block.min(hi.saturating_sub(lo))];
+    let mut pos = hi;
+    while pos > lo {
+        let from = pos.saturating_sub(block).max(lo);
+        let block = &mut buf[..pos - from];
+        trace!(
+            "seek to byte {from}, copying {} bytes backwards",
+            pos - from
+        );
+        file.seek(std::io::SeekFrom::Start(from as u64))?;
+        file.read_exact(block)?;
+        block.reverse();
+        if out.prefixed() {
+            // every byte is out of place
+            for (i, b) in (from..pos).rev().zip(block.iter()) {
+                out.begin_record(i, i as u64)?;
+                out.write_all(&[*b])?;
+            }
+        } else {
+            out.write_all(block)?;
+        }
+        pos = from;
+    }
+    Ok(())
+}
+
+/// Read the range of `units` records of `unit` bytes, in an input of `len`
+/// bytes, backwards one record at a time.
+fn reverse_records(
+    range: &SliceRange,
+    units: usize,
+    unit: usize,
+    len: usize,
+    file: &mut (impl Read + Seek),
+    out: &mut impl RecordSink,
+) -> Result<()> {
+    let (lo, hi) = range.resolve(units);
+    let mut buf = vec![0; unit];
+    for i in (lo..hi).rev() {
+        let start = i * unit;
+        let record = &mut buf[..unit.min(len - start)];
+        trace!("seek to byte {start}, copying record {i}");
+        file.seek(std::io::SeekFrom::Start(start as u64))?;
+        file.read_exact(record)?;
+        out.begin_record(i, start as u64)?;
+        out.write_all(record)?;
+    }
+    Ok(())
+}
+
+/// Buffer the input, up to `sel.max` records, and emit the selected records
+/// of `range` back to front, after the first `sel.skip` records which are
+/// passed through as is.
+fn reverse_stream(
+    range: &SliceRange,
+    sel: &Selection,
+    mut mode: impl CountMode,
+    stream: &mut BufReader<impl Read>,
+    out: &mut impl RecordSink,
+) -> Result<()> {
+    let skip = sel.skip;
+    let limit = [range.upper_bound().map(|l| l + skip), sel.max]
+        .into_iter()
+        .flatten()
+        .min();
+    let mut data = Vec::new();
+    let mut ends = Vec::new(); // end offset of each record in data
+    'read: loop {
+        let buf = stream.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let mut pos = 0;
+        while pos < buf.len() {
+            if limit.is_some_and(|l| ends.len() >= l) {
+                break 'read;
+            }
+            let n = mode.inert(&buf[pos..]);
+            data.extend_from_slice(&buf[pos..pos + n]);
+            pos += n;
+            let Some(&c) = buf.get(pos) else { break };
+            if mode.ends_before(c) && ends.last().copied().unwrap_or(0) < data.len() {
+                ends.push(data.len());
+            }
+            if limit.is_some_and(|l| ends.len() >= l) {
+                break 'read;
+            }
+            data.push(c);
+            pos += 1;
+            if mode.count(c) > 0 {
+                ends.push(data.len());
+            }
+        }
+        stream.consume(pos);
+        sel.check_memory(data.len())?;
+    }
+    if ends.last().copied().unwrap_or(0) < data.len() {
+        ends.push(data.len());
+    }
+
+    let start = |i: usize| if i == 0 { 0 } else { ends[i - 1] };
+    let mut write = |i: usize| -> Result<()> {
+        out.begin_record(i, start(i) as u64)?;
+        out.write_all(&data[start(i)..ends[i]])?;
+        Ok(())
+    };
+    let skip = skip.min(ends.len());
+    sel.check(ends.len() - skip)?;
+    for i in 0..skip {
+        write(i)?;
+    }
+    let (lo, hi) = range.resolve(ends.len() - skip);
+    for i in (lo..hi).rev().step_by(range.step.unsigned_abs()) {
+        write(i + skip)?;
+    }
+    Ok(())
+}
+
+/// Which records of a stream are emitted.
+#[derive(Clone, Copy)]
+pub(crate) struct Selection<'a> {
+    pub ranges: &'a [SliceRange],
+    pub skip: usize, // leading records that are always emitted, e.g. a header
+    pub invert: bool,
+    pub sample: Option<usize>, // how many records to pick at random
+    pub seed: Option<u64>,
+    pub max: Option<usize>,    // records read before the input is cut off
+    pub strict: bool,          // whether ranges past the input are an error
+    pub unit: &'static str,    // what records are, for that error
+    pub memory: Option<usize>, // most bytes of the input to buffer
+}
+
+impl Selection<'_> {
+    /// Whether record `i` of `len` is emitted.
+    pub(crate) fn contains(&self, i: usize, len: usize) -> bool {
+        let skip = self.skip;
+        i < skip || self.invert != self.ranges.iter().any(|r| r.contains(i - skip, len - skip))
+    }
+
+    /// With `strict`, fail unless every range fits in `len` records, not
+    /// counting the skipped ones.
+    pub(crate) fn check(&self, len: usize) -> Result<()> {
+        match self.ranges.iter().find(|r| r.required_len() > len) {
+            Some(r) if self.strict => Err(format!(
+                "Range {r} is out of bounds: the input has {len} {}",
+                self.unit
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if `n` buffered bytes are more than the memory limit allows.
+    fn check_memory(&self, n: usize) -> Result<()> {
+        match self.memory {
+            Some(cap) if n > cap => {
+                Err(format!("Buffering the input takes more than {cap} bytes of memory").into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// How many records must follow record i before it can be decided.
+    fn depth(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(SliceRange::lookahead)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Exclusive bound on emitted records that holds for any input length.
+    fn limit(&self) -> Option<usize> {
+        if self.invert {
+            return None;
+        }
+        self.ranges
+            .iter()
+            .map(SliceRange::upper_bound)
+            .try_fold(0, |acc, b| b.map(|b| acc.max(b)))
+            .map(|l| l + self.skip)
+    }
+}
+
+/// Records read so far whose selection is not decided yet.
+#[derive(Default)]
+struct Pending {
+    data: Spool,
+    lens: VecDeque<(usize, usize)>, // lengths of the complete records, run-length coded
+    records: usize,                 // number of complete records
+    cur: usize,                     // bytes of the record being read
+    start: u64,                     // input offset of the oldest record
+}
+
+impl Pending {
+    fn push(&mut self, c: u8) -> std::io::Result<()> {
+        self.cur += 1;
+        self.data.push(c)
+    }
+
+    fn extend(&mut self, run: &[u8]) -> std::io::Result<()> {
+        self.cur += run.len();
+        self.data.extend(run)
+    }
+
+    fn end_record(&mut self) {
+        if self.cur > 0 {
+            // records of one length, like bytes, then take no memory each
+            match self.lens.back_mut() {
+                Some((n, k)) if *n == self.cur => *k += 1,
+                _ => self.lens.push_back((self.cur, 1)),
+            }
+            self.records += 1;
+            self.cur = 0;
+        }
+    }
+
+    /// Pop the oldest complete record, writing it out as record `i` if that
+    /// is given.
+    fn pop(&mut self, i: Option<usize>, out: &mut impl RecordSink) -> Result<()> {
+        let (n, k) = self.lens.front_mut().unwrap();
+        let n = *n;
+        *k -= 1;
+        if *k == 0 {
+            self.lens.pop_front();
+        }
+        self.records -= 1;
+        let out = match i {
+            Some(i) => {
+                out.begin_record(i, self.start)?;
+                Some(out as &mut dyn Write)
+            }
+            None => None,
+        };
+        self.data.take_front(n, out)?;
+        self.start += n as u64;
+        Ok(())
+    }
+}
+
+/// Selected bytes of the current input block, written out as one slice.
+#[derive(Default)]
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+impl Span {
+    /// Add `buf[from..to]`, first writing out what was gathered so far unless
+    /// it is adjacent.
+    fn add(&mut self, from: usize, to: usize, buf: &[u8], out: &mut impl Write) -> Result<()> {
+        if self.end != from {
+            self.flush(buf, out)?;
+            self.start = from;
+        }
+        self.end = to;
+        Ok(())
+    }
+
+    fn flush(&mut self, buf: &[u8], out: &mut impl Write) -> Result<()> {
+        if self.start < self.end {
+            out.write_all(&buf[self.start..self.end])?;
+        }
+        self.start = self.end;
+        Ok(())
+    }
+}
+
+fn slice_stream(
+    sel: &Selection,
+    mut mode: impl CountMode,
+    stream: &mut BufReader<impl Read>,
+    out: &mut impl RecordSink,
+) -> Result<()> {
+    // record i is decided once record i + depth exists: every from-end index
+    // then resolves the same way it would against the real length.
+    let depth = sel.depth();
+    let limit = sel.limit();
+    if limit == Some(0) {
+        return Ok(());
+    }
+
+    let mut i = 0; // index of the record being read
+    let mut at_start = true; // no byte of record i has been read yet
+    let mut emit = false; // whether record i is emitted (depth == 0 only)
+    // past the memory limit, records wait in a temporary file
+    let mut pending = Pending {
+        data: Spool::new(sel.memory),
+        ..Pending::default()
+    };
+    let mut base = 0; // input offset of the current block
+    let cut_off = |i: usize| sel.max.is_some_and(|m| i >= m);
+
+    // close record i; returns true once nothing more can be selected
+    let end_record = |i: &mut usize, pending: &mut Pending| {
+        *i += 1;
+        pending.end_record();
+        depth == 0 && limit.is_some_and(|l| *i >= l)
+    };
+
+    'read: loop {
+        if at_start && cut_off(i) {
+            break;
+        }
+        let buf = stream.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let mut pos = 0;
+        let mut span = Span::default();
+        while pos < buf.len() {
+            if !at_start {
+                // the rest of the record up to the next byte that matters
+                let n = mode.inert(&buf[pos..]);
+                if n > 0 {
+                    if depth == 0 {
+                        if emit {
+                            span.add(pos, pos + n, buf, out)?;
+                        }
+                    } else {
+                        pending.extend(&buf[pos..pos + n])?;
+                    }
+                    pos += n;
+                    continue;
+                }
+            }
+
+            let c = buf[pos];
+            if mode.ends_before(c) && !at_start {
+                if end_record(&mut i, &mut pending) {
+                    span.flush(buf, out)?;
+                    return Ok(());
+                }
+                at_start = true;
+            }
+
+            if at_start && cut_off(i) {
+                span.flush(buf, out)?;
+                stream.consume(pos);
+                break 'read;
+            }
+            if at_start {
+                if depth == 0 {
+                    emit = sel.contains(i, i + 1);
+                    if emit && out.prefixed() {
+                        span.flush(buf, out)?;
+                        out.begin_record(i, base + pos as u64)?;
+                    }
+                } else if i >= depth {
+                    let j = i - depth;
+                    pending.pop(sel.contains(j, i + 1).then_some(j), out)?;
+                    if limit.is_some_and(|l| i - depth + 1 >= l) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            if depth == 0 {
+                if emit {
+                    span.add(pos, pos + 1, buf, out)?;
+                }
+            } else {
+                pending.push(c)?;
+            }
+            pos += 1;
+
+            at_start = mode.count(c) > 0;
+            if at_start && end_record(&mut i, &mut pending) {
+                span.flush(buf, out)?;
+                return Ok(());
+            }
+        }
+        span.flush(buf, out)?;
+        stream.consume(pos);
+        base += pos as u64;
+    }
+
+    // the length is known now, resolve whatever is still buffered
+    let len = if at_start { i } else { i + 1 };
+    sel.check(len.saturating_sub(sel.skip))?;
+    pending.end_record();
+    for j in len - pending.records..len {
+        pending.pop(sel.contains(j, len).then_some(j), out)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::{io::Read, net::Shutdown};
+#[cfg(feature = "net")]
+use std::{
+    io::{Error, ErrorKind},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
+
+/// How long connecting to each address of a host may take.
+#[cfg(feature = "net")]
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether `url` is a `tcp://` input.
+#[cfg(feature = "net")]
+pub(crate) fn is_tcp(url: &str) -> bool {
+    url.starts_with("tcp://")
+}
+
+/// A connection to `url`, `tcp://host:port`, trying each address the host
+/// resolves to in turn.
+#[cfg(feature = "net")]
+pub(crate) fn connect_tcp(url: &str) -> std::io::Result<Conn> {
+    let addr = url.strip_prefix("tcp://").unwrap_or(url);
+    let addr = addr.strip_suffix('/').unwrap_or(addr);
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Invalid address, expected tcp://host:port",
+        )
+    };
+    let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let mut last = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(conn) => return Ok(Conn::Tcp(conn)),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| Error::new(ErrorKind::NotFound, "No address for the host")))
+}
+
+/// A connection to the Unix domain socket at `path`.
+#[cfg(unix)]
+pub(crate) fn connect_unix(path: &Path) -> std::io::Result<Conn> {
+    UnixStream::connect(path).map(Conn::Unix)
+}
+
+/// A stream socket an input is read from.
+pub(crate) enum Conn {
+    #[cfg(feature = "net")]
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Conn {
+    pub(crate) fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            #[cfg(feature = "net")]
+            Conn::Tcp(s) => s.try_clone().map(Conn::Tcp),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.try_clone().map(Conn::Unix),
+        }
+    }
+
+    fn shutdown(&self) {
+        let _ = match self {
+            #[cfg(feature = "net")]
+            Conn::Tcp(s) => s.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            #[cfg(feature = "net")]
+            Conn::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+/// `inner`, read from a connection that is shut down once it is dropped:
+/// the peer sees that nothing more is read, and a read of it still blocked
+/// in another thread, as when the time limit hit, returns.
+pub(crate) struct Hangup<R> {
+    inner: R,
+    conn: Conn,
+}
+
+impl<R> Hangup<R> {
+    pub(crate) fn new(inner: R, conn: Conn) -> Self {
+        Self { inner, conn }
+    }
+}
+
+impl<R: Read> Read for Hangup<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R> Drop for Hangup<R> {
+    fn drop(&mut self) {
+        self.conn.shutdown();
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    fs::File,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    os::{
+        fd::{AsFd, AsRawFd},
+        unix::fs::FileTypeExt,
+    },
+    path::Path,
+};
+
+use crate::{
+    Format, IoBackend, Result, Slicer,
+    compress::{Compression, MAGIC_LEN},
+    input::Concat,
+    platform::{self, Advice},
+    trace::{debug, trace},
+    uring,
+};
+
+/// Most bytes moved by one system call.
+const CHUNK: usize = 1 << 20;
+
+/// Bytes moved before they are dropped from the page cache, with fadvise.
+const ADVISED_PIECE: usize = 8 << 20;
+
+/// Move up to `len` bytes with `call`, a copy_file_range(2), sendfile(2) or
+/// splice(2) wrapper given the size of the next chunk. Returns how many bytes
+/// were moved, fewer at EOF, or None if the first call shows the descriptors
+/// are unsupported.
+fn transfer(len: u64, mut call: impl FnMut(usize) -> isize) -> std::io::Result<Option<u64>> {
+    let mut moved = 0;
+    while moved < len {
+        let n = call((len - moved).min(CHUNK as u64) as usize);
+        if n < 0 {
+            let e = std::io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                // EXDEV between filesystems, EBADF for an O_APPEND output
+                Some(
+                    libc::EINVAL | libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::EBADF,
+                ) if moved == 0 => {
+                    return Ok(None);
+                }
+                _ => return Err(e),
+            }
+        }
+        if n == 0 {
+            break;
+        }
+        moved += n as u64;
+    }
+    Ok(Some(moved))
+}
+
+fn send_file(file: &File, start: u64, len: u64, out: &impl AsFd) -> std::io::Result<Option<u64>> {
+    let (fd_in, fd_out) = (file.as_raw_fd(), out.as_fd().as_raw_fd());
+    let mut off = start as libc::off_t;
+    // SAFETY: both descriptors stay open for the whole transfer
+    transfer(len, |n| unsafe {
+        libc::sendfile(fd_out, fd_in, &mut off, n)
+    })
+}
+
+/// Like [`send_file`], but to a regular file, which lets the filesystem share
+/// the blocks instead of copying them where it can (reflinks).
+fn copy_range(file: &File, start: u64, len: u64, out: &impl AsFd) -> std::io::Result<Option<u64>> {
+    let (fd_in, fd_out) = (file.as_raw_fd(), out.as_fd().as_raw_fd());
+    let mut off = start as _;
+    // SAFETY: both descriptors stay open for the whole transfer; the output
+    // is written at, and advances, its own file offset
+    transfer(len, |n| unsafe {
+        libc::copy_file_range(fd_in, &mut off, fd_out, std::ptr::null_mut(), n, 0)
+    })
+}
+
+fn splice_pipe(pipe: &File, len: u64, out: &impl AsFd) -> std::io::Result<Option<u64>> {
+    let (fd_in, fd_out) = (pipe.as_raw_fd(), out.as_fd().as_raw_fd());
+    let (null_in, null_out) = (std::ptr::null_mut(), std::ptr::null_mut());
+    // SAFETY: both descriptors stay open for the whole transfer
+    transfer(len, |n| unsafe {
+        libc::splice(fd_in, null_in, fd_out, null_out, n, libc::SPLICE_F_MOVE)
+    })
+}
+
+impl Slicer {
+    /// Move the selected byte runs of `paths` to `out` inside the kernel.
+    /// Returns false without touching anything unless the input is a single
+    /// plain file or stdin is a pipe.
+    pub(crate) fn splice_files(
+        &self,
+        paths: &[impl AsRef<Path>],
+        out: &mut (impl Write + AsFd),
+    ) -> Result<bool> {
+        if self.byte_runs(0)?.is_none()
+            || self.direct
+            || self.archive_member.is_some()
+            || self.normalizes_end()
+            || self.format != Format::Raw
+            || self.utf8.is_some()
+        {
+            return Ok(false);
+        }
+        if let [path] = paths
+            && path.as_ref().as_os_str() == "-"
+            && self.splice_stdin(out)?
+        {
+            return Ok(true);
+        }
+        match Concat::open(paths, None, self.decompress, None)?.single_file()? {
+            Some(file) => self.send_runs(file, out).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    fn send_runs(&self, mut file: File, out: &mut (impl Write + AsFd)) -> Result<()> {
+        let size = file.metadata()?.len();
+        self.selection().check(self.units(size as usize))?;
+        let runs = self.byte_runs(size as usize)?.unwrap_or_default();
+        let runs = self.align_runs(runs, &mut file, size as usize)?;
+        if self.fadvise {
+            platform::advise(&file, 0, 0, Advice::Sequential);
+        }
+        debug!("moving {} byte run(s) of a {size} byte file", runs.len());
+        if self.io_backend == IoBackend::Uring {
+            out.flush()?;
+            if uring::copy_runs(&file, &runs, out)? {
+                debug!("copied through io_uring");
+                if self.fadvise {
+                    platform::advise(&file, 0, 0, Advice::DontNeed);
+                }
+                return Ok(());
+            }
+            debug!("io_uring is not available, falling back");
+        }
+        // with fadvise, runs are sent in pieces dropped from the cache once sent
+        let piece = if self.fadvise {
+            ADVISED_PIECE
+        } else {
+            usize::MAX
+        };
+        let pieces = runs.iter().flat_map(|&(s, e)| {
+            (s..e)
+                .step_by(piece)
+                .map(move |p| (p, e.min(p.saturating_add(piece))))
+        });
+        let out_file = File::from(out.as_fd().try_clone_to_owned()?);
+        let mut ranged = out_file.metadata()?.is_file();
+        let mut kernel = true;
+        for (s, e) in pieces {
+            let (s, len) = (s as u64, (e - s) as u64);
+            trace!("copying {len} bytes from byte {s}");
+            if ranged && copy_range(&file, s, len, out)?.is_none() {
+                debug!("copy_file_range(2) is not available, trying sendfile(2)");
+                ranged = false;
+            }
+            if !ranged && kernel && send_file(&file, s, len, out)?.is_none() {
+                debug!("sendfile(2) is not available, copying in userspace");
+                kernel = false;
+            }
+            if !ranged && !kernel {
+                file.seek(SeekFrom::Start(s))?;
+                std::io::copy(&mut (&file).take(len), out)?;
+            }
+            if self.fadvise {
+                platform::advise(&file, s, len, Advice::DontNeed);
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn splice_stdin(&self, out: &mut (impl Write + AsFd)) -> Result<bool> {
+        let pipe = File::from(std::io::stdin().as_fd().try_clone_to_owned()?);
+        // the pipe is spliced as it comes, without its length, so not when
+        // ranges from the end need that length up front, a deadline has to
+        // interrupt a splice(2) waiting on it, --strict checks whether the
+        // ranges reach past its end, or --align peeks around them
+        if !pipe.metadata()?.file_type().is_fifo()
+            || self.needs_len()
+            || self.time_limit.is_some()
+            || self.strict
+            || self.align.is_some()
+        {
+            return Ok(false);
+        }
+
+        // peeking consumes the bytes, so they are written out by hand
+        let mut prefix = Vec::new();
+        if self.decompress {
+            (&pipe).take(MAGIC_LEN as u64).read_to_end(&mut prefix)?;
+            if Compression::from_magic(&prefix).is_some() {
+                self.slice_reader(Cursor::new(prefix).chain(pipe), out)?;
+                return Ok(true);
+            }
+        }
+        let plen = prefix.len() as u64;
+        debug!("splicing byte runs from the stdin pipe");
+
+        let runs = self.byte_runs(usize::MAX)?.unwrap_or_default();
+        let mut pos = plen; // stream offset of the next byte in the pipe
+        let mut kernel = true;
+        for (s, e) in runs {
+            let (s, e) = (s as u64, e as u64);
+            if s < plen {
+                out.write_all(&prefix[s as usize..e.min(plen) as usize])?;
+            }
+            let skip = s.saturating_sub(pos);
+            pos += std::io::copy(&mut (&pipe).take(skip), &mut std::io::sink())?;
+            if pos < s {
+                break; // EOF
+            }
+            if e <= pos {
+                continue;
+            }
+
+            out.flush()?;
+            let len = e - pos;
+            let moved = match kernel.then(|| splice_pipe(&pipe, len, out)).transpose()? {
+                Some(Some(n)) => n,
+                _ => {
+                    kernel = false;
+                    std::io::copy(&mut (&pipe).take(len), out)?
+                }
+            };
+            pos += moved;
+            if moved < len {
+                break;
+            }
+        }
+        out.flush()?;
+        Ok(true)
+    }
+}
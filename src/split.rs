@@ -0,0 +1,112 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{Mode, Result, Slicer, output::RecordSink};
+
+/// Sink writing records to numbered files `PREFIX00`, `PREFIX01`, ...,
+/// starting the next one after every `size` records, or bytes in byte mode.
+/// Files are only created once something goes into them.
+struct Chunks<'a> {
+    prefix: &'a str,
+    size: usize,
+    bytes: bool,
+    files: usize, // files created so far
+    left: usize,  // units the current file still takes
+    buffer: usize,
+    cur: Option<BufWriter<File>>,
+}
+
+impl<'a> Chunks<'a> {
+    fn new(prefix: &'a str, size: usize, bytes: bool, buffer: usize) -> Self {
+        Self {
+            prefix,
+            size,
+            bytes,
+            files: 0,
+            left: 0,
+            buffer,
+            cur: None,
+        }
+    }
+
+    /// Finish the current file and start the next one.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.finish()?;
+        let path = format!("{}{:02}", self.prefix, self.files);
+        let file = File::create(&path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{path}: {e}")))?;
+        self.cur = Some(BufWriter::with_capacity(self.buffer, file));
+        self.files += 1;
+        self.left = self.size;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self.cur.take() {
+            Some(mut out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for Chunks<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.bytes {
+            if self.cur.is_none() {
+                self.rotate()?;
+            }
+            return self.cur.as_mut().unwrap().write(buf);
+        }
+        if self.left == 0 {
+            self.rotate()?;
+        }
+        let n = buf.len().min(self.left);
+        let n = self.cur.as_mut().unwrap().write(&buf[..n])?;
+        self.left -= n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.cur {
+            Some(out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl RecordSink for Chunks<'_> {
+    fn prefixed(&self) -> bool {
+        !self.bytes
+    }
+
+    fn begin_record(&mut self, _i: usize, _offset: u64) -> std::io::Result<()> {
+        if self.bytes {
+            return Ok(());
+        }
+        if self.left == 0 {
+            self.rotate()?;
+        }
+        self.left -= 1;
+        Ok(())
+    }
+}
+
+impl Slicer {
+    /// Write the selection of `paths`, read as with [`Slicer::slice_files`],
+    /// to numbered files named `prefix` followed by `00`, `01`, ..., each
+    /// holding `size` records, or bytes in byte mode, except maybe the last.
+    /// Records are written as they are, without prefixes or other formats.
+    pub fn split_files(&self, paths: &[impl AsRef<Path>], prefix: &str, size: usize) -> Result<()> {
+        if size == 0 {
+            return Err("The split size must be at least 1".into());
+        }
+        let input = self.open_files(paths)?;
+        let chunks = Chunks::new(prefix, size, self.mode == Mode::Byte, self.buf_len());
+        let mut chunks = self.emit_files(paths, input, chunks)?;
+        chunks.finish()?;
+        Ok(())
+    }
+}
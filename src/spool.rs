@@ -0,0 +1,168 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use crate::platform;
+
+/// Bytes gathered before they are written to the file at once.
+const BATCH: usize = 64 * 1024;
+
+/// Queue of bytes, added at the back and taken from the front. It is kept in
+/// memory up to a budget, and past it in a temporary file used as a ring,
+/// which only grows as far as the queue ever gets.
+#[derive(Default)]
+pub(crate) struct Spool {
+    mem: VecDeque<u8>,
+    budget: Option<usize>,
+    // once spilled, the queue is the rest of front, then the ring, then back
+    ring: Option<Ring>,
+    front: Vec<u8>, // oldest bytes, read back from the ring
+    taken: usize,   // bytes of front taken already
+    back: Vec<u8>,  // newest bytes, not in the ring yet
+}
+
+impl Spool {
+    pub fn new(budget: Option<usize>) -> Self {
+        Self {
+            budget,
+            ..Self::default()
+        }
+    }
+
+    pub fn push(&mut self, c: u8) -> std::io::Result<()> {
+        self.extend(&[c])
+    }
+
+    pub fn extend(&mut self, run: &[u8]) -> std::io::Result<()> {
+        if self.ring.is_none() {
+            if self.budget.is_none_or(|b| self.mem.len() + run.len() <= b) {
+                self.mem.extend(run);
+                return Ok(());
+            }
+            self.spill()?;
+        }
+        self.back.extend_from_slice(run);
+        if self.back.len() >= BATCH {
+            self.ring.as_mut().unwrap().append(&self.back)?;
+            self.back.clear();
+        }
+        Ok(())
+    }
+
+    /// Move what is in memory to the file.
+    fn spill(&mut self) -> std::io::Result<()> {
+        let mut ring = Ring::new()?;
+        let (a, b) = self.mem.as_slices();
+        ring.append(a)?;
+        ring.append(b)?;
+        self.mem = VecDeque::new();
+        self.ring = Some(ring);
+        Ok(())
+    }
+
+    /// Remove the first `n` bytes, writing them to `out` if given.
+    pub fn take_front(&mut self, n: usize, mut out: Option<&mut dyn Write>) -> std::io::Result<()> {
+        let Some(ring) = &mut self.ring else {
+            if let Some(out) = out {
+                let (a, b) = self.mem.as_slices();
+                let an = n.min(a.len());
+                out.write_all(&a[..an])?;
+                out.write_all(&b[..n - an])?;
+            }
+            self.mem.drain(..n);
+            return Ok(());
+        };
+        let mut left = n;
+        while left > 0 {
+            if self.taken == self.front.len() {
+                self.taken = 0;
+                if ring.len > 0 {
+                    ring.take_front(ring.len.min(BATCH as u64) as usize, &mut self.front)?;
+                } else {
+                    self.front.clear();
+                    std::mem::swap(&mut self.front, &mut self.back);
+                }
+            }
+            let k = left.min(self.front.len() - self.taken);
+            if let Some(out) = &mut out {
+                out.write_all(&self.front[self.taken..self.taken + k])?;
+            }
+            self.taken += k;
+            left -= k;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffer in a temporary file: `len` bytes from offset `head`, wrapping
+/// around at `cap`.
+struct Ring {
+    file: File,
+    cap: u64,
+    head: u64,
+    len: u64,
+}
+
+impl Ring {
+    fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            file: platform::temp_file()?,
+            cap: 0,
+            head: 0,
+            len: 0,
+        })
+    }
+
+    fn append(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        let n = buf.len() as u64;
+        if self.len + n > self.cap {
+            self.grow(self.len + n)?;
+        }
+        let tail = (self.head + self.len) % self.cap.max(1);
+        let first = n.min(self.cap - tail) as usize;
+        self.file.seek(SeekFrom::Start(tail))?;
+        self.file.write_all(&buf[..first])?;
+        if first < buf.len() {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(&buf[first..])?;
+        }
+        self.len += n;
+        Ok(())
+    }
+
+    /// Make room for `need` bytes, unwrapping the bytes that wrapped around
+    /// by moving them past the old end.
+    fn grow(&mut self, need: u64) -> std::io::Result<()> {
+        let cap = need.max(self.cap * 2).max(BATCH as u64 * 16);
+        let wrapped = (self.head + self.len).saturating_sub(self.cap);
+        let mut buf = vec![0; BATCH];
+        let mut done = 0;
+        while done < wrapped {
+            let k = (wrapped - done).min(BATCH as u64) as usize;
+            self.file.seek(SeekFrom::Start(done))?;
+            self.file.read_exact(&mut buf[..k])?;
+            self.file.seek(SeekFrom::Start(self.cap + done))?;
+            self.file.write_all(&buf[..k])?;
+            done += k as u64;
+        }
+        self.cap = cap;
+        Ok(())
+    }
+
+    /// Remove the first `n` bytes into `buf`.
+    fn take_front(&mut self, n: usize, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        buf.resize(n, 0);
+        let first = (n as u64).min(self.cap - self.head) as usize;
+        self.file.seek(SeekFrom::Start(self.head))?;
+        self.file.read_exact(&mut buf[..first])?;
+        if first < n {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.read_exact(&mut buf[first..])?;
+        }
+        self.head = (self.head + n as u64) % self.cap;
+        self.len -= n as u64;
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+    time::Instant,
+};
+
+use crate::{Result, Slicer, input::Concat};
+
+/// Reader copying everything read through it to `copy`.
+struct Tee<R, W> {
+    inner: R,
+    copy: W,
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+impl Slicer {
+    /// Slice the concatenated `paths` as a stream to `out`, while copying all
+    /// of them as they are, still compressed if they are, to `tee`. They
+    /// are read to the end even when the slice ends before, so `tee` gets
+    /// everything, like tee(1) would.
+    pub fn slice_tee(
+        &self,
+        paths: &[impl AsRef<Path>],
+        tee: impl Write,
+        out: impl Write,
+    ) -> Result<()> {
+        let deadline = self.time_limit.map(|t| Instant::now() + t);
+        let mut input = Tee {
+            inner: Concat::open(paths, None, false, deadline)?,
+            copy: tee,
+        };
+        self.slice_reader(&mut input, out)?;
+        std::io::copy(&mut input, &mut std::io::sink())?;
+        input.copy.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// Writer passing at most `rate` bytes a second on to the one it wraps, with
+/// a token bucket holding a tenth of a second's worth: bursts up to that size
+/// go out at once, anything more waits for the bucket to refill.
+pub struct RateLimit<W> {
+    inner: W,
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl<W: Write> RateLimit<W> {
+    /// Limit writes to `inner` to `rate` bytes a second, which must not be 0.
+    pub fn new(inner: W, rate: u64) -> Self {
+        let rate = rate as f64;
+        let burst = (rate / 10.0).max(1.0);
+        Self {
+            inner,
+            rate,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let secs = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + secs * self.rate).min(self.burst);
+        self.last = now;
+    }
+}
+
+impl<W: Write> Write for RateLimit<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let want = buf.len().min(self.burst as usize);
+        self.refill();
+        if self.tokens < want as f64 {
+            let wait = (want as f64 - self.tokens) / self.rate;
+            std::thread::sleep(Duration::from_secs_f64(wait));
+            self.refill();
+        }
+        let n = self.inner.write(&buf[..want])?;
+        self.tokens -= n as f64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
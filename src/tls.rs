@@ -0,0 +1,200 @@
+use std::{
+    ffi::{CStr, CString, c_char, c_int, c_long, c_ulong, c_void},
+    io::{Error, ErrorKind, Read, Write},
+    net::{IpAddr, TcpStream},
+    os::fd::AsRawFd,
+    sync::OnceLock,
+};
+
+// opaque OpenSSL types
+#[repr(C)]
+struct SslMethod([u8; 0]);
+#[repr(C)]
+struct SslCtx([u8; 0]);
+#[repr(C)]
+struct Ssl([u8; 0]);
+#[repr(C)]
+struct VerifyParam([u8; 0]);
+
+// from openssl/ssl.h
+const SSL_VERIFY_PEER: c_int = 1;
+const SSL_OP_IGNORE_UNEXPECTED_EOF: u64 = 1 << 7;
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+const TLSEXT_NAMETYPE_HOST_NAME: c_long = 0;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+const X509_V_OK: c_long = 0;
+
+#[link(name = "ssl")]
+unsafe extern "C" {
+    fn TLS_client_method() -> *const SslMethod;
+    fn SSL_CTX_new(method: *const SslMethod) -> *mut SslCtx;
+    fn SSL_CTX_set_default_verify_paths(ctx: *mut SslCtx) -> c_int;
+    fn SSL_CTX_set_verify(ctx: *mut SslCtx, mode: c_int, callback: *const c_void);
+    fn SSL_CTX_set_options(ctx: *mut SslCtx, options: u64) -> u64;
+    fn SSL_new(ctx: *mut SslCtx) -> *mut Ssl;
+    fn SSL_free(ssl: *mut Ssl);
+    fn SSL_set_fd(ssl: *mut Ssl, fd: c_int) -> c_int;
+    fn SSL_ctrl(ssl: *mut Ssl, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_set1_host(ssl: *mut Ssl, host: *const c_char) -> c_int;
+    fn SSL_get0_param(ssl: *mut Ssl) -> *mut VerifyParam;
+    fn SSL_connect(ssl: *mut Ssl) -> c_int;
+    fn SSL_read(ssl: *mut Ssl, buf: *mut c_void, num: c_int) -> c_int;
+    fn SSL_write(ssl: *mut Ssl, buf: *const c_void, num: c_int) -> c_int;
+    fn SSL_get_error(ssl: *const Ssl, ret: c_int) -> c_int;
+    fn SSL_get_verify_result(ssl: *const Ssl) -> c_long;
+}
+
+#[link(name = "crypto")]
+unsafe extern "C" {
+    fn ERR_get_error() -> c_ulong;
+    fn ERR_clear_error();
+    fn ERR_error_string_n(e: c_ulong, buf: *mut c_char, len: usize);
+    fn X509_VERIFY_PARAM_set1_ip_asc(param: *mut VerifyParam, ip: *const c_char) -> c_int;
+    fn X509_verify_cert_error_string(n: c_long) -> *const c_char;
+}
+
+/// The client context every connection is made from, verifying servers
+/// against the system's certificates, or those `SSL_CERT_FILE` and
+/// `SSL_CERT_DIR` name.
+struct Context(*mut SslCtx);
+
+// SAFETY: a context is safe to share once set up, only connections are not
+unsafe impl Send for Context {}
+unsafe impl Sync for Context {}
+
+fn context() -> std::io::Result<&'static Context> {
+    static CONTEXT: OnceLock<Option<Context>> = OnceLock::new();
+    // SAFETY: the context is set up before it is shared
+    let ctx = CONTEXT.get_or_init(|| unsafe {
+        let ctx = SSL_CTX_new(TLS_client_method());
+        if ctx.is_null() || SSL_CTX_set_default_verify_paths(ctx) != 1 {
+            return None;
+        }
+        SSL_CTX_set_verify(ctx, SSL_VERIFY_PEER, std::ptr::null());
+        // servers closing the connection without telling are taken to be done
+        SSL_CTX_set_options(ctx, SSL_OP_IGNORE_UNEXPECTED_EOF);
+        Some(Context(ctx))
+    });
+    ctx.as_ref().ok_or_else(last_error)
+}
+
+/// The error OpenSSL queued last, clearing the queue.
+fn last_error() -> Error {
+    // SAFETY: the buffer is as long as it is said to be
+    let msg = unsafe {
+        let mut buf = [0 as c_char; 256];
+        let e = ERR_get_error();
+        ERR_clear_error();
+        if e == 0 {
+            return Error::other("TLS error");
+        }
+        ERR_error_string_n(e, buf.as_mut_ptr(), buf.len());
+        CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+    };
+    Error::other(msg)
+}
+
+/// A TLS connection over TCP to a server verified to be `host`.
+pub(crate) struct Stream {
+    ssl: *mut Ssl,
+    tcp: TcpStream, // owns the descriptor OpenSSL reads and writes
+}
+
+// SAFETY: a connection may move to another thread, as long as only one uses it
+unsafe impl Send for Stream {}
+
+impl Stream {
+    pub(crate) fn connect(tcp: TcpStream, host: &str) -> std::io::Result<Self> {
+        let ctx = context()?;
+        let name = CString::new(host).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+        // SAFETY: the connection is freed if it is not handed out, and the
+        // descriptor outlives it
+        let ssl = unsafe { SSL_new(ctx.0) };
+        if ssl.is_null() {
+            return Err(last_error());
+        }
+        let this = Self { ssl, tcp };
+        // SAFETY: the connection and the name are valid for every call
+        unsafe {
+            let set = match host.parse::<IpAddr>() {
+                // an address is not sent as a server name, only checked
+                Ok(_) => X509_VERIFY_PARAM_set1_ip_asc(SSL_get0_param(ssl), name.as_ptr()),
+                Err(_) => {
+                    SSL_ctrl(
+                        ssl,
+                        SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                        TLSEXT_NAMETYPE_HOST_NAME,
+                        name.as_ptr() as *mut c_void,
+                    );
+                    SSL_set1_host(ssl, name.as_ptr())
+                }
+            };
+            if set != 1 || SSL_set_fd(ssl, this.tcp.as_raw_fd()) != 1 {
+                return Err(last_error());
+            }
+            if SSL_connect(ssl) != 1 {
+                let verified = SSL_get_verify_result(ssl);
+                if verified != X509_V_OK {
+                    ERR_clear_error();
+                    let why = CStr::from_ptr(X509_verify_cert_error_string(verified));
+                    let msg = format!(
+                        "Certificate of {host} not verified: {}",
+                        why.to_string_lossy()
+                    );
+                    return Err(Error::other(msg));
+                }
+                return Err(last_error());
+            }
+        }
+        Ok(this)
+    }
+
+    /// The error of the call on the connection that returned `ret`.
+    fn error(&self, ret: c_int) -> std::io::Result<usize> {
+        // SAFETY: the connection is valid until dropped
+        match unsafe { SSL_get_error(self.ssl, ret) } {
+            SSL_ERROR_ZERO_RETURN => Ok(0),
+            SSL_ERROR_SYSCALL => match Error::last_os_error() {
+                e if e.raw_os_error() == Some(0) => Ok(0),
+                e => Err(e),
+            },
+            _ => Err(last_error()),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        // SAFETY: at most `len` bytes are written to `buf`
+        let n = unsafe { SSL_read(self.ssl, buf.as_mut_ptr().cast(), len) };
+        match n {
+            1.. => Ok(n as usize),
+            _ => self.error(n),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        // SAFETY: at most `len` bytes are read from `buf`
+        let n = unsafe { SSL_write(self.ssl, buf.as_ptr().cast(), len) };
+        match n {
+            1.. => Ok(n as usize),
+            _ => self.error(n).and(Err(ErrorKind::WriteZero.into())),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        // SAFETY: nothing uses the connection after this
+        unsafe { SSL_free(self.ssl) };
+    }
+}
@@ -0,0 +1,74 @@
+//! Logging for `-v` and `-vv`. This is meant to be `tracing` with a
+//! `tracing-subscriber` writing to stderr, but neither crate is in the
+//! offline registry this is built from, so a small logger stands in: the
+//! [`debug!`] and [`trace!`] macros take the same arguments as those of
+//! `tracing` and log at its `DEBUG` and `TRACE` levels, so that either can
+//! be swapped for the other by changing the imports.
+
+use std::{
+    io::Write,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU8, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Log what slicing does to stderr at `level`: 1 for how inputs are read,
+/// the buffers used and how long each phase takes, like `tracing`'s
+/// `DEBUG`, 2 for every seek and copy as well, like `TRACE`, 0 for nothing,
+/// which is the default. Times are logged from the first call.
+pub fn set_verbosity(level: u8) {
+    START.get_or_init(Instant::now);
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+pub(crate) fn enabled(level: u8) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level
+}
+
+pub(crate) fn write(args: std::fmt::Arguments) {
+    let at = START.get().map_or(Duration::ZERO, Instant::elapsed);
+    // like the progress line, logging never fails the slice
+    let _ = writeln!(
+        std::io::stderr().lock(),
+        "slice: [{:10.6}] {args}",
+        at.as_secs_f64()
+    );
+}
+
+/// Log a message at level 1 of [`set_verbosity`], formatted only if it is
+/// logged.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::trace::enabled(1) {
+            $crate::trace::write(format_args!($($arg)*));
+        }
+    };
+}
+pub(crate) use debug;
+
+/// Log a message at level 2 of [`set_verbosity`], formatted only if it is
+/// logged.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::trace::enabled(2) {
+            $crate::trace::write(format_args!($($arg)*));
+        }
+    };
+}
+pub(crate) use trace;
+
+/// Run `f`, logging how long `phase` took at level 1.
+pub(crate) fn timed<T>(phase: &str, f: impl FnOnce() -> T) -> T {
+    if !enabled(1) {
+        return f();
+    }
+    let start = Instant::now();
+    let out = f();
+    write(format_args!("{phase} took {:?}", start.elapsed()));
+    out
+}
@@ -0,0 +1,420 @@
+use std::{
+    fs::File,
+    os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr::NonNull,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Chunks read ahead while the oldest one is written.
+const DEPTH: usize = 8;
+
+/// Bytes moved by one request.
+const CHUNK: usize = 1 << 20;
+
+// from linux/io_uring.h
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+const IORING_FEAT_RW_CUR_POS: u32 = 1 << 3;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)] // laid out as the kernel has it
+struct SqOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct CqOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqOffsets,
+    cq_off: CqOffsets,
+}
+
+/// A submission queue entry, for reads and writes.
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    file_index: u32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// Memory shared with the kernel.
+struct Map {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl Map {
+    fn new(fd: &OwnedFd, len: usize, offset: libc::off_t) -> std::io::Result<Self> {
+        // SAFETY: a fresh shared mapping of the ring, owned by this Map
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        let ptr = NonNull::new(ptr.cast()).ok_or(std::io::ErrorKind::InvalidData)?;
+        Ok(Self { ptr, len })
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        // SAFETY: the kernel gives offsets within the mapping
+        unsafe { self.ptr.as_ptr().add(offset as usize).cast() }
+    }
+
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        // SAFETY: ring indices are aligned u32s the kernel also updates atomically
+        unsafe { &*self.at::<AtomicU32>(offset) }
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is not used past here
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+    }
+}
+
+/// An io_uring instance, with its submission and completion queues in one
+/// mapping.
+struct Ring {
+    rings: Map,
+    sqes: Map,
+    params: Params,
+    fd: OwnedFd,
+    queued: u32, // entries pushed but not yet submitted
+}
+
+impl Ring {
+    /// A ring of `entries` entries, or None if the kernel has no io_uring,
+    /// forbids it, or is older than 5.6.
+    fn new(entries: u32) -> std::io::Result<Option<Self>> {
+        let mut params = Params::default();
+        // SAFETY: params is a valid io_uring_params for the kernel to fill
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            let e = std::io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENOSYS | libc::EPERM | libc::EINVAL) => Ok(None),
+                _ => Err(e),
+            };
+        }
+        // SAFETY: the descriptor was just created and is owned by nobody else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let needed = IORING_FEAT_SINGLE_MMAP | IORING_FEAT_RW_CUR_POS;
+        if params.features & needed != needed {
+            return Ok(None);
+        }
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let rings = Map::new(&fd, sq_len.max(cq_len), IORING_OFF_SQ_RING)?;
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        let sqes = Map::new(&fd, sqes_len, IORING_OFF_SQES)?;
+        Ok(Some(Self {
+            rings,
+            sqes,
+            params,
+            fd,
+            queued: 0,
+        }))
+    }
+
+    /// Queue `sqe`; there must be room for it.
+    fn push(&mut self, sqe: Sqe) {
+        let off = &self.params.sq_off;
+        let tail = self.rings.atomic(off.tail).load(Ordering::Relaxed);
+        let idx = tail & self.rings.atomic(off.ring_mask).load(Ordering::Relaxed);
+        // SAFETY: idx is masked to the queue, and the entry is not the
+        // kernel's until the tail moves past it
+        unsafe {
+            self.sqes.at::<Sqe>(0).add(idx as usize).write(sqe);
+            self.rings.at::<u32>(off.array).add(idx as usize).write(idx);
+        }
+        self.rings
+            .atomic(off.tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        self.queued += 1;
+    }
+
+    /// Submit what is queued and wait for at least one completion.
+    fn submit_and_wait(&mut self) -> std::io::Result<()> {
+        loop {
+            // SAFETY: no signal mask is passed
+            let n = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd(),
+                    self.queued,
+                    1u32,
+                    IORING_ENTER_GETEVENTS,
+                    std::ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            if n >= 0 {
+                self.queued -= n as u32;
+                return Ok(());
+            }
+            let e = std::io::Error::last_os_error();
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    /// The next completion, as its user data and result.
+    fn pop(&mut self) -> Option<(u64, i32)> {
+        let off = &self.params.cq_off;
+        let head = self.rings.atomic(off.head).load(Ordering::Relaxed);
+        if head == self.rings.atomic(off.tail).load(Ordering::Acquire) {
+            return None;
+        }
+        let idx = head & self.rings.atomic(off.ring_mask).load(Ordering::Relaxed);
+        // SAFETY: entries between head and tail are filled in by the kernel
+        let cqe = unsafe { self.rings.at::<Cqe>(off.cqes).add(idx as usize).read() };
+        self.rings
+            .atomic(off.head)
+            .store(head.wrapping_add(1), Ordering::Release);
+        Some((cqe.user_data, cqe.res))
+    }
+}
+
+/// A buffer holding one chunk on its way through.
+struct Slot {
+    buf: Vec<u8>,
+    chunk: usize,
+    len: usize, // bytes in the chunk, fewer if the file ended early
+    read: usize,
+    written: usize,
+}
+
+/// Chunks of a file copied through a ring, reading ahead while writing.
+struct Copier {
+    ring: Ring,
+    slots: Vec<Slot>,
+    chunks: Vec<(u64, usize)>, // offset and length of each chunk
+    in_flight: usize,
+    fd_in: RawFd,
+    fd_out: RawFd,
+}
+
+impl Copier {
+    fn read(&mut self, slot: usize) {
+        let s = &mut self.slots[slot];
+        self.ring.push(Sqe {
+            opcode: IORING_OP_READ,
+            fd: self.fd_in,
+            off: self.chunks[s.chunk].0 + s.read as u64,
+            addr: s.buf[s.read..].as_mut_ptr() as u64,
+            len: (s.len - s.read) as u32,
+            user_data: (slot as u64) << 1,
+            ..Sqe::default()
+        });
+        self.in_flight += 1;
+    }
+
+    fn write(&mut self, slot: usize) {
+        let s = &self.slots[slot];
+        self.ring.push(Sqe {
+            opcode: IORING_OP_WRITE,
+            fd: self.fd_out,
+            off: u64::MAX, // the current position, for outputs that have one
+            addr: s.buf[s.written..].as_ptr() as u64,
+            len: (s.read - s.written) as u32,
+            user_data: (slot as u64) << 1 | 1,
+            ..Sqe::default()
+        });
+        self.in_flight += 1;
+    }
+
+    fn run(&mut self) -> std::io::Result<()> {
+        let (mut next_read, mut next_write) = (0, 0);
+        let mut end = self.chunks.len();
+        let mut writing = false;
+        while next_write < end {
+            let s = &self.slots[next_write % DEPTH];
+            if !writing && s.chunk == next_write && s.read == s.len {
+                if s.len == 0 {
+                    next_write += 1;
+                    continue;
+                }
+                self.write(next_write % DEPTH);
+                writing = true;
+            }
+            while next_read < end && next_read < next_write + DEPTH {
+                let s = &mut self.slots[next_read % DEPTH];
+                (s.chunk, s.len, s.read, s.written) = (next_read, self.chunks[next_read].1, 0, 0);
+                self.read(next_read % DEPTH);
+                next_read += 1;
+            }
+
+            self.ring.submit_and_wait()?;
+            while let Some((data, res)) = self.ring.pop() {
+                self.in_flight -= 1;
+                let (slot, is_write) = ((data >> 1) as usize, data & 1 == 1);
+                if res < 0 {
+                    let e = std::io::Error::from_raw_os_error(-res);
+                    match e.kind() {
+                        std::io::ErrorKind::Interrupted if is_write => self.write(slot),
+                        std::io::ErrorKind::Interrupted => self.read(slot),
+                        _ => return Err(e),
+                    }
+                    continue;
+                }
+                let (n, s) = (res as usize, &mut self.slots[slot]);
+                if is_write {
+                    if n == 0 {
+                        return Err(std::io::ErrorKind::WriteZero.into());
+                    }
+                    s.written += n;
+                    if s.written < s.read {
+                        self.write(slot);
+                    } else {
+                        writing = false;
+                        next_write += 1;
+                    }
+                } else if s.chunk >= end {
+                    // read ahead past where the file turned out to end
+                } else if n == 0 {
+                    // the file shrank while it was read
+                    s.len = s.read;
+                    end = s.chunk + 1;
+                } else {
+                    s.read += n;
+                    if s.read < s.len {
+                        self.read(slot);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for every request still in flight, which may be filling the
+    /// buffers. Returns false if that failed, so they must never be freed.
+    fn drain(&mut self) -> bool {
+        while self.in_flight > 0 {
+            if self.ring.submit_and_wait().is_err() {
+                return false;
+            }
+            while self.ring.pop().is_some() {
+                self.in_flight -= 1;
+            }
+        }
+        true
+    }
+}
+
+/// Copy the byte `runs` of `file` to `out` through io_uring, reading the
+/// next chunks while the current one is written. Returns false, having done
+/// nothing, if io_uring is unavailable.
+pub(crate) fn copy_runs(
+    file: &File,
+    runs: &[(usize, usize)],
+    out: &impl AsFd,
+) -> std::io::Result<bool> {
+    let Some(ring) = Ring::new(2 * DEPTH as u32)? else {
+        return Ok(false);
+    };
+    let chunks = runs
+        .iter()
+        .flat_map(|&(s, e)| {
+            (s..e)
+                .step_by(CHUNK)
+                .map(move |o| (o as u64, CHUNK.min(e - o)))
+        })
+        .collect();
+    let slots = (0..DEPTH)
+        .map(|_| Slot {
+            buf: vec![0; CHUNK],
+            chunk: usize::MAX,
+            len: 0,
+            read: 0,
+            written: 0,
+        })
+        .collect();
+    let mut copier = Copier {
+        ring,
+        slots,
+        chunks,
+        in_flight: 0,
+        fd_in: file.as_raw_fd(),
+        fd_out: out.as_fd().as_raw_fd(),
+    };
+    let result = copier.run();
+    if !copier.drain() {
+        std::mem::forget(copier.slots);
+    }
+    result.map(|()| true)
+}
@@ -0,0 +1,141 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Result};
+
+/// Shell pattern for file names, as `find -name` takes them: `*` matches any
+/// run of characters, `?` any one, `[a-z]` one of a set, `[!a-z]` one out of
+/// it, and `\` makes the next character literal.
+#[derive(Debug, Clone)]
+pub struct Glob(Vec<Token>);
+
+#[derive(Debug, Clone)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                '*' => Token::Star,
+                '?' => Token::Any,
+                '\\' => Token::Char(chars.next().ok_or("Trailing backslash in glob")?),
+                '[' => {
+                    let mut negated = false;
+                    let mut ranges = Vec::new();
+                    loop {
+                        let c = chars
+                            .next()
+                            .ok_or_else(|| format!("Unclosed [ in glob: {pattern}"))?;
+                        match c {
+                            '!' | '^' if ranges.is_empty() && !negated => negated = true,
+                            // a ] right after the [ is a member, not the end
+                            ']' if !ranges.is_empty() => break,
+                            _ => {
+                                let mut ahead = chars.clone();
+                                match (ahead.next(), ahead.next()) {
+                                    (Some('-'), Some(hi)) if hi != ']' => {
+                                        chars = ahead;
+                                        ranges.push((c, hi));
+                                    }
+                                    _ => ranges.push((c, c)),
+                                }
+                            }
+                        }
+                    }
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Char(c),
+            });
+        }
+        Ok(Self(tokens))
+    }
+
+    /// Whether the whole of `name` matches.
+    pub fn matches(&self, name: &OsStr) -> bool {
+        let name: Vec<char> = name.to_string_lossy().chars().collect();
+        let (mut p, mut n) = (0, 0);
+        // where to resume after the last star: its token, and the name
+        let mut backtrack = None;
+        while n < name.len() {
+            match self.0.get(p) {
+                Some(Token::Star) => {
+                    backtrack = Some((p, n));
+                    p += 1;
+                    continue;
+                }
+                Some(t) if t.matches(name[n]) => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+                _ => (),
+            }
+            // let the last star take one more character
+            match backtrack {
+                Some((star, from)) => {
+                    p = star + 1;
+                    n = from + 1;
+                    backtrack = Some((star, from + 1));
+                }
+                None => return false,
+            }
+        }
+        self.0[p..].iter().all(|t| matches!(t, Token::Star))
+    }
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(t) => *t == c,
+            Token::Any => true,
+            Token::Star => false,
+            Token::Class { negated, ranges } => {
+                *negated != ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&c))
+            }
+        }
+    }
+}
+
+/// The files under `dir`, and whatever is under its subdirectories, whose
+/// names match `glob` if given. They are sorted by path, with the files of a
+/// directory before those of its subdirectories, so the order is the same
+/// every time. Links to directories are not followed, which could loop, and
+/// only regular files are taken, not pipes or devices.
+pub fn walk(dir: &Path, glob: Option<&Glob>) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let with_path = |e| Error::at(&dir, e);
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&dir).map_err(with_path)? {
+            let entry = entry.map_err(with_path)?;
+            entries.push((entry.path(), entry.file_type().map_err(with_path)?));
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut subdirs = Vec::new();
+        for (path, ftype) in entries {
+            let matches = || glob.is_none_or(|g| path.file_name().is_some_and(|n| g.matches(n)));
+            if ftype.is_dir() {
+                subdirs.push(path);
+            } else if (ftype.is_file() || ftype.is_symlink() && path.is_file()) && matches() {
+                files.push(path);
+            }
+        }
+        // popped from the end, so pushed in reverse to go in order
+        dirs.extend(subdirs.into_iter().rev());
+    }
+    Ok(files)
+}
@@ -0,0 +1,351 @@
+use std::fs::File;
+
+use chrono::{
+    Datelike, NaiveDate, NaiveDateTime, TimeDelta,
+    format::{Item, Parsed, StrftimeItems, parse_and_remainder},
+};
+use memchr::memchr;
+use regex::bytes::Regex;
+
+use crate::{
+    Error, Mode, Result, Slicer,
+    compress::Compression,
+    mmap,
+    range::{SliceIdx, SliceRange, pattern_error},
+    scan,
+};
+
+/// The records of a log stamped from `since` up to but excluding `until`.
+///
+/// A record is stamped with the first date and time in it written like
+/// `2024-01-01 12:00:30` or `2024-01-01T12:00:30.123`, unless told where and
+/// how else to find it; records without one, such as the rest of a stack
+/// trace, go with the record before them. The log must be in time order:
+/// the window runs from the first record stamped `since` or later to the
+/// first one stamped `until` or later after it.
+#[derive(Debug, Clone)]
+pub struct TimeWindow {
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    stamp: Regex,
+    format: Option<Vec<Item<'static>>>,
+    field: Option<usize>,
+    pattern: Option<Regex>,
+}
+
+impl TimeWindow {
+    /// A window between two times like `2024-01-01 12:00`, where the seconds
+    /// or the whole time of day may be left out. `until` may also be a
+    /// duration after `since` like `+90m`, in `s`, `m`, `h` or `d`. A missing
+    /// bound leaves that end of the window open.
+    pub fn new(since: Option<&str>, until: Option<&str>) -> Result<Self> {
+        let since = since.map(parse_time).transpose()?;
+        let until = match until {
+            Some(u) if u.starts_with('+') => {
+                let since = since.ok_or("A relative --until needs --since")?;
+                let t = since.checked_add_signed(parse_duration(u)?);
+                Some(t.ok_or_else(|| format!("Time is out of range: {u}"))?)
+            }
+            u => u.map(parse_time).transpose()?,
+        };
+        let stamp =
+            Regex::new(r"(\d{4})-(\d\d)-(\d\d)[T ](\d\d):(\d\d)(?::(\d\d)(?:[.,](\d{1,9}))?)?")
+                .expect("valid pattern");
+        Ok(Self {
+            since,
+            until,
+            stamp,
+            format: None,
+            field: None,
+            pattern: None,
+        })
+    }
+
+    /// Read stamps written in `format`, a strftime pattern such as
+    /// `%d/%b/%Y:%H:%M:%S`, at the start of the record or of the part of it
+    /// picked by [`TimeWindow::field`] or [`TimeWindow::pattern`]. Stamps
+    /// without a year are taken to be in the year of `since`, or `until`.
+    pub fn format(mut self, format: Option<&str>) -> Result<Self> {
+        self.format = match format {
+            Some(f) => Some(
+                StrftimeItems::new(f)
+                    .parse_to_owned()
+                    .map_err(|_| Error::Parse(format!("Invalid time format: {f}")))?,
+            ),
+            None => None,
+        };
+        Ok(self)
+    }
+
+    /// Look for stamps from the `n`-th field, counted from 1, of each record
+    /// on, where fields are separated by whitespace, as in awk.
+    pub fn field(mut self, n: Option<usize>) -> Self {
+        self.field = n;
+        self
+    }
+
+    /// Look for stamps only in the first match of `pattern` in each record,
+    /// or in its first group if it has one.
+    pub fn pattern(mut self, pattern: Option<&str>) -> Result<Self> {
+        self.pattern = match pattern {
+            Some(p) => Some(Regex::new(p).map_err(|e| pattern_error(p, e))?),
+            None => None,
+        };
+        Ok(self)
+    }
+
+    /// The time `record` is stamped with, if any.
+    fn stamp(&self, record: &[u8]) -> Option<NaiveDateTime> {
+        let mut text = match self.field {
+            Some(n) => &record[field_start(record, n)?..],
+            None => record,
+        };
+        let caps;
+        if let Some(pattern) = &self.pattern {
+            caps = pattern.captures(text)?;
+            text = caps.get(1).or_else(|| caps.get(0))?.as_bytes();
+        }
+        match &self.format {
+            Some(items) => self.parse_stamp(text, items),
+            None => self.find_stamp(text),
+        }
+    }
+
+    /// The time at the start of `text` in the given format.
+    fn parse_stamp(&self, text: &[u8], items: &[Item]) -> Option<NaiveDateTime> {
+        let text = text.utf8_chunks().next()?.valid();
+        let mut parsed = Parsed::new();
+        parse_and_remainder(&mut parsed, text, items.iter()).ok()?;
+        let dated = parsed.year().is_some()
+            || parsed.year_mod_100().is_some()
+            || parsed.isoyear().is_some()
+            || parsed.timestamp().is_some();
+        if !dated {
+            let year = self.since.or(self.until).map_or(1970, |t| t.year());
+            parsed.set_year(year.into()).ok()?;
+        }
+        match parsed.to_naive_datetime_with_offset(0) {
+            Ok(t) => Some(t),
+            Err(_) => Some(parsed.to_naive_date().ok()?.into()),
+        }
+    }
+
+    /// The first time in `text` written like `2024-01-01 12:00:30`.
+    fn find_stamp(&self, text: &[u8]) -> Option<NaiveDateTime> {
+        let caps = self.stamp.captures(text)?;
+        let num = |i: usize| -> Option<u32> {
+            let digits = caps.get(i).map_or(&b"0"[..], |m| m.as_bytes());
+            std::str::from_utf8(digits).ok()?.parse().ok()
+        };
+        // a fraction of a second, padded to nanoseconds
+        let scale = 10u32.pow(9 - caps.get(7).map_or(9, |m| m.len()) as u32);
+        let (date, nanos) = (
+            NaiveDate::from_ymd_opt(num(1)? as i32, num(2)?, num(3)?)?,
+            num(7)?,
+        );
+        date.and_hms_nano_opt(num(4)?, num(5)?, num(6)?, nanos * scale)
+    }
+
+    /// Whether a record stamped `t` is at or after the start of the window.
+    fn started(&self, t: NaiveDateTime) -> bool {
+        self.since.is_none_or(|s| t >= s)
+    }
+
+    /// Whether a record stamped `t` is past the end of the window.
+    fn ended(&self, t: NaiveDateTime) -> bool {
+        self.until.is_some_and(|u| t >= u)
+    }
+
+    /// The first stamped record of `data` starting at or after `pos`, with
+    /// the offset where it starts.
+    fn next_stamp(&self, data: &[u8], delim: u8, pos: usize) -> Option<(usize, NaiveDateTime)> {
+        let mut start = match pos {
+            0 => 0,
+            p => memchr(delim, &data[p - 1..]).map_or(data.len(), |n| p + n),
+        };
+        while start < data.len() {
+            let end = memchr(delim, &data[start..]).map_or(data.len(), |n| start + n + 1);
+            if let Some(t) = self.stamp(&data[start..end]) {
+                return Some((start, t));
+            }
+            start = end;
+        }
+        None
+    }
+
+    /// Offset of the first stamped record of `data` from `lo` on for which
+    /// `bound` holds, or the end, by binary search.
+    fn search(
+        &self,
+        data: &[u8],
+        delim: u8,
+        mut lo: usize,
+        bound: impl Fn(NaiveDateTime) -> bool,
+    ) -> usize {
+        let holds = |pos| {
+            self.next_stamp(data, delim, pos)
+                .is_none_or(|(_, t)| bound(t))
+        };
+        let mut hi = data.len();
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if holds(mid) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        self.next_stamp(data, delim, lo)
+            .map_or(data.len(), |(p, _)| p)
+    }
+}
+
+/// Offset of the `n`-th whitespace separated field of `record`, from 1.
+fn field_start(record: &[u8], n: usize) -> Option<usize> {
+    let mut fields = 0;
+    let mut space = true;
+    for (i, c) in record.iter().enumerate() {
+        if space && !c.is_ascii_whitespace() {
+            fields += 1;
+            if fields == n {
+                return Some(i);
+            }
+        }
+        space = c.is_ascii_whitespace();
+    }
+    None
+}
+
+/// Parse a time like `2024-01-01 12:00:30`, `2024-01-01T12:00` or just a
+/// date, for midnight.
+fn parse_time(s: &str) -> Result<NaiveDateTime> {
+    const FORMATS: [&str; 4] = [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ];
+    FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .or_else(|| Some(NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?.into()))
+        .ok_or_else(|| Error::Parse(format!("Invalid time: {s}")))
+}
+
+/// Parse a duration like `+90m`.
+fn parse_duration(s: &str) -> Result<TimeDelta> {
+    let invalid = || Error::Parse(format!("Invalid duration: {s}"));
+    let body = s.strip_prefix('+').ok_or_else(invalid)?;
+    let (n, unit) = body.split_at(body.len().saturating_sub(1));
+    let n: i64 = n.parse().map_err(|_| invalid())?;
+    let delta = match unit {
+        "s" => TimeDelta::try_seconds(n),
+        "m" => TimeDelta::try_minutes(n),
+        "h" => TimeDelta::try_hours(n),
+        "d" => TimeDelta::try_days(n),
+        _ => None,
+    };
+    delta.ok_or_else(invalid)
+}
+
+/// Where a time window starts and ends among the records of the input,
+/// noted one after another.
+pub(crate) struct Clock<'a> {
+    window: &'a TimeWindow,
+    start: Option<usize>,
+    end: Option<usize>,
+    seen: usize,
+}
+
+impl<'a> Clock<'a> {
+    pub fn new(window: &'a TimeWindow) -> Self {
+        Self {
+            window,
+            start: window.since.is_none().then_some(0),
+            end: None,
+            seen: 0,
+        }
+    }
+
+    /// Note record `i`.
+    pub fn record(&mut self, i: usize, record: &[u8]) {
+        self.seen = i + 1;
+        if self.end.is_some() {
+            return;
+        }
+        let Some(t) = self.window.stamp(record) else {
+            return;
+        };
+        if self.start.is_none() && self.window.started(t) {
+            self.start = Some(i);
+        }
+        if self.start.is_some() && self.window.ended(t) {
+            self.end = Some(i);
+        }
+    }
+
+    /// The first record in the window and how many there are.
+    pub fn resolve(&self) -> (usize, usize) {
+        let start = self.start.unwrap_or(self.seen);
+        (start, self.end.unwrap_or(self.seen) - start)
+    }
+}
+
+/// `ranges` over the `len` records from record `start` on, as ranges over
+/// the whole input.
+pub(crate) fn within(ranges: &[SliceRange], start: usize, len: usize) -> Vec<SliceRange> {
+    let at = |n: usize| SliceIdx::FromStart(start + n);
+    ranges
+        .iter()
+        .map(|r| match r.resolve(len) {
+            (lo, hi) if hi <= lo => SliceRange::new(at(0), at(0)),
+            // from the last record down to the first, which are inclusive
+            (lo, hi) if r.is_reversed() => SliceRange {
+                start: Some(at(hi - 1)),
+                end: (start + lo).checked_sub(1).map(SliceIdx::FromStart),
+                step: r.step,
+            },
+            (lo, hi) => SliceRange::new(at(lo), at(hi)).with_step(r.step),
+        })
+        .collect()
+}
+
+impl Slicer {
+    /// This slicer with its time window found by binary search in `file`,
+    /// if it is a regular, uncompressed file of records ending in a byte
+    /// with each one counted.
+    pub(crate) fn search_window(&self, file: &File) -> Result<Option<Slicer>> {
+        let (Some(window), &Mode::Delimited(delim)) = (&self.window, &self.mode) else {
+            return Ok(None);
+        };
+        if self.header
+            || self.max_records.is_some()
+            || self.has_anchors()
+            || !file.metadata()?.is_file()
+        {
+            return Ok(None);
+        }
+        if self.decompress && Compression::detect_file(&mut file.try_clone()?, None)?.is_some() {
+            return Ok(None);
+        }
+        let map = mmap::map(file)?;
+        let data = map.as_deref().unwrap_or_default();
+        let start = match window.since {
+            Some(_) => window.search(data, delim, 0, |t| window.started(t)),
+            None => 0,
+        };
+        let end = match window.until {
+            Some(_) => window.search(data, delim, start, |t| window.ended(t)),
+            None => data.len(),
+        };
+
+        // the window ends at a record boundary or the end of the input
+        let skip = scan::count(&data[..start], delim);
+        let records = &data[start..end];
+        let len = mmap::count_records(records, delim);
+        let mut this = self.clone();
+        this.ranges = within(&self.ranges, skip, len);
+        this.window = None;
+        Ok(Some(this))
+    }
+}
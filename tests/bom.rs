@@ -0,0 +1,25 @@
+//! A leading byte order mark is not counted as a character of its own.
+
+use std::process::Command;
+
+fn chars(range: &str, data: &[u8]) -> Vec<u8> {
+    let path = std::env::temp_dir().join(format!("slice-bom-{}-{range}", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_slice"))
+        .args(["-m", range])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(out.status.success());
+    out.stdout
+}
+
+#[test]
+fn bom_belongs_to_the_first_character() {
+    assert_eq!(chars("0:1", b"\xef\xbb\xbfab"), b"\xef\xbb\xbfa");
+    assert_eq!(chars("1:", b"\xef\xbb\xbfab"), b"b");
+    assert_eq!(chars("0:1", b"\xef\xbb\xbf"), b"\xef\xbb\xbf");
+    // only at the start
+    assert_eq!(chars("1:2", b"a\xef\xbb\xbfb"), "\u{feff}".as_bytes());
+}
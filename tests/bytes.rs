@@ -0,0 +1,39 @@
+//! `-c` takes a count only when it is given one; otherwise what follows it is
+//! the range or a file, as before it took a value.
+
+use std::process::Command;
+
+fn slice(args: &[&str], files: &[(&str, &str)]) -> String {
+    let dir = std::env::temp_dir().join(format!(
+        "slice-bytes-{}-{}",
+        std::process::id(),
+        args.join("_")
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, data) in files {
+        std::fs::write(dir.join(name), data).unwrap();
+    }
+    let out = Command::new(env!("CARGO_BIN_EXE_slice"))
+        .args(args)
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(out.status.success(), "slice {args:?} failed");
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn range_then_bare_c_then_file() {
+    let files = [("f", "abcdef\n"), ("g", "XYZ\n")];
+    assert_eq!(slice(&["0:3", "-c", "f"], &files), "abc");
+    assert_eq!(slice(&["0:6", "g", "-c", "f"], &files), "XYZ\nab");
+}
+
+#[test]
+fn c_takes_a_count_or_a_range() {
+    let files = [("f", "abcdef\n")];
+    assert_eq!(slice(&["-c", "2", "f"], &files), "ab");
+    assert_eq!(slice(&["f", "-c", "2"], &files), "ab");
+    assert_eq!(slice(&["-c", "1:3", "f"], &files), "bc");
+}
@@ -0,0 +1,77 @@
+//! With `--error-format json`, every error is reported as a line of JSON,
+//! whether from parsing the arguments, one of several files or the data.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn slice(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_slice"))
+        .arg("--error-format")
+        .arg("json")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // slice may fail before reading it all
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait_with_output().unwrap()
+}
+
+fn errors(out: &Output) -> Vec<serde_json::Value> {
+    String::from_utf8_lossy(&out.stderr)
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap_or_else(|_| panic!("not JSON: {l}")))
+        .collect()
+}
+
+#[test]
+fn usage_errors_are_json() {
+    for args in [&["--bogus", "0:"][..], &["--buffer-size", "xx", "0:"], &[]] {
+        let out = slice(args, b"");
+        assert_eq!(out.status.code(), Some(2), "{args:?}");
+        let errors = errors(&out);
+        assert_eq!(errors.len(), 1, "{args:?}");
+        assert_eq!(errors[0]["error"], "parse", "{args:?}");
+    }
+}
+
+#[test]
+fn invalid_data_has_its_own_kind() {
+    for (args, input) in [
+        (&["--validate-utf8", "0:"][..], &b"a\xff\n"[..]),
+        (&["--jsonl", "0:"], b"{x\n"),
+    ] {
+        let out = slice(args, input);
+        assert_eq!(out.status.code(), Some(6), "{args:?}");
+        assert_eq!(errors(&out)[0]["error"], "data", "{args:?}");
+    }
+}
+
+#[test]
+fn skipped_files_are_json() {
+    let dir = std::env::temp_dir().join(format!("slice-errors-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("good"), "{}\n").unwrap();
+    std::fs::write(dir.join("bad"), "{\n").unwrap();
+    let out = slice(
+        &[
+            "--jsonl",
+            "--continue-on-error",
+            "-r",
+            "0:",
+            dir.to_str().unwrap(),
+        ],
+        b"",
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    let errors = errors(&out);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["error"], "data");
+    assert_eq!(errors[0]["file"], dir.join("bad").to_str().unwrap());
+    assert_eq!(errors[1]["error"], "other");
+}
@@ -0,0 +1,40 @@
+//! Following a file goes on from where the slice ended, however it was read.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    thread::sleep,
+    time::Duration,
+};
+
+fn follow(flag: &str) {
+    let path = std::env::temp_dir().join(format!("slice-follow-{}{flag}", std::process::id()));
+    std::fs::write(&path, "1\n2\n3\n4\n5\n").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_slice"))
+        .args([flag, "-s", "0.05", "--", "-2:"])
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    sleep(Duration::from_millis(300));
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"6\n").unwrap();
+    sleep(Duration::from_millis(300));
+    child.kill().unwrap();
+    let out = child.wait_with_output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "4\n5\n6\n", "{flag}");
+}
+
+#[test]
+fn follow_descriptor_goes_on_after_the_slice() {
+    follow("-f");
+}
+
+#[test]
+fn follow_name_goes_on_after_the_slice() {
+    follow("-F");
+}
@@ -0,0 +1,23 @@
+//! Slicing in place leaves a file it cannot slice as it was.
+
+use std::{io::Write, process::Command};
+
+use flate2::{Compression, write::GzEncoder};
+
+#[test]
+fn compressed_file_is_refused() {
+    let path = std::env::temp_dir().join(format!("slice-in-place-{}.log.gz", std::process::id()));
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(b"line1\nline2\nline3\n").unwrap();
+    let gz = gz.finish().unwrap();
+    std::fs::write(&path, &gz).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_slice"))
+        .args(["--in-place", "0:2"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let left = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(!out.status.success());
+    assert_eq!(left, gz);
+}
@@ -0,0 +1,134 @@
+//! Ranges resolved the way Python resolves `seq[start:stop:step]`, whether
+//! the input is a file or a stream.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+const BOUNDS: [Option<isize>; 9] = [
+    None,
+    Some(-8),
+    Some(-3),
+    Some(-1),
+    Some(0),
+    Some(1),
+    Some(3),
+    Some(5),
+    Some(8),
+];
+const STEPS: [isize; 4] = [-2, -1, 1, 2];
+const LENS: [usize; 4] = [0, 1, 4, 7];
+
+/// The indices `seq[start:stop:step]` takes of a sequence of `len`.
+fn python_slice(len: usize, start: Option<isize>, stop: Option<isize>, step: isize) -> Vec<usize> {
+    let len = len as isize;
+    let clamp = |i: isize, lo: isize, hi: isize| {
+        let i = if i < 0 { i + len } else { i };
+        i.clamp(lo, hi)
+    };
+    let mut out = Vec::new();
+    if step > 0 {
+        let (mut i, stop) = (
+            start.map_or(0, |s| clamp(s, 0, len)),
+            stop.map_or(len, |s| clamp(s, 0, len)),
+        );
+        while i < stop {
+            out.push(i as usize);
+            i += step;
+        }
+    } else {
+        let (mut i, stop) = (
+            start.map_or(len - 1, |s| clamp(s, -1, len - 1)),
+            stop.map_or(-1, |s| clamp(s, -1, len - 1)),
+        );
+        while i > stop {
+            out.push(i as usize);
+            i += step;
+        }
+    }
+    out
+}
+
+fn range_arg(start: Option<isize>, stop: Option<isize>, step: isize) -> String {
+    let part = |b: Option<isize>| b.map_or(String::new(), |b| b.to_string());
+    format!("{}:{}:{step}", part(start), part(stop))
+}
+
+/// What slice prints for `range` of `input`, from stdin or from a file.
+fn slice(args: &[&str], range: &str, input: &[u8], file: Option<&std::path::Path>) -> Vec<u8> {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_slice"));
+    cmd.args(args).arg("--").arg(range);
+    if let Some(file) = file {
+        cmd.arg(file);
+    }
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    if file.is_none() {
+        // slice may stop reading once it has what it needs
+        let _ = stdin.write_all(input);
+    }
+    drop(stdin);
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success(), "slice {args:?} {range} failed");
+    out.stdout
+}
+
+fn check(args: &[&str], render: impl Fn(&[usize]) -> Vec<u8>) {
+    let dir = std::env::temp_dir().join(format!(
+        "slice-ranges-{}-{}",
+        std::process::id(),
+        args.len()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    for len in LENS {
+        let all: Vec<usize> = (0..len).collect();
+        let input = render(&all);
+        let path = dir.join(len.to_string());
+        std::fs::write(&path, &input).unwrap();
+        for start in BOUNDS {
+            for stop in BOUNDS {
+                for step in STEPS {
+                    let range = range_arg(start, stop, step);
+                    let want = render(&python_slice(len, start, stop, step));
+                    for file in [None, Some(path.as_path())] {
+                        let got = slice(args, &range, &input, file);
+                        assert_eq!(
+                            String::from_utf8_lossy(&got),
+                            String::from_utf8_lossy(&want),
+                            "{range} of {len} {} from {}",
+                            if args.is_empty() { "lines" } else { "bytes" },
+                            if file.is_some() { "a file" } else { "stdin" },
+                        );
+                    }
+                }
+            }
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn lines_resolve_like_python() {
+    check(&[], |idx| {
+        idx.iter()
+            .map(|i| format!("{i}\n"))
+            .collect::<String>()
+            .into_bytes()
+    });
+}
+
+#[test]
+fn bytes_resolve_like_python() {
+    check(&["-c"], |idx| idx.iter().map(|&i| b'a' + i as u8).collect());
+}
+
+#[test]
+fn relative_end_saturates() {
+    let range = format!("2:+{}", usize::MAX - 1);
+    assert_eq!(slice(&[], &range, b"0\n1\n2\n3\n", None), b"2\n3\n");
+}
@@ -0,0 +1,63 @@
+//! Shuffled records stay apart, even one the input left unterminated.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+fn shuffle(args: &[&str], input: &[u8], seed: u64) -> Vec<u8> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_slice"))
+        .args(args)
+        .args(["--shuffle", "--seed", &seed.to_string(), "0:"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    out.stdout
+}
+
+#[test]
+fn unterminated_last_line_is_ended_when_moved() {
+    for seed in 0..8 {
+        let out = shuffle(&[], b"a\nb\nc", seed);
+        let mut lines: Vec<&[u8]> = out.split(|&c| c == b'\n').collect();
+        // only the record written last may be left unterminated
+        if lines.last() == Some(&&b""[..]) {
+            lines.pop();
+        }
+        lines.sort_unstable();
+        assert_eq!(lines, [b"a", b"b", b"c"], "seed {seed}");
+    }
+}
+
+#[test]
+fn unterminated_last_paragraph_is_ended_when_moved() {
+    for seed in 0..8 {
+        let out = shuffle(&["-p"], b"a\n\nb\n", seed);
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out == "a\n\nb\n" || out == "b\n\na\n\n",
+            "seed {seed}: {out:?}"
+        );
+    }
+}
+
+#[test]
+fn shuffle_keeps_within_max_memory() {
+    let input: String = (0..20_000).map(|i| format!("{i}\n")).collect();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_slice"))
+        .args(["--max-memory", "10K", "--shuffle", "0:"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // slice may give up before reading it all
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    let out = child.wait_with_output().unwrap();
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty());
+}
@@ -0,0 +1,45 @@
+//! Stdin redirected from a file is sliced like that file, however often it
+//! is read.
+
+use std::{
+    fs::File,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+fn fixture(name: &str, data: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("slice-stdin-{}-{name}", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// Run slice with `args` and stdin redirected from `input`.
+fn slice(args: &[&str], input: &PathBuf) -> Output {
+    let out = Command::new(env!("CARGO_BIN_EXE_slice"))
+        .args(args)
+        .stdin(File::open(input).unwrap())
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "slice {args:?} failed");
+    out
+}
+
+#[test]
+fn anchors_on_redirected_stdin() {
+    let input = fixture("anchors", "x\nBEGIN\na\nEND\ny\n");
+    let out = slice(&["/^BEGIN/:/^END/"], &input);
+    std::fs::remove_file(&input).unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "BEGIN\na\n");
+}
+
+#[test]
+fn output_file_from_redirected_stdin() {
+    let data: String = (1..=100).map(|i| format!("{i}\n")).collect();
+    let input = fixture("output-in", &data);
+    let output = std::env::temp_dir().join(format!("slice-stdin-{}-out", std::process::id()));
+    slice(&["-c", "3:10", "-o", output.to_str().unwrap()], &input);
+    let got = std::fs::read(&output).unwrap();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert_eq!(got, &data.as_bytes()[3..10]);
+}